@@ -0,0 +1,134 @@
+use std::sync::Arc;
+use vulkano::{
+    format::Format,
+    image::{view::ImageView, Image, ImageCreateInfo, ImageType, ImageUsage},
+    memory::allocator::{AllocationCreateInfo, MemoryAllocator},
+};
+
+use crate::camera::Camera;
+use crate::error::AppError;
+
+/// Progressive rendering of a still view. Each frame adds a sample, jittered within the pixel,
+/// to the sum in a float image and shows the average so far.
+pub struct Accumulation {
+    image: Arc<ImageView>,
+    samples: u32,
+    /// The camera the samples so far were taken with.
+    camera: Option<Camera>,
+}
+
+/// What the shader needs to add one sample, see `Globals` in `common.glsl`.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    /// Samples already summed in the image.
+    pub index: u32,
+    /// Offset from the pixel center, within half a pixel.
+    pub jitter: [f32; 2],
+}
+
+impl Accumulation {
+    pub fn new(
+        memory_allocator: Arc<dyn MemoryAllocator>,
+        extent: [u32; 2],
+    ) -> Result<Self, AppError> {
+        let image = Image::new(
+            memory_allocator,
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R32G32B32A32_SFLOAT,
+                extent: [extent[0], extent[1], 1],
+                usage: ImageUsage::STORAGE,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .map_err(|e| AppError::Render(e.to_string()))?;
+
+        Ok(Accumulation {
+            image: ImageView::new_default(image)?,
+            samples: 0,
+            camera: None,
+        })
+    }
+
+    pub fn image(&self) -> Arc<ImageView> {
+        self.image.clone()
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn reset(&mut self) {
+        self.samples = 0;
+        self.camera = None;
+    }
+
+    /// The next sample to take of the view from `camera`. Starts over if the camera moved or
+    /// `changed` reports that the scene did.
+    pub fn next_sample(&mut self, camera: &Camera, changed: bool) -> Sample {
+        if changed || self.camera.as_ref() != Some(camera) {
+            self.samples = 0;
+            self.camera = Some(camera.clone());
+        }
+        let index = self.samples;
+        self.samples += 1;
+        Sample {
+            index,
+            jitter: jitter(index),
+        }
+    }
+}
+
+/// The offset of sample `index` from the pixel center, within half a pixel.
+fn jitter(index: u32) -> [f32; 2] {
+    // The first sample is the pixel center, so accumulation starts from the plain image.
+    if index == 0 {
+        [0.0; 2]
+    } else {
+        [halton(index, 2) - 0.5, halton(index, 3) - 0.5]
+    }
+}
+
+/// The `index`th element of the Halton sequence in `base`, evenly covering [0, 1).
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut f = 1.0;
+    while index > 0 {
+        f /= base as f32;
+        result += f * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halton_radical_inverse() {
+        let base2: Vec<f32> = (0..8).map(|i| halton(i, 2)).collect();
+        assert_eq!(base2, [0.0, 0.5, 0.25, 0.75, 0.125, 0.625, 0.375, 0.875]);
+        let base3: Vec<f32> = (1..5).map(|i| halton(i, 3)).collect();
+        let expected = [1.0 / 3.0, 2.0 / 3.0, 1.0 / 9.0, 4.0 / 9.0];
+        for (a, b) in base3.iter().zip(expected) {
+            assert!((a - b).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn jitter_stratifies_the_pixel() {
+        assert_eq!(jitter(0), [0.0; 2]);
+        // Consecutive samples fall into different strips across and down the pixel.
+        let strips = |samples: u32, axis: usize| {
+            let mut strips: Vec<usize> = (1..=samples)
+                .map(|i| ((jitter(i)[axis] + 0.5) * samples as f32 + 1e-4) as usize)
+                .collect();
+            strips.sort();
+            strips
+        };
+        assert_eq!(strips(8, 0), (0..8).collect::<Vec<_>>());
+        assert_eq!(strips(9, 1), (0..9).collect::<Vec<_>>());
+    }
+}
@@ -1,90 +1,181 @@
-use glam::{vec3, Vec2, Vec3};
-use std::{sync::Arc, time::Instant};
+use glam::{Vec2, Vec3};
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 use vulkano::{
-    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
+    buffer::Subbuffer,
     command_buffer::{
         allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
-        RenderPassBeginInfo, SubpassBeginInfo, SubpassContents,
-    },
-    device::{
-        physical::PhysicalDeviceType, Device, DeviceCreateInfo, DeviceExtensions, Queue,
-        QueueCreateInfo, QueueFlags,
     },
+    device::{Device, DeviceCreateInfo, DeviceExtensions, Queue, QueueCreateInfo, QueueFlags},
+    format::Format,
     image::{view::ImageView, Image, ImageUsage},
     instance::{Instance, InstanceCreateFlags, InstanceCreateInfo},
-    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
-    pipeline::{
-        graphics::{
-            color_blend::{ColorBlendAttachmentState, ColorBlendState},
-            input_assembly::InputAssemblyState,
-            multisample::MultisampleState,
-            rasterization::RasterizationState,
-            vertex_input::{Vertex, VertexDefinition},
-            viewport::{Viewport, ViewportState},
-            GraphicsPipelineCreateInfo,
-        },
-        layout::PipelineDescriptorSetLayoutCreateInfo,
-        DynamicState, GraphicsPipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo,
-    },
-    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+    memory::allocator::StandardMemoryAllocator,
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass},
+    shader::ShaderModule,
     swapchain::{
-        acquire_next_image, Surface, Swapchain, SwapchainCreateInfo, SwapchainPresentInfo,
+        acquire_next_image, ColorSpace, PresentMode, Surface, Swapchain, SwapchainCreateInfo,
+        SwapchainPresentInfo,
     },
     sync::{self, GpuFuture},
     Validated, VulkanError, VulkanLibrary,
 };
 use winit::{
     application::ApplicationHandler,
-    event::{DeviceEvent, ElementState, KeyEvent, StartCause, WindowEvent},
+    dpi::PhysicalSize,
+    event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
     event_loop::{ActiveEventLoop, EventLoop},
-    keyboard::{KeyCode, PhysicalKey},
-    window::{Window, WindowId},
+    keyboard::{KeyCode, ModifiersState, PhysicalKey},
+    raw_window_handle::HasDisplayHandle,
+    window::{Fullscreen, Window, WindowId},
 };
 
+use crate::accumulation::Accumulation;
 use crate::camera::{Camera, CameraEvent};
-use crate::shaders::fragment;
-use crate::shaders::vertex;
+use crate::camera_path::CameraPath;
+use crate::capture;
+use crate::cli::Options;
+use crate::controller::CameraController;
+use crate::device::{self, DeviceSelector};
+use crate::error::AppError;
+use crate::gpu_timer::GpuTimer;
+use crate::headless::{self, HeadlessRenderer};
+use crate::light::{Light, LightId, LightList};
+use crate::params::FrameTime;
+use crate::picking;
+use crate::post::{Effect, PostChain, PostSettings};
+use crate::quality::Quality;
+use crate::render::{self, Backend, MyVertex, ScenePipeline, ShaderResources};
+use crate::resolution::RenderScale;
+use crate::scene::{self, Scene};
+use crate::shaders::{compiler, watcher::ShaderWatcher};
+use crate::timestep::FixedTimestep;
+use crate::tonemap;
 
 pub struct App {
     instance: Arc<Instance>,
+    device_selector: Option<DeviceSelector>,
     device: Arc<Device>,
     queue: Arc<Queue>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
     command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
     vertex_buffer: Subbuffer<[MyVertex]>,
+    backend: Backend,
+    /// The shader of `backend`.
+    scene_shader: Arc<ShaderModule>,
+    resources: ShaderResources,
+    post_settings: PostSettings,
+    render_scale: RenderScale,
+    /// The scene the fragment shader was generated from, `None` for the built-in one.
+    scene: Option<Scene>,
+    scene_path: Option<PathBuf>,
+    shader_watcher: Option<ShaderWatcher>,
+    placed_lights: Vec<LightId>,
+    window_size: [u32; 2],
+    fullscreen: bool,
+    present_mode: PresentMode,
     render_ctx: Option<RenderContext>,
+    /// Renders screenshots and posters, created on first use.
+    capture_renderer: Option<HeadlessRenderer>,
+    supersample: u32,
+    poster_scale: u32,
+    error: Option<AppError>,
     camera: Camera,
+    /// The camera before the last update, rendering interpolates from it to `camera`.
+    previous_camera: Camera,
+    controller: Box<dyn CameraController>,
+    camera_path: CameraPath,
+    camera_path_file: PathBuf,
+    /// The camera path being played, the camera ignores input meanwhile.
+    playback: Option<Playback>,
+    playback_speed: f32,
+    /// Play the camera path once, print the frame rate and exit.
+    benchmark: bool,
+    /// Average the frames of a still view, see `Accumulation`.
+    accumulate: bool,
+    modifiers: ModifiersState,
     mouse_pressed: bool,
+    cursor_position: Vec2,
     last_mouse_pos: Vec2,
     cam_rotation: Option<Vec2>,
     cam_up: bool,
     cam_down: bool,
     cam_left: bool,
     cam_right: bool,
-    frame_time: Instant,
+    cam_ascend: bool,
+    cam_descend: bool,
+    cam_roll_left: bool,
+    cam_roll_right: bool,
+    cam_zoom: f32,
+    timestep: FixedTimestep,
+    /// Simulation time in seconds, advanced by `update`.
+    time: f32,
+    /// Frames presented so far.
+    frame: u32,
+    /// Animation time of the last presented frame.
+    last_frame_time: f32,
     fps: u32,
     ups: u32,
-    lag: u32,
     timer: Instant,
 }
 
-const NANOS: f32 = 1000000000. / 60.;
+struct Playback {
+    /// Seconds into the camera path.
+    time: f32,
+    started: Instant,
+    frames: u32,
+    last_frame: Instant,
+    slowest_frame: Duration,
+}
+
+impl Playback {
+    fn new() -> Self {
+        Playback {
+            time: 0.0,
+            started: Instant::now(),
+            frames: 0,
+            last_frame: Instant::now(),
+            slowest_frame: Duration::ZERO,
+        }
+    }
+}
 
 struct RenderContext {
     window: Arc<Window>,
     swapchain: Arc<Swapchain>,
+    /// Renders the scene into `hdr`.
     render_pass: Arc<RenderPass>,
+    /// Sized for the window, the scene is drawn to the top left `scene_extent` of it.
+    hdr: Arc<Framebuffer>,
+    scene_extent: [u32; 2],
+    pipeline: ScenePipeline,
+    /// The preset `pipeline` is specialized for.
+    quality: Quality,
+    /// Maps `hdr` to the swapchain images, through `framebuffers`.
+    post: PostChain,
     framebuffers: Vec<Arc<Framebuffer>>,
-    pipeline: Arc<GraphicsPipeline>,
-    viewport: Viewport,
     recreate_swapchain: bool,
     previous_frame_end: Option<Box<dyn GpuFuture>>,
+    accumulation: Accumulation,
+    /// Measures the frames for the dynamic render scale, if the queue supports it.
+    timer: Option<GpuTimer>,
 }
 
 impl App {
-    pub fn new(event_loop: &EventLoop<()>) -> Self {
-        let library = VulkanLibrary::new().unwrap();
-
-        let required_extensions = Surface::required_extensions(event_loop).unwrap();
+    pub fn new(
+        event_loop: &EventLoop<()>,
+        options: &Options,
+        scene: Option<Scene>,
+        camera_path: CameraPath,
+        post_settings: PostSettings,
+    ) -> Result<Self, AppError> {
+        let library = VulkanLibrary::new().map_err(AppError::Library)?;
+
+        let required_extensions = Surface::required_extensions(event_loop)
+            .map_err(|e| AppError::Window(e.to_string()))?;
 
         let instance = Instance::new(
             library,
@@ -96,62 +187,9 @@ impl App {
                 ..Default::default()
             },
         )
-        .unwrap();
-
-        let device_extensions = DeviceExtensions {
-            khr_swapchain: true,
-            ..DeviceExtensions::empty()
-        };
+        .map_err(AppError::Instance)?;
 
-        let (physical_device, queue_family_index) = instance
-            .enumerate_physical_devices()
-            .unwrap()
-            .filter(|p| p.supported_extensions().contains(&device_extensions))
-            .filter_map(|p| {
-                p.queue_family_properties()
-                    .iter()
-                    .enumerate()
-                    .position(|(i, q)| {
-                        q.queue_flags.intersects(QueueFlags::GRAPHICS)
-                            && p.presentation_support(i as u32, event_loop).unwrap()
-                    })
-                    .map(|i| (p, i as u32))
-            })
-            .min_by_key(|(p, _)| {
-                // We assign a lower score to device types that are likely to be faster/better.
-                match p.properties().device_type {
-                    PhysicalDeviceType::DiscreteGpu => 0,
-                    PhysicalDeviceType::IntegratedGpu => 1,
-                    PhysicalDeviceType::VirtualGpu => 2,
-                    PhysicalDeviceType::Cpu => 3,
-                    PhysicalDeviceType::Other => 4,
-                    _ => 5,
-                }
-            })
-            .expect("no suitable physical device found");
-
-        // Some little debug infos.
-        println!(
-            "Using device: {} (type: {:?})",
-            physical_device.properties().device_name,
-            physical_device.properties().device_type,
-        );
-
-        let (device, mut queues) = Device::new(
-            physical_device,
-            DeviceCreateInfo {
-                enabled_extensions: device_extensions,
-                queue_create_infos: vec![QueueCreateInfo {
-                    queue_family_index,
-                    ..Default::default()
-                }],
-
-                ..Default::default()
-            },
-        )
-        .unwrap();
-
-        let queue = queues.next().unwrap();
+        let (device, queue) = create_device(&instance, options.device.as_ref(), event_loop)?;
 
         let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
 
@@ -160,91 +198,284 @@ impl App {
             Default::default(),
         ));
 
-        let vertices = [
-            MyVertex {
-                position: [-1.0, -1.0],
-            },
-            MyVertex {
-                position: [-1.0, 1.0],
-            },
-            MyVertex {
-                position: [1.0, -1.0],
-            },
-            MyVertex {
-                position: [1.0, 1.0],
-            },
-            MyVertex {
-                position: [1.0, -1.0],
-            },
-            MyVertex {
-                position: [-1.0, 1.0],
-            },
-        ];
-        let vertex_buffer = Buffer::from_iter(
-            memory_allocator,
-            BufferCreateInfo {
-                usage: BufferUsage::VERTEX_BUFFER,
-                ..Default::default()
-            },
-            AllocationCreateInfo {
-                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
-                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                ..Default::default()
-            },
-            vertices,
-        )
-        .unwrap();
+        let vertex_buffer = render::fullscreen_quad(memory_allocator.clone())?;
+
+        let scene_path = options.scene.clone();
+        let scene_shader = load_scene_shader(
+            device.clone(),
+            options.backend,
+            scene.as_ref(),
+            options.watch_shaders,
+        )?;
+        let shader_watcher = options
+            .watch_shaders
+            .then(|| ShaderWatcher::new(scene_path.iter().cloned().collect()));
+        let mut resources = ShaderResources::new(
+            device.clone(),
+            memory_allocator.clone(),
+            scene.as_ref().unwrap_or(&Scene::builtin()),
+        );
+        resources.settings = options.render_settings;
 
         let rcx = None;
 
-        let camera = Camera::new_with_pos(Vec3::new(-0.5, 3., 8.0), Vec3::new(0., -1., -5.));
+        let mut camera = options.camera();
+        let controller = options.controller.create(&mut camera);
 
-        App {
+        Ok(App {
             instance,
+            device_selector: options.device.clone(),
             device,
             queue,
+            memory_allocator,
             command_buffer_allocator,
             vertex_buffer,
+            backend: options.backend,
+            scene_shader,
+            resources,
+            post_settings,
+            render_scale: options.render_scale.clone(),
+            scene,
+            scene_path,
+            shader_watcher,
+            placed_lights: vec![],
+            window_size: options.size,
+            fullscreen: options.fullscreen,
+            present_mode: options.present_mode,
             render_ctx: rcx,
+            capture_renderer: None,
+            supersample: options.supersample,
+            poster_scale: options.poster_scale,
+            error: None,
+            previous_camera: camera.clone(),
             camera,
+            controller,
+            camera_path,
+            camera_path_file: options.camera_path.clone(),
+            playback: (options.play || options.benchmark).then(Playback::new),
+            playback_speed: 1.0,
+            benchmark: options.benchmark,
+            accumulate: options.accumulate,
+            modifiers: ModifiersState::empty(),
             mouse_pressed: false,
+            cursor_position: Vec2::ZERO,
             last_mouse_pos: Vec2::ZERO,
             cam_rotation: None,
             cam_down: false,
             cam_left: false,
             cam_right: false,
+            cam_ascend: false,
+            cam_descend: false,
+            cam_roll_left: false,
+            cam_roll_right: false,
+            cam_zoom: 0.0,
             cam_up: false,
-            frame_time: Instant::now(),
+            timestep: FixedTimestep::new(options.tick_rate),
+            time: 0.0,
+            frame: 0,
+            last_frame_time: 0.0,
             fps: 0u32,
             ups: 0u32,
-            lag: 0u32,
             timer: Instant::now(),
+        })
+    }
+
+    /// The error that stopped the event loop, if any.
+    pub fn take_error(&mut self) -> Option<AppError> {
+        self.error.take()
+    }
+
+    fn fail(&mut self, event_loop: &ActiveEventLoop, e: AppError) {
+        self.error = Some(e);
+        event_loop.exit();
+    }
+
+    /// The camera and the animation time of the frame being shown, interpolated between the
+    /// last two updates.
+    fn view(&self) -> (Camera, FrameTime) {
+        let alpha = self.timestep.alpha();
+        // Interpolating between equal cameras may still round differently from frame to frame,
+        // which would restart accumulation.
+        let camera = if self.previous_camera == self.camera {
+            self.camera.clone()
+        } else {
+            self.previous_camera.interpolate(&self.camera, alpha)
+        };
+        let time = (self.time - self.timestep.dt() * (1.0 - alpha)).max(0.0);
+        let frame = FrameTime {
+            time,
+            delta_time: (time - self.last_frame_time).max(0.0),
+            frame: self.frame,
+        };
+        (camera, frame)
+    }
+
+    /// Prints what is under the cursor.
+    fn pick(&self) {
+        let builtin;
+        let scene = match &self.scene {
+            Some(scene) => scene,
+            None => {
+                builtin = Scene::builtin();
+                &builtin
+            }
+        };
+        // The center of the pixel under the cursor, as the fragment shader sees it.
+        let coord = self.cursor_position.floor() + 0.5;
+        let (camera, frame) = self.view();
+        match picking::pick(&scene.at(frame), &camera, coord) {
+            Some(pick) => println!("Picked {pick}"),
+            None => println!("Picked nothing"),
         }
     }
-}
 
-impl ApplicationHandler for App {
-    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let window = Arc::new(
-            event_loop
-                .create_window(Window::default_attributes())
-                .unwrap(),
+    /// Renders the current view at `scale` times the window size and saves it next to the
+    /// working directory as `<kind>-<timestamp>.png`.
+    fn capture(&mut self, kind: &str, scale: u32) {
+        let Some(rcx) = &self.render_ctx else {
+            return;
+        };
+        let window_size = rcx.window.inner_size();
+        let size = [window_size.width * scale, window_size.height * scale];
+        let ss = self.supersample;
+        let (camera, frame) = self.view();
+
+        let renderer = match self.capture_renderer.take() {
+            Some(renderer) => Ok(renderer),
+            None => HeadlessRenderer::with_device(
+                self.device.clone(),
+                self.queue.clone(),
+                self.scene_shader.clone(),
+                self.scene.as_ref().unwrap_or(&Scene::builtin()),
+            ),
+        };
+        let pixels = renderer.and_then(|mut renderer| {
+            renderer.set_lights(self.resources.lights.iter().cloned().collect());
+            renderer.set_settings(self.resources.settings);
+            renderer.set_post_settings(self.post_settings.clone());
+            let pixels = renderer.render(&camera, frame, size[0] * ss, size[1] * ss);
+            self.capture_renderer = Some(renderer);
+            pixels
+        });
+        let pixels = match pixels {
+            Ok(pixels) => capture::downsample(&pixels, size[0], size[1], ss),
+            Err(e) => {
+                eprintln!("Capture failed: {e}");
+                return;
+            }
+        };
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let path = PathBuf::from(format!("{kind}-{timestamp}.png"));
+        let text = capture::metadata(
+            &camera,
+            frame.time,
+            self.scene_path.as_deref(),
+            size,
+            ss,
+            &self.resources.settings,
+            &self.post_settings,
         );
-        let surface = Surface::from_window(self.instance.clone(), window.clone()).unwrap();
+        match headless::save_png(&path, size[0], size[1], &pixels, &text) {
+            Ok(()) => println!("Wrote {}x{} {kind} to {}", size[0], size[1], path.display()),
+            Err(e) => eprintln!("Cannot write {}: {e}", path.display()),
+        }
+    }
+
+    /// Recompiles the scene shader from disk, reloading the scene file if there is one, and
+    /// swaps in a new pipeline. On failure the current scene and pipeline are kept.
+    fn reload_shaders(&mut self) {
+        let scene = match self.scene_path.as_deref().map(Scene::load).transpose() {
+            Ok(scene) => scene,
+            Err(e) => {
+                eprintln!("{e}\nScene reload failed, keeping the previous scene.");
+                return;
+            }
+        };
+        let scene_source = scene.as_ref().map(scene::generate_glsl);
+
+        let scene_shader = match compiler::compile_scene_shader_from_disk(
+            self.device.clone(),
+            self.backend,
+            scene_source.as_deref(),
+        ) {
+            Ok(scene_shader) => scene_shader,
+            Err(e) => {
+                eprintln!("{e}\nShader reload failed, keeping the previous pipeline.");
+                return;
+            }
+        };
+
+        if let Some(rcx) = self.render_ctx.as_mut() {
+            match ScenePipeline::new(
+                self.device.clone(),
+                rcx.render_pass.clone(),
+                scene_shader.clone(),
+                rcx.quality,
+            ) {
+                Ok(pipeline) => {
+                    rcx.pipeline = pipeline;
+                    rcx.accumulation.reset();
+                }
+                Err(e) => {
+                    eprintln!("{e}\nShader reload failed, keeping the previous pipeline.");
+                    return;
+                }
+            }
+        }
+        if let Some(scene) = &scene {
+            self.resources.params = scene.params.clone();
+            self.resources.materials.replace(scene.materials.clone());
+            self.resources.lights = LightList::new(scene.lights.clone());
+            self.resources.bvh.replace(&scene.root);
+            self.placed_lights.clear();
+        }
+        self.scene = scene;
+        self.scene_shader = scene_shader;
+        self.capture_renderer = None;
+        println!("Shaders reloaded");
+    }
+
+    fn create_render_context(&self, window: Arc<Window>) -> Result<RenderContext, AppError> {
+        let surface = Surface::from_window(self.instance.clone(), window.clone())
+            .map_err(AppError::Surface)?;
         let window_size = window.inner_size();
+        let physical_device = self.device.physical_device();
 
         let (swapchain, images) = {
-            let surface_capabilities = self
-                .device
-                .physical_device()
+            let surface_capabilities = physical_device
                 .surface_capabilities(&surface, Default::default())
-                .unwrap();
-
-            let (image_format, _) = self
-                .device
-                .physical_device()
-                .surface_formats(&surface, Default::default())
-                .unwrap()[0];
+                .map_err(|e| AppError::vulkan(e, AppError::Swapchain))?;
+
+            let (image_format, image_color_space) = choose_surface_format(
+                &physical_device
+                    .surface_formats(&surface, Default::default())
+                    .map_err(|e| AppError::vulkan(e, AppError::Swapchain))?,
+            )?;
+            println!("Swapchain format: {image_format:?} ({image_color_space:?})");
+
+            let present_mode = if physical_device
+                .surface_present_modes(&surface, Default::default())
+                .map_err(|e| AppError::vulkan(e, AppError::Swapchain))?
+                .contains(&self.present_mode)
+            {
+                self.present_mode
+            } else {
+                // FIFO is the only mode every implementation has to support.
+                eprintln!("{:?} is not supported, using Fifo", self.present_mode);
+                PresentMode::Fifo
+            };
+
+            let composite_alpha = surface_capabilities
+                .supported_composite_alpha
+                .into_iter()
+                .next()
+                .ok_or_else(|| {
+                    AppError::Window("the surface supports no composite alpha mode".into())
+                })?;
 
             Swapchain::new(
                 self.device.clone(),
@@ -252,112 +483,154 @@ impl ApplicationHandler for App {
                 SwapchainCreateInfo {
                     min_image_count: surface_capabilities.min_image_count.max(2),
                     image_format,
+                    image_color_space,
                     image_extent: window_size.into(),
                     image_usage: ImageUsage::COLOR_ATTACHMENT,
-                    composite_alpha: surface_capabilities
-                        .supported_composite_alpha
-                        .into_iter()
-                        .next()
-                        .unwrap(),
-                    present_mode: vulkano::swapchain::PresentMode::Fifo,
+                    composite_alpha,
+                    present_mode,
                     ..Default::default()
                 },
             )
-            .unwrap()
+            .map_err(|e| AppError::vulkan(e, AppError::Swapchain))?
         };
 
-        let render_pass = vulkano::single_pass_renderpass!(
+        let render_pass = render::render_pass(self.device.clone(), render::HDR_FORMAT)?;
+        let hdr = render::scene_framebuffer(
+            self.memory_allocator.clone(),
+            render_pass.clone(),
+            window_size.into(),
+        )?;
+        let quality = self.resources.settings.quality;
+        let pipeline = ScenePipeline::new(
             self.device.clone(),
-            attachments: {
-                color: {
-                    format: swapchain.image_format(),
-                    samples: 1,
-                    load_op: Clear,
-                    store_op: Store,
-                },
-            },
-            pass: {
-                color: [color],
-                depth_stencil: {},
-            },
-        )
-        .unwrap();
-
-        let framebuffers = window_size_dependent_setup(&images, &render_pass);
-        let pipeline = {
-            let vs = vertex::load(self.device.clone())
-                .unwrap()
-                .entry_point("main")
-                .unwrap();
-            let fs = fragment::load(self.device.clone())
-                .unwrap()
-                .entry_point("main")
-                .unwrap();
-
-            let vertex_input_state = MyVertex::per_vertex().definition(&vs).unwrap();
-
-            let stages = [
-                PipelineShaderStageCreateInfo::new(vs),
-                PipelineShaderStageCreateInfo::new(fs),
-            ];
-
-            let layout = PipelineLayout::new(
-                self.device.clone(),
-                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
-                    .into_pipeline_layout_create_info(self.device.clone())
-                    .unwrap(),
-            )
-            .unwrap();
-
-            let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
-
-            GraphicsPipeline::new(
-                self.device.clone(),
-                None,
-                GraphicsPipelineCreateInfo {
-                    stages: stages.into_iter().collect(),
-                    vertex_input_state: Some(vertex_input_state),
-                    input_assembly_state: Some(InputAssemblyState::default()),
-                    viewport_state: Some(ViewportState::default()),
-                    rasterization_state: Some(RasterizationState::default()),
-                    multisample_state: Some(MultisampleState::default()),
-                    color_blend_state: Some(ColorBlendState::with_attachment_states(
-                        subpass.num_color_attachments(),
-                        ColorBlendAttachmentState::default(),
-                    )),
-                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
-                    subpass: Some(subpass.into()),
-                    ..GraphicsPipelineCreateInfo::layout(layout)
-                },
-            )
-            .unwrap()
-        };
-
-        let viewport = Viewport {
-            offset: [0.0, 0.0],
-            extent: window_size.into(),
-            depth_range: 0.0..=1.0,
-        };
+            render_pass.clone(),
+            self.scene_shader.clone(),
+            quality,
+        )?;
+        let post = PostChain::new(
+            self.device.clone(),
+            self.memory_allocator.clone(),
+            swapchain.image_format(),
+        )?;
+        let framebuffers = window_size_dependent_setup(&images, post.render_pass())?;
 
         let recreate_swapchain = false;
 
         let previous_frame_end = Some(sync::now(self.device.clone()).boxed());
 
-        self.render_ctx = Some(RenderContext {
+        let accumulation = Accumulation::new(self.memory_allocator.clone(), window_size.into())?;
+
+        let timer = GpuTimer::new(&self.queue)?;
+        if timer.is_none() && self.render_scale.target_frame_time.is_some() {
+            eprintln!("The device has no timestamps, the render scale stays fixed");
+        }
+
+        Ok(RenderContext {
             window,
             swapchain,
             render_pass,
-            framebuffers,
+            hdr,
+            scene_extent: window_size.into(),
             pipeline,
-            viewport,
+            quality,
+            post,
+            framebuffers,
             recreate_swapchain,
             previous_frame_end,
-        });
+            accumulation,
+            timer,
+        })
+    }
+
+    /// Replaces a lost device and everything created from it. The scene, lights and camera are
+    /// kept, the window gets a new swapchain.
+    fn recreate_device(&mut self, event_loop: &ActiveEventLoop) -> Result<(), AppError> {
+        let window = self.render_ctx.take().map(|rcx| rcx.window);
+
+        let (device, queue) =
+            create_device(&self.instance, self.device_selector.as_ref(), event_loop)?;
+        let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+
+        self.command_buffer_allocator = Arc::new(StandardCommandBufferAllocator::new(
+            device.clone(),
+            Default::default(),
+        ));
+        self.vertex_buffer = render::fullscreen_quad(memory_allocator.clone())?;
+        self.scene_shader = load_scene_shader(
+            device.clone(),
+            self.backend,
+            self.scene.as_ref(),
+            self.shader_watcher.is_some(),
+        )?;
+        self.resources
+            .recreate(device.clone(), memory_allocator.clone());
+        self.memory_allocator = memory_allocator;
+        self.capture_renderer = None;
+        self.device = device;
+        self.queue = queue;
+
+        if let Some(window) = window {
+            self.render_ctx = Some(self.create_render_context(window)?);
+        }
+        Ok(())
+    }
+
+    /// Handles an error returned by `draw_frame`, rebuilding what was lost when possible and
+    /// stopping the event loop otherwise.
+    fn recover(&mut self, event_loop: &ActiveEventLoop, e: AppError) {
+        let result = match e {
+            AppError::SurfaceLost => {
+                eprintln!("Surface lost, recreating the swapchain");
+                // The old swapchain has to go before the window gets a new surface.
+                let window = self.render_ctx.take().map(|rcx| rcx.window);
+                window.map_or(Ok(()), |window| {
+                    self.create_render_context(window)
+                        .map(|rcx| self.render_ctx = Some(rcx))
+                })
+            }
+            AppError::DeviceLost => {
+                eprintln!("Device lost, recreating it");
+                self.recreate_device(event_loop)
+            }
+            e => Err(e),
+        };
+        if let Err(e) = result {
+            self.fail(event_loop, e);
+        }
+    }
+
+    /// Runs the simulation steps that are due.
+    fn tick(&mut self) {
+        let steps = self.timestep.advance();
+        for _ in 0..steps {
+            self.update(self.timestep.dt());
+        }
+        self.ups += steps;
     }
 
-    fn new_events(&mut self, event_loop: &ActiveEventLoop, cause: StartCause) {
-        let elapsed = self.frame_time.elapsed();
-        let ts = elapsed.as_secs_f32();
+    /// Advances the camera and the animations by one fixed step of `dt` seconds.
+    fn update(&mut self, dt: f32) {
+        self.previous_camera = self.camera.clone();
+        self.time += dt;
+
+        if let Some(playback) = self.playback.as_mut() {
+            let duration = self.camera_path.duration();
+            playback.time += dt * self.playback_speed;
+            if playback.time > duration {
+                if self.benchmark {
+                    self.stop_playback();
+                    return;
+                }
+                playback.time %= duration.max(f32::EPSILON);
+                self.camera_path.apply(playback.time, &mut self.camera);
+                self.previous_camera = self.camera.clone();
+            } else {
+                self.camera_path.apply(playback.time, &mut self.camera);
+            }
+            self.cam_rotation = None;
+            self.cam_zoom = 0.0;
+            return;
+        }
 
         let mut events: Vec<CameraEvent> = vec![];
         if self.cam_up {
@@ -372,14 +645,289 @@ impl ApplicationHandler for App {
         if self.cam_right {
             events.push(CameraEvent::Right)
         }
+        if self.cam_ascend {
+            events.push(CameraEvent::Ascend)
+        }
+        if self.cam_descend {
+            events.push(CameraEvent::Descend)
+        }
+        if self.cam_roll_left {
+            events.push(CameraEvent::RollLeft)
+        }
+        if self.cam_roll_right {
+            events.push(CameraEvent::RollRight)
+        }
         if let Some(delta) = self.cam_rotation {
             events.push(CameraEvent::RotateXY { delta })
         }
+        if self.cam_zoom != 0.0 {
+            events.push(CameraEvent::Zoom {
+                delta: self.cam_zoom,
+            })
+        }
         if !events.is_empty() {
-            self.camera.update(&events, ts);
+            self.camera.update(self.controller.as_mut(), &events, dt);
         }
         self.cam_rotation = None;
-        self.frame_time = Instant::now();
+        self.cam_zoom = 0.0;
+    }
+
+    /// Adds the current camera as a keyframe and saves the path.
+    fn record_keyframe(&mut self) {
+        self.camera_path.record(&self.camera);
+        let count = self.camera_path.keyframes.len();
+        match self.camera_path.save(&self.camera_path_file) {
+            Ok(()) => println!(
+                "Keyframe {count} saved to {}",
+                self.camera_path_file.display()
+            ),
+            Err(e) => eprintln!("Cannot write {}: {e}", self.camera_path_file.display()),
+        }
+    }
+
+    fn start_playback(&mut self) {
+        if self.camera_path.keyframes.len() < 2 {
+            println!("The camera path needs at least 2 keyframes, record them with R");
+            return;
+        }
+        self.playback = Some(Playback::new());
+        println!(
+            "Playing {:.1}s camera path at {}x speed",
+            self.camera_path.duration(),
+            self.playback_speed
+        );
+    }
+
+    /// Stops playing the camera path and hands the camera back to the controller. A benchmark
+    /// run prints its results.
+    fn stop_playback(&mut self) {
+        let Some(playback) = self.playback.take() else {
+            return;
+        };
+        self.controller = self.controller.kind().create(&mut self.camera);
+        self.previous_camera = self.camera.clone();
+
+        if self.benchmark {
+            let elapsed = playback.started.elapsed().as_secs_f32();
+            println!(
+                "Benchmark: {} frames in {elapsed:.2}s, average {:.1} FPS, slowest frame {:.2}ms",
+                playback.frames,
+                playback.frames as f32 / elapsed,
+                playback.slowest_frame.as_secs_f64() * 1000.0
+            );
+        }
+    }
+
+    fn toggle_effect(&mut self, effect: Effect) {
+        let state = if self.post_settings.toggle(effect) {
+            "on"
+        } else {
+            "off"
+        };
+        println!("{effect} {state}");
+    }
+
+    fn draw_frame(&mut self) -> Result<(), AppError> {
+        let (camera, frame) = self.view();
+        let Some(rcx) = self.render_ctx.as_mut() else {
+            return Ok(());
+        };
+        let window_size = rcx.window.inner_size();
+
+        if window_size.width == 0 || window_size.height == 0 {
+            return Ok(());
+        }
+        if self.fps == 0 {
+            if let Some(previous_frame_end) = rcx.previous_frame_end.as_mut() {
+                previous_frame_end.cleanup_finished();
+            }
+        }
+        if rcx.recreate_swapchain {
+            let (new_swapchain, new_images) = rcx
+                .swapchain
+                .recreate(SwapchainCreateInfo {
+                    image_extent: window_size.into(),
+                    ..rcx.swapchain.create_info()
+                })
+                .map_err(|e| AppError::vulkan(e, AppError::Swapchain))?;
+
+            rcx.swapchain = new_swapchain;
+            rcx.framebuffers = window_size_dependent_setup(&new_images, rcx.post.render_pass())?;
+            rcx.hdr = render::scene_framebuffer(
+                self.memory_allocator.clone(),
+                rcx.render_pass.clone(),
+                window_size.into(),
+            )?;
+            rcx.accumulation =
+                Accumulation::new(self.memory_allocator.clone(), window_size.into())?;
+            rcx.recreate_swapchain = false;
+        }
+
+        let (image_index, suboptimal, acquire_future) =
+            match acquire_next_image(rcx.swapchain.clone(), None).map_err(Validated::unwrap) {
+                Ok(r) => r,
+                Err(VulkanError::OutOfDate) => {
+                    rcx.recreate_swapchain = true;
+                    return Ok(());
+                }
+                Err(e) => return Err(Validated::Error(e).into()),
+            };
+
+        if suboptimal {
+            rcx.recreate_swapchain = true;
+        }
+
+        let elapsed = self.timer.elapsed().as_secs_f32();
+        if elapsed >= 1.0 {
+            self.timer = Instant::now();
+            let mut title = format!(
+                "FPS {:.0} UPS {:.0}",
+                self.fps as f32 / elapsed,
+                self.ups as f32 / elapsed
+            );
+            if self.accumulate {
+                title += &format!(" Samples {}", rcx.accumulation.samples());
+            }
+            if let Some(gpu_time) = rcx.timer.as_ref().and_then(GpuTimer::last) {
+                title += &format!(" GPU {:.1} ms", gpu_time.as_secs_f32() * 1000.0);
+            }
+            if self.render_scale != RenderScale::default() {
+                title += &format!(" Scale {:.0}%", self.render_scale.scale * 100.0);
+            }
+            rcx.window.set_title(&title);
+            self.fps = 0;
+            self.ups = 0;
+        }
+
+        let scene_extent = self.render_scale.extent(window_size.into());
+        if scene_extent != rcx.scene_extent {
+            rcx.scene_extent = scene_extent;
+            rcx.accumulation.reset();
+        }
+
+        let quality = self.resources.settings.quality;
+        if quality != rcx.quality {
+            rcx.pipeline = ScenePipeline::new(
+                self.device.clone(),
+                rcx.render_pass.clone(),
+                self.scene_shader.clone(),
+                quality,
+            )?;
+            rcx.quality = quality;
+            rcx.accumulation.reset();
+        }
+
+        let changed = self.resources.animate(frame);
+        let sample = self
+            .accumulate
+            .then(|| rcx.accumulation.next_sample(&camera, changed));
+
+        let descriptor_set = self.resources.descriptor_set(
+            rcx.pipeline.layout(),
+            scene_extent.map(|x| x as f32),
+            &camera,
+            rcx.accumulation.image(),
+            sample,
+        )?;
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.command_buffer_allocator.clone(),
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        if let Some(timer) = rcx.timer.as_mut() {
+            // While a still view accumulates, a new scale would start it over.
+            let still = self.accumulate && rcx.accumulation.samples() > 1;
+            if let Some(gpu_time) = timer.begin(&mut builder)?.filter(|_| !still) {
+                self.render_scale.adapt(gpu_time);
+            }
+        }
+
+        rcx.pipeline.record(
+            &mut builder,
+            rcx.hdr.clone(),
+            scene_extent,
+            self.vertex_buffer.clone(),
+            descriptor_set,
+        )?;
+        rcx.post.draw(
+            &mut builder,
+            rcx.framebuffers[image_index as usize].clone(),
+            rcx.hdr.attachments()[0].clone(),
+            scene_extent,
+            self.vertex_buffer.clone(),
+            &self.resources.settings,
+            &self.post_settings,
+            self.frame,
+        )?;
+        if let Some(timer) = rcx.timer.as_mut() {
+            timer.end(&mut builder)?;
+        }
+
+        let command_buffer = builder.build()?;
+
+        let sc_info =
+            SwapchainPresentInfo::swapchain_image_index(rcx.swapchain.clone(), image_index);
+
+        let future = rcx
+            .previous_frame_end
+            .take()
+            .unwrap_or_else(|| sync::now(self.device.clone()).boxed())
+            .join(acquire_future)
+            .then_execute(self.queue.clone(), command_buffer)
+            .map_err(|e| AppError::Render(e.to_string()))?
+            .then_swapchain_present(self.queue.clone(), sc_info)
+            .then_signal_fence_and_flush();
+
+        match future.map_err(Validated::unwrap) {
+            Ok(future) => {
+                rcx.previous_frame_end = Some(future.boxed());
+                self.fps += 1;
+                self.frame += 1;
+                self.last_frame_time = frame.time;
+                if let Some(playback) = self.playback.as_mut() {
+                    let now = Instant::now();
+                    if playback.frames > 0 {
+                        playback.slowest_frame =
+                            playback.slowest_frame.max(now - playback.last_frame);
+                    }
+                    playback.last_frame = now;
+                    playback.frames += 1;
+                }
+            }
+            Err(VulkanError::OutOfDate) => {
+                rcx.recreate_swapchain = true;
+                rcx.previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+            }
+            Err(e) => {
+                rcx.previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+                return Err(Validated::Error(e).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ApplicationHandler for App {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        let [width, height] = self.window_size;
+        let mut attributes =
+            Window::default_attributes().with_inner_size(PhysicalSize::new(width, height));
+        if self.fullscreen {
+            attributes = attributes.with_fullscreen(Some(Fullscreen::Borderless(None)));
+        }
+        let window = match event_loop.create_window(attributes) {
+            Ok(window) => Arc::new(window),
+            Err(e) => return self.fail(event_loop, AppError::Window(e.to_string())),
+        };
+        let size = window.inner_size();
+        self.camera
+            .resize(size.width as usize, size.height as usize);
+        match self.create_render_context(window) {
+            Ok(rcx) => self.render_ctx = Some(rcx),
+            Err(e) => self.fail(event_loop, e),
+        }
     }
 
     fn window_event(
@@ -388,8 +936,6 @@ impl ApplicationHandler for App {
         _window_id: WindowId,
         event: WindowEvent,
     ) {
-        let rcx = self.render_ctx.as_mut().unwrap();
-
         match event {
             WindowEvent::KeyboardInput {
                 event:
@@ -426,14 +972,137 @@ impl ApplicationHandler for App {
                 PhysicalKey::Code(KeyCode::KeyD) => {
                     self.cam_right = state.is_pressed();
                 }
+                PhysicalKey::Code(KeyCode::Space) => {
+                    self.cam_ascend = state.is_pressed();
+                }
+                PhysicalKey::Code(KeyCode::ShiftLeft) => {
+                    self.cam_descend = state.is_pressed();
+                }
+                PhysicalKey::Code(KeyCode::KeyQ) => {
+                    self.cam_roll_left = state.is_pressed();
+                }
+                PhysicalKey::Code(KeyCode::KeyE) => {
+                    self.cam_roll_right = state.is_pressed();
+                }
+                PhysicalKey::Code(KeyCode::F12) if state.is_pressed() => {
+                    if self.modifiers.shift_key() {
+                        self.capture("poster", self.poster_scale);
+                    } else {
+                        self.capture("screenshot", 1);
+                    }
+                }
+                PhysicalKey::Code(KeyCode::Tab) if state.is_pressed() => {
+                    let kind = self.controller.kind().next();
+                    self.controller = kind.create(&mut self.camera);
+                    self.previous_camera = self.camera.clone();
+                    println!("Camera: {kind}");
+                }
+                PhysicalKey::Code(KeyCode::KeyL) if state.is_pressed() => {
+                    let light = Light::point(self.camera.position, 20.0, Vec3::ONE, 10.0);
+                    self.placed_lights.push(self.resources.lights.add(light));
+                }
+                PhysicalKey::Code(KeyCode::KeyK) if state.is_pressed() => {
+                    if let Some(id) = self.placed_lights.pop() {
+                        self.resources.lights.remove(id);
+                    }
+                }
+                PhysicalKey::Code(KeyCode::KeyR) if state.is_pressed() => {
+                    self.record_keyframe();
+                }
+                PhysicalKey::Code(KeyCode::KeyP) if state.is_pressed() => {
+                    if self.playback.is_some() {
+                        self.stop_playback();
+                    } else {
+                        self.start_playback();
+                    }
+                }
+                PhysicalKey::Code(KeyCode::BracketLeft) if state.is_pressed() => {
+                    self.playback_speed = (self.playback_speed / 2.0).max(1.0 / 16.0);
+                    println!("Playback speed {}x", self.playback_speed);
+                }
+                PhysicalKey::Code(KeyCode::BracketRight) if state.is_pressed() => {
+                    self.playback_speed = (self.playback_speed * 2.0).min(16.0);
+                    println!("Playback speed {}x", self.playback_speed);
+                }
+                PhysicalKey::Code(KeyCode::KeyC) if state.is_pressed() => {
+                    self.accumulate = !self.accumulate;
+                    if let Some(rcx) = self.render_ctx.as_mut() {
+                        rcx.accumulation.reset();
+                    }
+                    let state = if self.accumulate { "on" } else { "off" };
+                    println!("Accumulation {state}");
+                }
+                PhysicalKey::Code(KeyCode::KeyM) if state.is_pressed() => {
+                    let mode = self.resources.settings.mode.next();
+                    self.resources.settings.mode = mode;
+                    if let Some(rcx) = self.render_ctx.as_mut() {
+                        rcx.accumulation.reset();
+                    }
+                    println!("Render mode: {mode}");
+                }
+                PhysicalKey::Code(KeyCode::KeyT) if state.is_pressed() => {
+                    let tonemap = self.resources.settings.tonemap.next();
+                    self.resources.settings.tonemap = tonemap;
+                    println!("Tone mapping: {tonemap}");
+                }
+                PhysicalKey::Code(KeyCode::KeyG) if state.is_pressed() => {
+                    let quality = self.resources.settings.quality.next();
+                    self.resources.settings.quality = quality;
+                    println!("Quality: {quality}");
+                }
+                PhysicalKey::Code(KeyCode::Minus) if state.is_pressed() => {
+                    self.resources.settings.exposure -= 0.5;
+                    println!("Exposure: {:+} EV", self.resources.settings.exposure);
+                }
+                PhysicalKey::Code(KeyCode::Equal) if state.is_pressed() => {
+                    self.resources.settings.exposure += 0.5;
+                    println!("Exposure: {:+} EV", self.resources.settings.exposure);
+                }
+                PhysicalKey::Code(KeyCode::Comma) if state.is_pressed() => {
+                    self.render_scale.step(-0.05);
+                    println!("Render scale: {:.0}%", self.render_scale.scale * 100.0);
+                }
+                PhysicalKey::Code(KeyCode::Period) if state.is_pressed() => {
+                    self.render_scale.step(0.05);
+                    println!("Render scale: {:.0}%", self.render_scale.scale * 100.0);
+                }
+                PhysicalKey::Code(KeyCode::KeyU) if state.is_pressed() => {
+                    let upscale = self.post_settings.upscale.next();
+                    self.post_settings.upscale = upscale;
+                    println!("Upscale: {upscale}");
+                }
+                PhysicalKey::Code(KeyCode::F1) if state.is_pressed() => {
+                    self.toggle_effect(Effect::Bloom)
+                }
+                PhysicalKey::Code(KeyCode::F2) if state.is_pressed() => {
+                    self.toggle_effect(Effect::ChromaticAberration)
+                }
+                PhysicalKey::Code(KeyCode::F3) if state.is_pressed() => {
+                    self.toggle_effect(Effect::ColorGrade)
+                }
+                PhysicalKey::Code(KeyCode::F4) if state.is_pressed() => {
+                    self.toggle_effect(Effect::Vignette)
+                }
+                PhysicalKey::Code(KeyCode::F5) if state.is_pressed() => {
+                    self.toggle_effect(Effect::Grain)
+                }
+                PhysicalKey::Code(KeyCode::F6) if state.is_pressed() => {
+                    self.toggle_effect(Effect::Fxaa)
+                }
+                PhysicalKey::Code(KeyCode::Backspace) if state.is_pressed() => {
+                    self.stop_playback();
+                    self.camera_path = CameraPath::default();
+                    println!("Camera path cleared");
+                }
                 _ => {}
             },
 
-            WindowEvent::CursorMoved {
-                device_id,
-                position,
-            } => {
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+            }
+            WindowEvent::CursorMoved { position, .. } => {
                 let pos = Vec2::new(position.x as f32, position.y as f32);
+                self.cursor_position = pos;
                 if self.mouse_pressed {
                     if self.last_mouse_pos == Vec2::ZERO {
                         self.last_mouse_pos = pos;
@@ -441,27 +1110,32 @@ impl ApplicationHandler for App {
                     let delta = (pos - self.last_mouse_pos) * 0.05;
                     self.last_mouse_pos = pos;
                     if delta.x != 0.0 || delta.y != 0.0 {
-                        self.cam_rotation = Some(delta);
+                        // Accumulated until the next update.
+                        self.cam_rotation = Some(self.cam_rotation.unwrap_or(Vec2::ZERO) + delta);
                     }
                 }
             }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.cam_zoom += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 40.0,
+                };
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Right,
+                ..
+            } => self.pick(),
             WindowEvent::MouseInput {
-                device_id,
                 state,
-                button,
+                button: MouseButton::Left,
+                ..
             } => {
                 self.mouse_pressed = state.is_pressed();
-                self.render_ctx
-                    .as_mut()
-                    .unwrap()
-                    .window
-                    .set_cursor_visible(false);
+                if let Some(rcx) = &self.render_ctx {
+                    rcx.window.set_cursor_visible(!self.mouse_pressed);
+                }
                 if !self.mouse_pressed {
-                    self.render_ctx
-                        .as_mut()
-                        .unwrap()
-                        .window
-                        .set_cursor_visible(true);
                     self.cam_rotation = None;
                     self.last_mouse_pos = Vec2::ZERO;
                 }
@@ -469,198 +1143,160 @@ impl ApplicationHandler for App {
             WindowEvent::CloseRequested => {
                 event_loop.exit();
             }
-            WindowEvent::Resized(_) => {
-                rcx.recreate_swapchain = true;
-            }
-            WindowEvent::RedrawRequested => {
-                let window_size = rcx.window.inner_size();
-
-                if window_size.width == 0 || window_size.height == 0 {
-                    return;
-                }
-                if self.fps == 0 {
-                    rcx.previous_frame_end.as_mut().unwrap().cleanup_finished();
-                }
-                if rcx.recreate_swapchain {
-                    let (new_swapchain, new_images) = rcx
-                        .swapchain
-                        .recreate(SwapchainCreateInfo {
-                            image_extent: window_size.into(),
-                            ..rcx.swapchain.create_info()
-                        })
-                        .expect("failed to recreate swapchain");
-
-                    rcx.swapchain = new_swapchain;
-                    rcx.framebuffers = window_size_dependent_setup(&new_images, &rcx.render_pass);
-                    rcx.viewport.extent = window_size.into();
-                    rcx.recreate_swapchain = false;
-                }
-
-                let (image_index, suboptimal, acquire_future) = match acquire_next_image(
-                    rcx.swapchain.clone(),
-                    None,
-                )
-                .map_err(Validated::unwrap)
-                {
-                    Ok(r) => r,
-                    Err(VulkanError::OutOfDate) => {
-                        rcx.recreate_swapchain = true;
-                        return;
-                    }
-                    Err(e) => panic!("failed to acquire next image: {e}"),
-                };
-
-                if suboptimal {
+            WindowEvent::Resized(size) => {
+                if let Some(rcx) = self.render_ctx.as_mut() {
                     rcx.recreate_swapchain = true;
                 }
-
-                let millis = self.timer.elapsed().as_millis();
-
-                self.lag += millis as u32;
-
-                while self.lag >= 16 {
-                    // Update state
-
-                    self.lag -= 16;
-                }
-
-                self.ups += 1;
-
-                if millis > 1000 {
-                    self.timer = Instant::now();
-                    rcx.window
-                        .set_title(format!("FPS {} UPS {}", self.fps, self.ups).as_str());
-                    self.fps = 0;
-                    self.ups = 0;
-                }
-
-                let pc_screen = fragment::AppData {
-                    screen: [(window_size.width as f32), (window_size.height as f32)].into(),
-                    cam_position: self.camera.position.to_array().into(),
-                    cam_uu: self.camera.uu.to_array().into(),
-                    cam_vv: self.camera.vv.to_array().into(),
-                    cam_ww: self.camera.ww.to_array().into(),
-                    materials: [
-                        fragment::Material {
-                            specular: 2.9.into(),
-                            shininess: 320.0.into(),
-                            roughness: 0.8.into(),
-                            diffuse: 0.9.into(),
-                            color: vec3(0.7, 0.0, 0.0).to_array().into(),
-                        }
-                        .into(),
-                        fragment::Material {
-                            specular: 0.5.into(),
-                            shininess: 80.0.into(),
-                            roughness: 0.8.into(),
-                            diffuse: 1.1.into(),
-                            color: vec3(0.9, 0.9, 0.8).to_array().into(),
-                        }
-                        .into(),
-                    ],
-                };
-
-                let layout = rcx.pipeline.layout().clone();
-
-                let mut builder = AutoCommandBufferBuilder::primary(
-                    self.command_buffer_allocator.clone(),
-                    self.queue.queue_family_index(),
-                    CommandBufferUsage::OneTimeSubmit,
-                )
-                .unwrap();
-
-                builder
-                    .begin_render_pass(
-                        RenderPassBeginInfo {
-                            clear_values: vec![Some([0.0, 0.0, 0.0, 1.0].into())],
-                            ..RenderPassBeginInfo::framebuffer(
-                                rcx.framebuffers[image_index as usize].clone(),
-                            )
-                        },
-                        SubpassBeginInfo {
-                            contents: SubpassContents::Inline,
-                            ..Default::default()
-                        },
-                    )
-                    .unwrap()
-                    .set_viewport(0, [rcx.viewport.clone()].into_iter().collect())
-                    .unwrap()
-                    .push_constants(layout, 0, pc_screen)
-                    .unwrap()
-                    .bind_pipeline_graphics(rcx.pipeline.clone())
-                    .unwrap()
-                    .bind_vertex_buffers(0, self.vertex_buffer.clone())
-                    .unwrap();
-
-                // We add a draw command.
-                unsafe { builder.draw(self.vertex_buffer.len() as u32, 1, 0, 0) }.unwrap();
-
-                builder.end_render_pass(Default::default()).unwrap();
-
-                let command_buffer = builder.build().unwrap();
-
-                let mut sc_info =
-                    SwapchainPresentInfo::swapchain_image_index(rcx.swapchain.clone(), image_index);
-
-                let future = rcx
-                    .previous_frame_end
-                    .take()
-                    .unwrap()
-                    .join(acquire_future)
-                    .then_execute(self.queue.clone(), command_buffer)
-                    .unwrap()
-                    .then_swapchain_present(self.queue.clone(), sc_info)
-                    .then_signal_fence_and_flush();
-
-                match future.map_err(Validated::unwrap) {
-                    Ok(future) => {
-                        rcx.previous_frame_end = Some(future.boxed());
-                        self.fps += 1;
-                    }
-                    Err(VulkanError::OutOfDate) => {
-                        rcx.recreate_swapchain = true;
-                        rcx.previous_frame_end = Some(sync::now(self.device.clone()).boxed());
-                    }
-                    Err(e) => {
-                        panic!("failed to flush future: {e}");
-                        // previous_frame_end = Some(sync::now(device.clone()).boxed());
-                    }
+                if size.width > 0 && size.height > 0 {
+                    let event = CameraEvent::Resize {
+                        w: size.width as usize,
+                        h: size.height as usize,
+                    };
+                    self.camera.update(self.controller.as_mut(), &[event], 0.0);
+                }
+            }
+            WindowEvent::RedrawRequested => {
+                self.tick();
+                if let Err(e) = self.draw_frame() {
+                    self.recover(event_loop, e);
                 }
             }
             _ => {}
         }
     }
 
-    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
-        let rcx = self.render_ctx.as_mut().unwrap();
-        rcx.window.request_redraw();
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        if self.benchmark && self.playback.is_none() {
+            event_loop.exit();
+            return;
+        }
+        if self.shader_watcher.as_mut().is_some_and(|w| w.poll()) {
+            self.reload_shaders();
+        }
+
+        if let Some(rcx) = &self.render_ctx {
+            rcx.window.request_redraw();
+        }
+    }
+}
+
+fn create_device(
+    instance: &Arc<Instance>,
+    selector: Option<&DeviceSelector>,
+    event_loop: &impl HasDisplayHandle,
+) -> Result<(Arc<Device>, Arc<Queue>), AppError> {
+    let device_extensions = DeviceExtensions {
+        khr_swapchain: true,
+        ..DeviceExtensions::empty()
+    };
+
+    let (physical_device, queue_family_index) =
+        device::select_physical_device(instance, selector, |p| {
+            if !p.supported_extensions().contains(&device_extensions)
+                || !p
+                    .supported_features()
+                    .contains(&device::required_features())
+            {
+                return None;
+            }
+            p.queue_family_properties()
+                .iter()
+                .enumerate()
+                .position(|(i, q)| {
+                    // The compute backend runs on the same queue.
+                    q.queue_flags
+                        .contains(QueueFlags::GRAPHICS | QueueFlags::COMPUTE)
+                        && p.presentation_support(i as u32, event_loop)
+                            .unwrap_or(false)
+                })
+                .map(|i| i as u32)
+        })
+        .map_err(AppError::NoDevice)?;
+
+    // Some little debug infos.
+    println!(
+        "Using device: {} (type: {:?})",
+        physical_device.properties().device_name,
+        physical_device.properties().device_type,
+    );
+
+    let (device, mut queues) = Device::new(
+        physical_device,
+        DeviceCreateInfo {
+            enabled_extensions: device_extensions,
+            enabled_features: device::required_features(),
+            queue_create_infos: vec![QueueCreateInfo {
+                queue_family_index,
+                ..Default::default()
+            }],
+
+            ..Default::default()
+        },
+    )
+    .map_err(AppError::Device)?;
+
+    let queue = queues
+        .next()
+        .ok_or_else(|| AppError::NoDevice("the device has no queue".into()))?;
+    Ok((device, queue))
+}
+
+/// With `from_disk` the GLSL sources are compiled from disk, as they may have changed since the
+/// last build, falling back to the built-in shaders if they do not compile.
+fn load_scene_shader(
+    device: Arc<Device>,
+    backend: Backend,
+    scene: Option<&Scene>,
+    from_disk: bool,
+) -> Result<Arc<ShaderModule>, AppError> {
+    if from_disk {
+        let scene_source = scene.map(scene::generate_glsl);
+        match compiler::compile_scene_shader_from_disk(
+            device.clone(),
+            backend,
+            scene_source.as_deref(),
+        ) {
+            Ok(scene_shader) => return Ok(scene_shader),
+            Err(e) => eprintln!("{e}\nFalling back to the built-in shaders."),
+        }
     }
+    render::scene_shader(device, backend, scene)
 }
 
-#[derive(BufferContents, Vertex)]
-#[repr(C)]
-struct MyVertex {
-    #[format(R32G32_SFLOAT)]
-    position: [f32; 2],
+/// Prefers an sRGB format, which encodes the tone mapped colors on write, in the sRGB color space
+/// the tone mapping produces. Failing that any format in that color space will do and the tone
+/// mapping shader encodes instead.
+fn choose_surface_format(
+    formats: &[(Format, ColorSpace)],
+) -> Result<(Format, ColorSpace), AppError> {
+    let srgb_space = formats
+        .iter()
+        .filter(|(_, color_space)| *color_space == ColorSpace::SrgbNonLinear);
+    srgb_space
+        .clone()
+        .find(|(format, _)| tonemap::is_srgb(*format))
+        .or_else(|| srgb_space.clone().next())
+        .or(formats.first())
+        .copied()
+        .ok_or_else(|| AppError::Window("the surface supports no image formats".into()))
 }
 
 fn window_size_dependent_setup(
     images: &[Arc<Image>],
     render_pass: &Arc<RenderPass>,
-) -> Vec<Arc<Framebuffer>> {
+) -> Result<Vec<Arc<Framebuffer>>, AppError> {
     images
         .iter()
         .map(|image| {
-            let view = ImageView::new_default(image.clone()).unwrap();
+            let view = ImageView::new_default(image.clone())?;
 
-            Framebuffer::new(
+            Ok(Framebuffer::new(
                 render_pass.clone(),
                 FramebufferCreateInfo {
                     attachments: vec![view],
                     ..Default::default()
                 },
-            )
-            .unwrap()
+            )?)
         })
-        .collect::<Vec<_>>()
+        .collect()
 }
@@ -0,0 +1,313 @@
+use glam::Vec3;
+use std::sync::Arc;
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    device::DeviceOwned,
+    memory::allocator::{AllocationCreateInfo, MemoryAllocator, MemoryTypeFilter},
+};
+
+use crate::error::AppError;
+use crate::params::ParamValues;
+use crate::scene::{bounds, Aabb, Node};
+use crate::shaders::fragment;
+
+/// Marks leaves in the `right` field of a node, as `BVH_LEAF` in `common.glsl`.
+const LEAF: u32 = u32::MAX;
+
+/// Holds the bounded objects of the scene and keeps a bounding volume hierarchy over them in a
+/// storage buffer, laid out as `common.glsl` describes.
+pub struct Bvh {
+    objects: Vec<Node>,
+    bounds: Vec<Aabb>,
+    buffer: Option<Subbuffer<[fragment::BvhNode]>>,
+}
+
+impl Bvh {
+    pub fn new(root: &Node) -> Self {
+        let objects: Vec<Node> = bounds::objects(root).0.into_iter().cloned().collect();
+        Bvh {
+            bounds: object_bounds(&objects),
+            objects,
+            buffer: None,
+        }
+    }
+
+    pub fn replace(&mut self, root: &Node) {
+        *self = Bvh::new(root);
+    }
+
+    /// Moves the objects driven by parameters, building the hierarchy again if any bounds
+    /// changed.
+    pub fn animate(&mut self, values: &ParamValues) {
+        for object in &mut self.objects {
+            object.update(values);
+        }
+        let bounds = object_bounds(&self.objects);
+        if bounds != self.bounds {
+            self.bounds = bounds;
+            self.buffer = None;
+        }
+    }
+
+    /// Whether the bounds changed since they were last uploaded.
+    pub fn is_dirty(&self) -> bool {
+        self.buffer.is_none()
+    }
+
+    /// Returns the storage buffer, building and uploading the hierarchy first if the bounds
+    /// changed since the last call.
+    pub fn buffer(
+        &mut self,
+        memory_allocator: Arc<dyn MemoryAllocator>,
+    ) -> Result<Subbuffer<[fragment::BvhNode]>, AppError> {
+        if self
+            .buffer
+            .as_ref()
+            .is_some_and(|b| b.device() != memory_allocator.device())
+        {
+            // Left over from a lost device.
+            self.buffer = None;
+        }
+        if let Some(buffer) = &self.buffer {
+            return Ok(buffer.clone());
+        }
+        let buffer = upload(memory_allocator, &build(&self.bounds))?;
+        self.buffer = Some(buffer.clone());
+        Ok(buffer)
+    }
+}
+
+fn object_bounds(objects: &[Node]) -> Vec<Aabb> {
+    objects.iter().filter_map(Node::bounds).collect()
+}
+
+struct BvhNode {
+    bounds: Aabb,
+    left: u32,
+    right: u32,
+}
+
+/// The leaves in the order of `bounds`, followed by the inner nodes with the root last.
+fn build(bounds: &[Aabb]) -> Vec<BvhNode> {
+    let mut nodes: Vec<BvhNode> = bounds
+        .iter()
+        .enumerate()
+        .map(|(i, b)| BvhNode {
+            bounds: *b,
+            left: i as u32,
+            right: LEAF,
+        })
+        .collect();
+    if nodes.is_empty() {
+        // Storage buffers cannot be empty. Nothing reads it without objects.
+        nodes.push(BvhNode {
+            bounds: Aabb {
+                min: Vec3::ZERO,
+                max: Vec3::ZERO,
+            },
+            left: 0,
+            right: LEAF,
+        });
+        return nodes;
+    }
+    let mut objects: Vec<u32> = (0..bounds.len() as u32).collect();
+    split(&mut nodes, &mut objects);
+    nodes
+}
+
+/// Builds the subtree over `objects` and returns its root, splitting them at the median along
+/// the axis their centers spread the most on.
+fn split(nodes: &mut Vec<BvhNode>, objects: &mut [u32]) -> u32 {
+    if let [object] = objects {
+        return *object;
+    }
+    let center = |i: &u32| nodes[*i as usize].bounds.center();
+    let (lo, hi) = objects.iter().map(center).fold(
+        (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+        |(lo, hi), c| (lo.min(c), hi.max(c)),
+    );
+    let spread = hi - lo;
+    let axis = if spread.x >= spread.y && spread.x >= spread.z {
+        0
+    } else if spread.y >= spread.z {
+        1
+    } else {
+        2
+    };
+    objects.sort_unstable_by(|a, b| center(a)[axis].total_cmp(&center(b)[axis]));
+
+    let (left_objects, right_objects) = objects.split_at_mut(objects.len() / 2);
+    let left = split(nodes, left_objects);
+    let right = split(nodes, right_objects);
+    nodes.push(BvhNode {
+        bounds: nodes[left as usize]
+            .bounds
+            .union(nodes[right as usize].bounds),
+        left,
+        right,
+    });
+    nodes.len() as u32 - 1
+}
+
+fn upload(
+    memory_allocator: Arc<dyn MemoryAllocator>,
+    nodes: &[BvhNode],
+) -> Result<Subbuffer<[fragment::BvhNode]>, AppError> {
+    Buffer::from_iter(
+        memory_allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::STORAGE_BUFFER,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        nodes.iter().map(|n| fragment::BvhNode {
+            min: n.bounds.min.to_array(),
+            left: n.left,
+            max: n.bounds.max.to_array(),
+            right: n.right,
+        }),
+    )
+    .map_err(|e| AppError::Render(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reference::common::{hit_union, Hit, Ray};
+    use crate::reference::scene::{object_sdf, sdf};
+    use crate::scene::Scene;
+
+    /// Random values in [0, 1).
+    fn random(seed: &mut u32) -> f32 {
+        *seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+        (*seed >> 8) as f32 / (1 << 24) as f32
+    }
+
+    /// `count` spheres and rotated boxes overlapping each other and sinking into the ground.
+    fn scene(count: usize) -> Scene {
+        let mut seed = count as u32;
+        let mut objects: Vec<String> = (0..count)
+            .map(|i| {
+                let [x, y, z] = [8.0, 1.0, 8.0].map(|size| random(&mut seed) * size);
+                let child = if i % 2 == 0 {
+                    r#"{ "sphere": { "radius": 0.7 } }"#
+                } else {
+                    r#"{ "box": { "size": [0.6, 0.3, 0.9] } }"#
+                };
+                format!(
+                    r#"{{ "transform": {{ "translate": [{x}, {y}, {z}], "rotate": [0, {}, 20],
+                        "child": {child} }} }}"#,
+                    i * 37
+                )
+            })
+            .collect();
+        objects.push(r#"{ "plane": {} }"#.into());
+        Scene::parse(&format!(
+            r#"{{ "materials": [{{ "base_color": [1, 1, 1] }}],
+                "root": {{ "union": [{}] }} }}"#,
+            objects.join(", ")
+        ))
+        .unwrap()
+    }
+
+    /// Mirrors `bvh_sdf` in `bvh.glsl`.
+    fn bvh_sdf(bvh: &Bvh, nodes: &[BvhNode], scene: &Scene, p: Vec3, mut nearest: Hit) -> Hit {
+        let mut stack = vec![nodes.len() as u32 - 1];
+        while let Some(index) = stack.pop() {
+            let node = &nodes[index as usize];
+            let d = node.bounds.distance(p);
+            if d > nearest.dist.max(0.0) {
+                continue;
+            }
+            if node.right == LEAF {
+                let object = &bvh.objects[node.left as usize];
+                let h = object_sdf(object, Some(node.bounds), &scene.materials, p);
+                nearest = hit_union(nearest, h);
+                continue;
+            }
+            let d_left = nodes[node.left as usize].bounds.distance(p);
+            let d_right = nodes[node.right as usize].bounds.distance(p);
+            if d_left <= d_right {
+                stack.extend([node.right, node.left]);
+            } else {
+                stack.extend([node.left, node.right]);
+            }
+        }
+        nearest
+    }
+
+    fn depth(nodes: &[BvhNode], index: u32) -> usize {
+        let node = &nodes[index as usize];
+        if node.right == LEAF {
+            return 1;
+        }
+        1 + depth(nodes, node.left).max(depth(nodes, node.right))
+    }
+
+    #[test]
+    fn builds_a_balanced_tree_over_every_object() {
+        for count in [1, 2, 3, 9, 40, 100] {
+            let scene = scene(count);
+            let bvh = Bvh::new(&scene.root);
+            let nodes = build(&bvh.bounds);
+            assert_eq!(nodes.len(), 2 * count - 1);
+
+            let root = nodes.len() as u32 - 1;
+            assert!(depth(&nodes, root) <= count.next_power_of_two().ilog2() as usize + 1);
+            for (i, leaf) in nodes[..count].iter().enumerate() {
+                assert_eq!((leaf.left, leaf.right), (i as u32, LEAF));
+            }
+            for node in &nodes[count..] {
+                let children = [node.left, node.right].map(|c| nodes[c as usize].bounds);
+                assert_eq!(node.bounds, children[0].union(children[1]));
+            }
+        }
+    }
+
+    #[test]
+    fn builds_a_placeholder_without_objects() {
+        let nodes = build(&[]);
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].right, LEAF);
+    }
+
+    #[test]
+    fn traversal_matches_evaluating_every_object() {
+        for count in [1, 2, 9, 40] {
+            let scene = scene(count);
+            let bvh = Bvh::new(&scene.root);
+            let nodes = build(&bvh.bounds);
+            let unbounded = scene
+                .objects
+                .iter()
+                .filter(|(_, bounds)| bounds.is_none())
+                .map(|(object, _)| object);
+
+            let mut seed = 7;
+            for _ in 0..5000 {
+                let p = Vec3::new(10.0, 3.0, 10.0)
+                    * Vec3::new(random(&mut seed), random(&mut seed), random(&mut seed))
+                    - Vec3::new(1.0, 1.0, 1.0);
+                let nearest = unbounded
+                    .clone()
+                    .map(|object| object_sdf(object, None, &scene.materials, p))
+                    .reduce(hit_union)
+                    .unwrap();
+                let traversed = bvh_sdf(&bvh, &nodes, &scene, p, nearest);
+                let brute_force = sdf(
+                    &scene,
+                    Ray {
+                        origin: p,
+                        direction: Vec3::X,
+                    },
+                    0.0,
+                );
+                assert_eq!(traversed.dist, brute_force.dist, "{count} objects at {p}");
+            }
+        }
+    }
+}
@@ -1,68 +1,146 @@
-use glam::{ivec2, uvec2, vec2, vec3, Mat4, UVec2, Vec2, Vec3, Vec4, Vec4Swizzles};
+use glam::{vec2, vec3, Mat3, Quat, Vec2, Vec3};
 
-static DEGREES: f32 = std::f32::consts::PI / 180.;
-static UP: Vec3 = vec3(0., 1., 0.);
+use crate::controller::CameraController;
+use crate::reference::common::Ray;
 
-#[derive(Debug, Clone)]
+pub const DEGREES: f32 = std::f32::consts::PI / 180.;
+pub const UP: Vec3 = vec3(0., 1., 0.);
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Camera {
     pub resolution: Vec2,
     pub position: Vec3,
     pub uu: Vec3,
     pub vv: Vec3,
     pub ww: Vec3,
+    /// Vertical field of view in radians.
+    pub fov_y: f32,
+    /// Width over height of the image, updated by `resize`.
+    pub aspect: f32,
+    /// Distance along the view ray where primary rays start marching.
+    pub near: f32,
+    /// Distance along the view ray where primary rays give up and show the sky.
+    pub far: f32,
 }
 
 pub enum CameraEvent {
-    Resize { w: usize, h: usize },
-    RotateXY { delta: Vec2 },
+    Resize {
+        w: usize,
+        h: usize,
+    },
+    RotateXY {
+        delta: Vec2,
+    },
+    /// Forward.
     Up,
+    /// Backward.
     Down,
     Left,
     Right,
+    /// Vertical movement.
+    Ascend,
+    Descend,
+    RollLeft,
+    RollRight,
+    /// Scroll wheel lines, positive away from the user.
+    Zoom {
+        delta: f32,
+    },
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera::new_with_pos(vec3(-0.5, 3., 8.0), vec3(0., -1., -5.))
+    }
 }
 
 impl Camera {
-    pub fn new_with_pos(position: Vec3, forward: Vec3) -> Camera {
-        let ww = forward.normalize();
-        let uu = ww.cross(vec3(0., 1., 0.)).normalize();
-        let vv = uu.cross(ww).normalize();
+    /// The field of view the renderer always used, matching a focal length of 1.5.
+    pub const DEFAULT_FOV_Y: f32 = 0.643_501_1; // 2 * atan(1 / 3)
 
-        Camera {
+    pub fn new_with_pos(position: Vec3, forward: Vec3) -> Camera {
+        let mut camera = Camera {
             resolution: vec2(800., 600.),
             position,
-            uu,
-            vv,
-            ww,
-        }
+            uu: Vec3::X,
+            vv: Vec3::Y,
+            ww: Vec3::Z,
+            fov_y: Camera::DEFAULT_FOV_Y,
+            aspect: 800. / 600.,
+            near: 0.,
+            far: 100.,
+        };
+        camera.look(forward);
+        camera
     }
 
-    pub fn update(&mut self, events: &Vec<CameraEvent>, ts: f32) {
-        let speed = 7.;
-        let rotation_speed = 2.;
-        for event in events {
-            match event {
-                CameraEvent::Up => self.position += self.ww * speed * ts,
-                CameraEvent::Down => self.position -= self.ww * speed * ts,
-                CameraEvent::Left => self.position -= self.uu * speed * ts,
-                CameraEvent::Right => self.position += self.uu * speed * ts,
-                CameraEvent::Resize { w, h } => {
-                    self.resolution = vec2(*w as f32, *h as f32);
-                }
+    /// Points the camera along `forward`, keeping `UP` up.
+    pub fn look(&mut self, forward: Vec3) {
+        self.orient(forward, UP);
+    }
+
+    /// Points the camera along `forward`, with `up` as close to the top of the image as it can
+    /// be.
+    pub fn orient(&mut self, forward: Vec3, up: Vec3) {
+        self.ww = forward.normalize();
+        self.uu = self.ww.cross(up).normalize();
+        self.vv = self.uu.cross(self.ww).normalize();
+    }
+
+    pub fn resize(&mut self, w: usize, h: usize) {
+        self.resolution = vec2(w as f32, h as f32);
+        self.aspect = w as f32 / h.max(1) as f32;
+    }
+
+    /// The primary ray through `coord`, a framebuffer position as in `gl_FragCoord` with pixel
+    /// centers at half coordinates. Same as `camera_ray` in `ray_marching.glsl`.
+    pub fn ray_for_pixel(&self, coord: Vec2) -> Ray {
+        let mut ndc = coord / self.resolution * 2.0 - 1.0;
+        ndc.y = -ndc.y;
+        let tan_half = (self.fov_y * 0.5).tan();
+        Ray {
+            origin: self.position,
+            direction: (ndc.x * self.aspect * tan_half * self.uu
+                + ndc.y * tan_half * self.vv
+                + self.ww)
+                .normalize(),
+        }
+    }
 
-                CameraEvent::RotateXY { delta } => {
-                    let pitch_delta = -delta.y * rotation_speed;
-                    let yaw_delta = -delta.x * rotation_speed;
+    /// Blends from `self` at `t = 0` to `other` at `t = 1`, moving in a straight line and
+    /// rotating along the shortest arc. Projection parameters are taken from `other`.
+    pub fn interpolate(&self, other: &Camera, t: f32) -> Camera {
+        let mut camera = other.clone();
+        camera.position = self.position.lerp(other.position, t);
+        camera.set_rotation(self.rotation().slerp(other.rotation(), t));
+        camera
+    }
 
-                    let rotation = Mat4::from_rotation_x(pitch_delta as f32 * DEGREES)
-                        * Mat4::from_rotation_y(yaw_delta as f32 * DEGREES);
+    /// The orientation as a rotation from looking down -z with +y up.
+    pub fn rotation(&self) -> Quat {
+        // `uu`, `vv` and `ww` form a left-handed basis, flipping `ww` makes it a rotation.
+        Quat::from_mat3(&Mat3::from_cols(self.uu, self.vv, -self.ww))
+    }
 
-                    let fd = rotation * Vec4::new(self.ww.x, self.ww.y, self.ww.z, 1.);
+    pub fn set_rotation(&mut self, rotation: Quat) {
+        let basis = Mat3::from_quat(rotation.normalize());
+        self.uu = basis.x_axis;
+        self.vv = basis.y_axis;
+        self.ww = -basis.z_axis;
+    }
 
-                    self.ww = fd.xyz().normalize();
-                    self.uu = self.ww.cross(vec3(0., 1., 0.)).normalize();
-                    self.vv = self.uu.cross(self.ww).normalize();
-                }
+    /// Handles `Resize` and lets `controller` move the camera.
+    pub fn update(
+        &mut self,
+        controller: &mut dyn CameraController,
+        events: &[CameraEvent],
+        ts: f32,
+    ) {
+        for event in events {
+            if let CameraEvent::Resize { w, h } = event {
+                self.resize(*w, *h);
             }
         }
+        controller.update(self, events, ts);
     }
 }
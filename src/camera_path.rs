@@ -0,0 +1,315 @@
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs, io, path::Path};
+
+use crate::camera::Camera;
+
+/// Seconds between keyframes recorded from the app. The times can be edited in the saved file.
+pub const KEYFRAME_SPACING: f32 = 2.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Seconds from the start of the path.
+    pub time: f32,
+    pub position: Vec3,
+    pub uu: Vec3,
+    pub vv: Vec3,
+    pub ww: Vec3,
+}
+
+/// A camera flight through keyframes. Positions follow a Catmull-Rom spline and orientations
+/// are slerped, both timed by the keyframe times.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+}
+
+#[derive(Debug)]
+pub enum CameraPathError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for CameraPathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CameraPathError::Io(e) => write!(f, "cannot read camera path: {e}"),
+            CameraPathError::Parse(e) => write!(f, "cannot parse camera path: {e}"),
+            CameraPathError::Invalid(msg) => write!(f, "invalid camera path: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for CameraPathError {}
+
+impl CameraPath {
+    pub fn load(path: &Path) -> Result<CameraPath, CameraPathError> {
+        let text = fs::read_to_string(path).map_err(CameraPathError::Io)?;
+        let camera_path: CameraPath =
+            serde_json::from_str(&text).map_err(CameraPathError::Parse)?;
+        camera_path.validate().map_err(CameraPathError::Invalid)?;
+        Ok(camera_path)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for (i, k) in self.keyframes.iter().enumerate() {
+            // JSON numbers past the range of `f32` parse as infinity.
+            if !k.time.is_finite() || !k.position.is_finite() {
+                return Err(format!("keyframe {i} has a non-finite time or position"));
+            }
+            let finite_nonzero = |v: Vec3| v.is_finite() && v != Vec3::ZERO;
+            if !(finite_nonzero(k.uu) && finite_nonzero(k.vv) && finite_nonzero(k.ww)) {
+                return Err(format!("keyframe {i} needs nonzero direction vectors"));
+            }
+        }
+        if self.keyframes.windows(2).any(|k| k[1].time <= k[0].time) {
+            return Err("keyframe times must be increasing".into());
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// Appends the pose of `camera`, `KEYFRAME_SPACING` seconds after the last keyframe.
+    pub fn record(&mut self, camera: &Camera) {
+        let time = self
+            .keyframes
+            .last()
+            .map_or(0.0, |k| k.time + KEYFRAME_SPACING);
+        self.keyframes.push(Keyframe {
+            time,
+            position: camera.position,
+            uu: camera.uu,
+            vv: camera.vv,
+            ww: camera.ww,
+        });
+    }
+
+    /// Seconds from the first to the last keyframe.
+    pub fn duration(&self) -> f32 {
+        match (self.keyframes.first(), self.keyframes.last()) {
+            (Some(first), Some(last)) => last.time - first.time,
+            _ => 0.0,
+        }
+    }
+
+    /// Moves `camera` to where the path is `time` seconds after its first keyframe. Times past
+    /// either end hold the first or last pose. Does nothing for an empty path.
+    pub fn apply(&self, time: f32, camera: &mut Camera) {
+        let keyframes = &self.keyframes;
+        let Some(first) = keyframes.first() else {
+            return;
+        };
+        let time = first.time + time.clamp(0.0, self.duration());
+
+        // The segment from keyframe `i` to `i + 1` containing `time`.
+        let i = keyframes
+            .iter()
+            .rposition(|k| k.time <= time)
+            .unwrap_or(0)
+            .min(keyframes.len().saturating_sub(2));
+        let k1 = &keyframes[i];
+        let Some(k2) = keyframes.get(i + 1) else {
+            pose(k1, camera);
+            return;
+        };
+        let k0 = &keyframes[i.saturating_sub(1)];
+        let k3 = keyframes.get(i + 2).unwrap_or(k2);
+
+        let t = (time - k1.time) / (k2.time - k1.time);
+        camera.position = catmull_rom(k0, k1, k2, k3, t);
+
+        let (mut a, mut b) = (camera.clone(), camera.clone());
+        pose(k1, &mut a);
+        pose(k2, &mut b);
+        camera.set_rotation(a.rotation().slerp(b.rotation(), t));
+    }
+}
+
+fn pose(keyframe: &Keyframe, camera: &mut Camera) {
+    camera.position = keyframe.position;
+    camera.uu = keyframe.uu;
+    camera.vv = keyframe.vv;
+    camera.ww = keyframe.ww;
+}
+
+/// Hermite interpolation from `k1` to `k2` with Catmull-Rom tangents scaled to the keyframe
+/// times, so unevenly spaced keyframes do not overshoot.
+fn catmull_rom(k0: &Keyframe, k1: &Keyframe, k2: &Keyframe, k3: &Keyframe, t: f32) -> Vec3 {
+    let segment = k2.time - k1.time;
+    let tangent = |a: &Keyframe, b: &Keyframe| {
+        if b.time > a.time {
+            (b.position - a.position) / (b.time - a.time) * segment
+        } else {
+            Vec3::ZERO
+        }
+    };
+    let m1 = tangent(k0, k2);
+    let m2 = tangent(k1, k3);
+
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (2.0 * t3 - 3.0 * t2 + 1.0) * k1.position
+        + (t3 - 2.0 * t2 + t) * m1
+        + (-2.0 * t3 + 3.0 * t2) * k2.position
+        + (t3 - t2) * m2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, position: Vec3, forward: Vec3) -> Keyframe {
+        let camera = Camera::new_with_pos(position, forward);
+        Keyframe {
+            time,
+            position,
+            uu: camera.uu,
+            vv: camera.vv,
+            ww: camera.ww,
+        }
+    }
+
+    fn at(path: &CameraPath, time: f32) -> Camera {
+        let mut camera = Camera::new_with_pos(Vec3::ZERO, Vec3::Z);
+        path.apply(time, &mut camera);
+        camera
+    }
+
+    #[test]
+    fn passes_through_keyframes() {
+        let path = CameraPath {
+            keyframes: vec![
+                keyframe(1.0, Vec3::new(0.0, 1.0, 0.0), Vec3::Z),
+                keyframe(2.0, Vec3::new(3.0, 1.0, 2.0), Vec3::X),
+                keyframe(5.0, Vec3::new(-1.0, 4.0, 2.0), -Vec3::Z),
+                keyframe(6.0, Vec3::new(0.0, 0.0, 7.0), Vec3::Y + Vec3::Z),
+            ],
+        };
+        assert_eq!(path.duration(), 5.0);
+        for k in &path.keyframes {
+            let camera = at(&path, k.time - 1.0);
+            assert!(camera.position.abs_diff_eq(k.position, 1e-5));
+            assert!(camera.ww.abs_diff_eq(k.ww, 1e-5));
+            assert!(camera.uu.abs_diff_eq(k.uu, 1e-5));
+        }
+    }
+
+    #[test]
+    fn evenly_spaced_collinear_keyframes_move_linearly() {
+        let path = CameraPath {
+            keyframes: (0..4)
+                .map(|i| keyframe(i as f32, Vec3::X * i as f32 * 2.0, Vec3::Z))
+                .collect(),
+        };
+        for time in [0.25, 0.5, 1.3, 2.9] {
+            let camera = at(&path, time);
+            assert!(camera.position.abs_diff_eq(Vec3::X * time * 2.0, 1e-5));
+        }
+    }
+
+    #[test]
+    fn uneven_spacing_does_not_overshoot() {
+        let path = CameraPath {
+            keyframes: vec![
+                keyframe(0.0, Vec3::ZERO, Vec3::Z),
+                keyframe(0.1, Vec3::X, Vec3::Z),
+                keyframe(10.0, Vec3::X * 2.0, Vec3::Z),
+            ],
+        };
+        for i in 0..=100 {
+            let x = at(&path, i as f32 * 0.1).position.x;
+            assert!((0.0..=2.0 + 1e-5).contains(&x), "{x}");
+        }
+    }
+
+    #[test]
+    fn holds_the_end_poses() {
+        let first = keyframe(0.0, Vec3::ZERO, Vec3::Z);
+        let last = keyframe(2.0, Vec3::ONE, Vec3::X);
+        let path = CameraPath {
+            keyframes: vec![first.clone(), last.clone()],
+        };
+        assert!(at(&path, -1.0).position.abs_diff_eq(first.position, 1e-6));
+        assert!(at(&path, 5.0).position.abs_diff_eq(last.position, 1e-6));
+        assert!(at(&path, 5.0).ww.abs_diff_eq(last.ww, 1e-6));
+
+        let single = CameraPath {
+            keyframes: vec![last.clone()],
+        };
+        assert!(at(&single, 1.0).position.abs_diff_eq(last.position, 1e-6));
+    }
+
+    #[test]
+    fn slerps_the_orientation() {
+        let path = CameraPath {
+            keyframes: vec![
+                keyframe(0.0, Vec3::ZERO, Vec3::Z),
+                keyframe(1.0, Vec3::ZERO, Vec3::X),
+            ],
+        };
+        let camera = at(&path, 0.5);
+        assert!(camera.ww.abs_diff_eq((Vec3::X + Vec3::Z).normalize(), 1e-5));
+        assert!(camera.vv.abs_diff_eq(Vec3::Y, 1e-5));
+        assert!((camera.uu.dot(camera.ww)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn load_rejects_unordered_keyframes() {
+        let path = std::env::temp_dir().join("vk-ray-marching-unordered-path.json");
+        CameraPath {
+            keyframes: vec![
+                keyframe(1.0, Vec3::ZERO, Vec3::Z),
+                keyframe(1.0, Vec3::X, Vec3::Z),
+            ],
+        }
+        .save(&path)
+        .unwrap();
+        let result = CameraPath::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(CameraPathError::Invalid(_))));
+    }
+
+    #[test]
+    fn load_rejects_non_finite_and_degenerate_keyframes() {
+        let path = std::env::temp_dir().join("vk-ray-marching-degenerate-path.json");
+        let frame = |time: &str, ww: &str| {
+            format!(
+                r#"{{"time": {time}, "position": [0, 0, 0],
+                    "uu": [1, 0, 0], "vv": [0, 1, 0], "ww": {ww}}}"#
+            )
+        };
+        for keyframes in [
+            [frame("0", "[0, 0, 1]"), frame("1e39", "[0, 0, 1]")],
+            [frame("0", "[0, 0, 1]"), frame("1", "[0, 0, 0]")],
+        ] {
+            fs::write(
+                &path,
+                format!(r#"{{"keyframes": [{}]}}"#, keyframes.join(",")),
+            )
+            .unwrap();
+            let result = CameraPath::load(&path);
+            assert!(
+                matches!(result, Err(CameraPathError::Invalid(_))),
+                "{keyframes:?}"
+            );
+        }
+        fs::remove_file(&path).unwrap();
+
+        let mut nan = CameraPath {
+            keyframes: vec![
+                keyframe(0.0, Vec3::ZERO, Vec3::Z),
+                keyframe(1.0, Vec3::X, Vec3::Z),
+            ],
+        };
+        assert_eq!(nan.validate(), Ok(()));
+        nan.keyframes[0].time = f32::NAN;
+        assert!(nan.validate().is_err());
+        nan.keyframes[0].time = 0.0;
+        nan.keyframes[1].position.y = f32::NAN;
+        assert!(nan.validate().is_err());
+    }
+}
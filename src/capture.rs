@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use crate::camera::Camera;
+use crate::post::{Effect, PostSettings};
+use crate::quality::Quality;
+use crate::render::{RenderMode, RenderSettings};
+use crate::tonemap::{linear_to_srgb, srgb_to_linear};
+
+/// Averages each `factor` x `factor` block of a supersampled RGBA8 image into one pixel.
+/// `width` and `height` are the size of the result. Colors are averaged in linear space, the
+/// images being sRGB encoded.
+pub fn downsample(rgba: &[u8], width: u32, height: u32, factor: u32) -> Vec<u8> {
+    if factor == 1 {
+        return rgba.to_vec();
+    }
+    let (width, height, factor) = (width as usize, height as usize, factor as usize);
+    let src_row = width * factor * 4;
+    let samples = (factor * factor) as f32;
+
+    let mut out = vec![0u8; width * height * 4];
+    for (i, pixel) in out.chunks_mut(4).enumerate() {
+        let (x, y) = (i % width, i / width);
+        let mut sum = [0.0f32; 4];
+        for sy in 0..factor {
+            let row = (y * factor + sy) * src_row;
+            for sx in 0..factor {
+                let offset = row + (x * factor + sx) * 4;
+                for c in 0..3 {
+                    sum[c] += srgb_to_linear(rgba[offset + c] as f32 / 255.0);
+                }
+                sum[3] += rgba[offset + 3] as f32 / 255.0;
+            }
+        }
+        for c in 0..3 {
+            pixel[c] = (linear_to_srgb(sum[c] / samples) * 255.0).round() as u8;
+        }
+        pixel[3] = (sum[3] / samples * 255.0).round() as u8;
+    }
+    out
+}
+
+/// PNG text chunks describing how an image was rendered, including a command line that renders
+/// it again.
+pub fn metadata(
+    camera: &Camera,
+    time: f32,
+    scene: Option<&Path>,
+    size: [u32; 2],
+    supersample: u32,
+    settings: &RenderSettings,
+    post: &PostSettings,
+) -> Vec<(String, String)> {
+    let vec3 = |v: glam::Vec3| format!("{},{},{}", v.x, v.y, v.z);
+    let look_at = camera.position + camera.ww;
+    let fov = camera.fov_y.to_degrees();
+
+    let mut command = format!(
+        "vk-ray-marching --headless out.png --size {}x{} --camera-position {} --camera-look-at {} \
+         --camera-up {} --fov {fov} --time {time} --supersample {supersample} --tonemap {} \
+         --exposure {}",
+        size[0],
+        size[1],
+        vec3(camera.position),
+        vec3(look_at),
+        vec3(camera.vv),
+        settings.tonemap,
+        settings.exposure,
+    );
+    if settings.mode != RenderMode::Fast {
+        command += &format!(
+            " --mode {} --max-bounces {} --spp {}",
+            settings.mode, settings.max_bounces, settings.samples_per_pixel
+        );
+    }
+    if settings.quality != Quality::High {
+        command += &format!(" --quality {}", settings.quality);
+    }
+    if let Some(scene) = scene {
+        command += &format!(" --scene {}", scene.display());
+    }
+    let effects = post.enabled_list();
+    if !effects.is_empty() {
+        command += &format!(" --post {effects}");
+    }
+    if let (Some(lut), true) = (&post.lut, post.is_enabled(Effect::ColorGrade)) {
+        command += &format!(" --lut {}", lut.path.display());
+    }
+
+    let mut text = vec![
+        ("Software".to_owned(), "vk-ray-marching".to_owned()),
+        ("Camera position".to_owned(), vec3(camera.position)),
+        ("Camera direction".to_owned(), vec3(camera.ww)),
+        ("Camera up".to_owned(), vec3(camera.vv)),
+        ("Field of view".to_owned(), format!("{fov}")),
+        ("Time".to_owned(), format!("{time}")),
+        ("Supersample".to_owned(), format!("{supersample}")),
+        ("Mode".to_owned(), settings.mode.to_string()),
+        ("Tone mapping".to_owned(), settings.tonemap.to_string()),
+        ("Exposure".to_owned(), format!("{}", settings.exposure)),
+        ("Quality".to_owned(), settings.quality.to_string()),
+        ("Post effects".to_owned(), effects),
+    ];
+    if let Some(scene) = scene {
+        text.push(("Scene".to_owned(), scene.display().to_string()));
+    }
+    text.push(("Command".to_owned(), command));
+    text
+}
@@ -0,0 +1,538 @@
+use glam::Vec3;
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use vulkano::swapchain::PresentMode;
+
+use crate::camera::{Camera, UP};
+use crate::controller::ControllerKind;
+use crate::device::DeviceSelector;
+use crate::post::{Effect, Lut, LutError, PostSettings, Upscale};
+use crate::quality::Quality;
+use crate::render::{Backend, RenderMode, RenderSettings};
+use crate::resolution::{RenderScale, MIN_SCALE};
+use crate::tonemap::Tonemap;
+
+pub const USAGE: &str = "\
+Usage: vk-ray-marching [OPTIONS]
+       vk-ray-marching list-devices
+
+Options:
+      --scene <PATH>              Render a scene description file instead of the built-in scene
+      --size <WxH>                Window or output size [default: 800x600]
+      --fullscreen                Open a borderless fullscreen window
+      --present-mode <MODE>       fifo, mailbox or immediate [default: fifo]
+      --device <INDEX|NAME>       Device index or part of its name, see list-devices
+      --camera-position <X,Y,Z>   Initial camera position
+      --camera-look-at <X,Y,Z>    Point the initial camera looks at
+      --camera-up <X,Y,Z>         Up direction of the initial camera [default: 0,1,0]
+      --fov <DEGREES>             Vertical field of view [default: 36.87]
+      --camera <CONTROLLER>       free-fly, orbit or first-person [default: free-fly]
+      --tick-rate <HZ>            Simulation updates per second, 1 to 1000 [default: 60]
+      --camera-path <JSON>        Keyframe file to record to and play [default: camera-path.json]
+      --play                      Start playing the camera path
+      --benchmark                 Play the camera path once, print the frame rate and exit
+      --mode <MODE>               fast or path-tracing, M switches at runtime [default: fast]
+      --max-bounces <N>           Bounces per path when path tracing [default: 8]
+      --spp <N>                   Paths per pixel and frame when path tracing [default: 1]
+      --accumulate                Average frames while the view is still, C toggles it
+      --tonemap <OPERATOR>        none, reinhard, aces or agx, T cycles them [default: aces]
+      --exposure <EV>             Exposure in stops, - and = adjust it [default: 0]
+      --quality <PRESET>          Ray marching quality, low, medium, high or ultra, G cycles
+                                  them [default: high]
+      --post <EFFECTS>            Comma separated post effects: bloom, chromatic-aberration,
+                                  color-grade, vignette, grain and fxaa, F1-F6 toggle them
+      --lut <CUBE>                Color grade with a 3D LUT from a .cube file
+      --render-scale <FRACTION>   Render the scene at a fraction of the window size, from 0.25
+                                  to 1, comma and period adjust it [default: 1]
+      --target-frame-time <MS>    Adjust the render scale to hold this GPU frame time
+      --upscale <FILTER>          bilinear or edge-aware, U switches them [default: bilinear]
+      --backend <BACKEND>         Ray march in a fragment or a compute shader, fragment or
+                                  compute [default: fragment]
+      --watch-shaders             Recompile the GLSL sources when they change
+      --headless <PNG>            Render a single frame to a PNG file and exit
+      --cpu                       Use the CPU reference renderer for --headless
+      --time <SECONDS>            Animation time of the --headless frame [default: 0]
+      --supersample <N>           Render N x N samples per pixel for --headless and posters
+      --poster-scale <N>          Poster size as a multiple of the window size [default: 4]
+      --list-devices              Same as the list-devices command
+  -h, --help                      Print this help";
+
+pub enum Command {
+    Run(Box<Options>),
+    ListDevices,
+    Help,
+}
+
+pub struct Options {
+    pub scene: Option<PathBuf>,
+    pub size: [u32; 2],
+    pub fullscreen: bool,
+    pub present_mode: PresentMode,
+    pub device: Option<DeviceSelector>,
+    pub camera_position: Option<Vec3>,
+    pub camera_look_at: Option<Vec3>,
+    pub camera_up: Option<Vec3>,
+    pub fov: Option<f32>,
+    pub controller: ControllerKind,
+    pub tick_rate: f32,
+    pub camera_path: PathBuf,
+    pub play: bool,
+    pub benchmark: bool,
+    pub accumulate: bool,
+    pub render_settings: RenderSettings,
+    pub post: Vec<Effect>,
+    pub lut: Option<PathBuf>,
+    pub render_scale: RenderScale,
+    pub upscale: Upscale,
+    pub backend: Backend,
+    pub watch_shaders: bool,
+    pub headless: Option<PathBuf>,
+    pub cpu: bool,
+    pub time: f32,
+    pub supersample: u32,
+    pub poster_scale: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            scene: None,
+            size: [800, 600],
+            fullscreen: false,
+            present_mode: PresentMode::Fifo,
+            device: None,
+            camera_position: None,
+            camera_look_at: None,
+            camera_up: None,
+            fov: None,
+            controller: ControllerKind::FreeFly,
+            tick_rate: 60.0,
+            camera_path: PathBuf::from("camera-path.json"),
+            play: false,
+            benchmark: false,
+            accumulate: false,
+            render_settings: RenderSettings::default(),
+            post: vec![],
+            lut: None,
+            render_scale: RenderScale::default(),
+            upscale: Upscale::Bilinear,
+            backend: Backend::Fragment,
+            watch_shaders: false,
+            headless: None,
+            cpu: false,
+            time: 0.0,
+            supersample: 1,
+            poster_scale: 4,
+        }
+    }
+}
+
+impl Options {
+    /// The initial camera, the default one adjusted by the camera options.
+    pub fn camera(&self) -> Camera {
+        let default = Camera::default();
+        let position = self.camera_position.unwrap_or(default.position);
+        let mut camera = Camera::new_with_pos(position, default.ww);
+        let forward = match self.camera_look_at {
+            Some(target) => target - position,
+            None => default.ww,
+        };
+        camera.orient(forward, self.camera_up.unwrap_or(UP));
+        if let Some(fov) = self.fov {
+            camera.fov_y = fov.to_radians();
+        }
+        camera.resize(self.size[0] as usize, self.size[1] as usize);
+        camera
+    }
+
+    /// The post effects to start with, loading the LUT if there is one. A LUT enables color
+    /// grading.
+    pub fn post_settings(&self) -> Result<PostSettings, LutError> {
+        let mut settings = PostSettings {
+            enabled: self.post.clone(),
+            upscale: self.upscale,
+            ..Default::default()
+        };
+        if let Some(path) = &self.lut {
+            settings.lut = Some(Arc::new(Lut::load(path)?));
+            settings.set(Effect::ColorGrade, true);
+        }
+        Ok(settings)
+    }
+}
+
+/// Parses the arguments following the program name.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} expects a value"));
+        match arg.as_str() {
+            "list-devices" | "--list-devices" => return Ok(Command::ListDevices),
+            "-h" | "--help" => return Ok(Command::Help),
+            "--scene" => options.scene = Some(PathBuf::from(value()?)),
+            "--size" => options.size = parse_size(&value()?)?,
+            "--fullscreen" => options.fullscreen = true,
+            "--present-mode" => options.present_mode = parse_present_mode(&value()?)?,
+            "--device" => options.device = Some(parse_device(value()?)),
+            "--camera-position" => options.camera_position = Some(parse_vec3(&value()?)?),
+            "--camera-look-at" => options.camera_look_at = Some(parse_vec3(&value()?)?),
+            "--camera-up" => options.camera_up = Some(parse_vec3(&value()?)?),
+            "--fov" => options.fov = Some(parse_fov(&value()?)?),
+            "--tick-rate" => options.tick_rate = parse_tick_rate(&value()?)?,
+            "--camera" => options.controller = parse_controller(&value()?)?,
+            "--camera-path" => options.camera_path = PathBuf::from(value()?),
+            "--play" => options.play = true,
+            "--benchmark" => options.benchmark = true,
+            "--accumulate" => options.accumulate = true,
+            "--mode" => options.render_settings.mode = parse_mode(&value()?)?,
+            "--max-bounces" => options.render_settings.max_bounces = parse_factor(&arg, &value()?)?,
+            "--spp" => options.render_settings.samples_per_pixel = parse_factor(&arg, &value()?)?,
+            "--tonemap" => options.render_settings.tonemap = parse_tonemap(&value()?)?,
+            "--exposure" => options.render_settings.exposure = parse_number(&arg, &value()?)?,
+            "--quality" => options.render_settings.quality = parse_quality(&value()?)?,
+            "--post" => options.post = parse_effects(&value()?)?,
+            "--lut" => options.lut = Some(PathBuf::from(value()?)),
+            "--render-scale" => options.render_scale.scale = parse_render_scale(&value()?)?,
+            "--target-frame-time" => {
+                options.render_scale.target_frame_time = Some(parse_frame_time(&value()?)?)
+            }
+            "--upscale" => options.upscale = parse_upscale(&value()?)?,
+            "--backend" => options.backend = parse_backend(&value()?)?,
+            "--watch-shaders" => options.watch_shaders = true,
+            "--headless" => options.headless = Some(PathBuf::from(value()?)),
+            "--cpu" => options.cpu = true,
+            "--time" => options.time = parse_number(&arg, &value()?)?,
+            "--supersample" => options.supersample = parse_factor(&arg, &value()?)?,
+            "--poster-scale" => options.poster_scale = parse_factor(&arg, &value()?)?,
+            _ => return Err(format!("unexpected argument '{arg}', see --help")),
+        }
+    }
+
+    if options.cpu && options.headless.is_none() {
+        return Err("--cpu can only be used with --headless".into());
+    }
+    if options.cpu && options.render_settings.mode != RenderMode::Fast {
+        return Err("--cpu only supports the fast mode".into());
+    }
+    if options.cpu && options.render_settings.quality != Quality::High {
+        return Err("--cpu only supports the high quality".into());
+    }
+    if options.cpu && (!options.post.is_empty() || options.lut.is_some()) {
+        return Err("--cpu does not apply post effects".into());
+    }
+    if options.post.contains(&Effect::ColorGrade) && options.lut.is_none() {
+        return Err("color-grade needs a --lut".into());
+    }
+    if options.headless.is_some()
+        && (options.render_scale != RenderScale::default() || options.upscale != Upscale::Bilinear)
+    {
+        return Err("--headless renders at full resolution, without a render scale".into());
+    }
+    if (options.play || options.benchmark) && options.headless.is_some() {
+        return Err("--play and --benchmark cannot be used with --headless".into());
+    }
+    if options.accumulate && options.headless.is_some() {
+        return Err("--accumulate cannot be used with --headless, see --supersample".into());
+    }
+    if options.camera_position == options.camera_look_at && options.camera_position.is_some() {
+        return Err("the camera cannot look at its own position".into());
+    }
+    // `Options::camera` orients the camera against `UP` unless another up is given.
+    let up = options.camera_up.unwrap_or(UP);
+    let position = options
+        .camera_position
+        .unwrap_or(Camera::default().position);
+    let forward = options
+        .camera_look_at
+        .map_or(Camera::default().ww, |t| t - position);
+    if up.cross(forward).length_squared() < 1e-12 {
+        return Err("the camera up direction cannot be parallel to the view".into());
+    }
+    Ok(Command::Run(Box::new(options)))
+}
+
+fn parse_size(s: &str) -> Result<[u32; 2], String> {
+    let invalid = || format!("invalid size '{s}', expected WIDTHxHEIGHT");
+    let (w, h) = s.split_once('x').ok_or_else(invalid)?;
+    let size = [
+        w.parse().map_err(|_| invalid())?,
+        h.parse().map_err(|_| invalid())?,
+    ];
+    if size.contains(&0) {
+        return Err(invalid());
+    }
+    Ok(size)
+}
+
+fn parse_number(arg: &str, s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(n) if n.is_finite() => Ok(n),
+        _ => Err(format!(
+            "invalid value '{s}' for {arg}, expected a finite number"
+        )),
+    }
+}
+
+fn parse_factor(arg: &str, s: &str) -> Result<u32, String> {
+    match s.parse() {
+        Ok(n) if (1..=16).contains(&n) => Ok(n),
+        _ => Err(format!(
+            "invalid value '{s}' for {arg}, expected a whole number from 1 to 16"
+        )),
+    }
+}
+
+fn parse_present_mode(s: &str) -> Result<PresentMode, String> {
+    match s {
+        "fifo" => Ok(PresentMode::Fifo),
+        "mailbox" => Ok(PresentMode::Mailbox),
+        "immediate" => Ok(PresentMode::Immediate),
+        _ => Err(format!(
+            "invalid present mode '{s}', expected fifo, mailbox or immediate"
+        )),
+    }
+}
+
+fn parse_fov(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(fov) if fov > 0.0 && fov < 180.0 => Ok(fov),
+        _ => Err(format!(
+            "invalid field of view '{s}', expected degrees between 0 and 180"
+        )),
+    }
+}
+
+fn parse_tick_rate(s: &str) -> Result<f32, String> {
+    match parse_number("--tick-rate", s) {
+        Ok(rate) if (1.0..=1000.0).contains(&rate) => Ok(rate),
+        _ => Err(format!(
+            "invalid tick rate '{s}', expected 1 to 1000 updates per second"
+        )),
+    }
+}
+
+fn parse_mode(s: &str) -> Result<RenderMode, String> {
+    match s {
+        "fast" => Ok(RenderMode::Fast),
+        "path-tracing" => Ok(RenderMode::PathTracing),
+        _ => Err(format!("invalid mode '{s}', expected fast or path-tracing")),
+    }
+}
+
+fn parse_backend(s: &str) -> Result<Backend, String> {
+    match s {
+        "fragment" => Ok(Backend::Fragment),
+        "compute" => Ok(Backend::Compute),
+        _ => Err(format!(
+            "invalid backend '{s}', expected fragment or compute"
+        )),
+    }
+}
+
+fn parse_tonemap(s: &str) -> Result<Tonemap, String> {
+    match s {
+        "none" => Ok(Tonemap::None),
+        "reinhard" => Ok(Tonemap::Reinhard),
+        "aces" => Ok(Tonemap::Aces),
+        "agx" => Ok(Tonemap::AgX),
+        _ => Err(format!(
+            "invalid tone mapping '{s}', expected none, reinhard, aces or agx"
+        )),
+    }
+}
+
+fn parse_quality(s: &str) -> Result<Quality, String> {
+    match s {
+        "low" => Ok(Quality::Low),
+        "medium" => Ok(Quality::Medium),
+        "high" => Ok(Quality::High),
+        "ultra" => Ok(Quality::Ultra),
+        _ => Err(format!(
+            "invalid quality '{s}', expected low, medium, high or ultra"
+        )),
+    }
+}
+
+fn parse_render_scale(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(scale) if (MIN_SCALE..=1.0).contains(&scale) => Ok(scale),
+        _ => Err(format!(
+            "invalid render scale '{s}', expected a fraction from {MIN_SCALE} to 1"
+        )),
+    }
+}
+
+fn parse_frame_time(s: &str) -> Result<Duration, String> {
+    match s.parse::<f32>() {
+        Ok(ms) if ms > 0.0 && ms.is_finite() => Ok(Duration::from_secs_f32(ms / 1000.0)),
+        _ => Err(format!(
+            "invalid frame time '{s}', expected a positive number of milliseconds"
+        )),
+    }
+}
+
+fn parse_upscale(s: &str) -> Result<Upscale, String> {
+    match s {
+        "bilinear" => Ok(Upscale::Bilinear),
+        "edge-aware" => Ok(Upscale::EdgeAware),
+        _ => Err(format!(
+            "invalid upscale filter '{s}', expected bilinear or edge-aware"
+        )),
+    }
+}
+
+fn parse_effects(s: &str) -> Result<Vec<Effect>, String> {
+    s.split(',')
+        .map(|name| {
+            Effect::ALL
+                .into_iter()
+                .find(|e| e.to_string() == name.trim())
+                .ok_or_else(|| {
+                    format!(
+                        "invalid post effect '{name}', expected bloom, chromatic-aberration, \
+                         color-grade, vignette, grain or fxaa"
+                    )
+                })
+        })
+        .collect()
+}
+
+fn parse_controller(s: &str) -> Result<ControllerKind, String> {
+    match s {
+        "free-fly" => Ok(ControllerKind::FreeFly),
+        "orbit" => Ok(ControllerKind::Orbit),
+        "first-person" => Ok(ControllerKind::FirstPerson),
+        _ => Err(format!(
+            "invalid camera '{s}', expected free-fly, orbit or first-person"
+        )),
+    }
+}
+
+fn parse_device(s: String) -> DeviceSelector {
+    match s.parse() {
+        Ok(index) => DeviceSelector::Index(index),
+        Err(_) => DeviceSelector::Name(s),
+    }
+}
+
+fn parse_vec3(s: &str) -> Result<Vec3, String> {
+    let invalid = || format!("invalid vector '{s}', expected X,Y,Z");
+    let values = s
+        .split(',')
+        .map(|c| match c.trim().parse::<f32>() {
+            Ok(v) if v.is_finite() => Ok(v),
+            _ => Err(invalid()),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    match values[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(args: &str) -> Result<Options, String> {
+        match parse(args.split_whitespace().map(String::from))? {
+            Command::Run(options) => Ok(*options),
+            _ => Err("expected options".into()),
+        }
+    }
+
+    fn error(args: &str) -> String {
+        run(args).err().expect("arguments should be rejected")
+    }
+
+    #[test]
+    fn defaults_without_arguments() {
+        let options = run("").unwrap();
+        assert_eq!(options.size, [800, 600]);
+        assert_eq!(options.render_settings.quality, Quality::High);
+        assert!(options.headless.is_none());
+    }
+
+    #[test]
+    fn commands() {
+        assert!(matches!(
+            parse(["list-devices".into()]),
+            Ok(Command::ListDevices)
+        ));
+        assert!(matches!(parse(["--help".into()]), Ok(Command::Help)));
+    }
+
+    #[test]
+    fn parses_values() {
+        let options = run(
+            "--size 1280x720 --exposure -1.5 --time 2 --fov 60 --quality low \
+             --post bloom,fxaa --camera-position 1,2,3 --mode path-tracing --spp 4",
+        )
+        .unwrap();
+        assert_eq!(options.size, [1280, 720]);
+        assert_eq!(options.render_settings.exposure, -1.5);
+        assert_eq!(options.time, 2.0);
+        assert_eq!(options.fov, Some(60.0));
+        assert_eq!(options.render_settings.quality, Quality::Low);
+        assert_eq!(options.post, [Effect::Bloom, Effect::Fxaa]);
+        assert_eq!(options.camera_position, Some(Vec3::new(1.0, 2.0, 3.0)));
+        assert_eq!(options.render_settings.mode, RenderMode::PathTracing);
+        assert_eq!(options.render_settings.samples_per_pixel, 4);
+    }
+
+    #[test]
+    fn rejects_malformed_arguments() {
+        assert_eq!(error("--size"), "--size expects a value");
+        assert_eq!(
+            error("--bogus"),
+            "unexpected argument '--bogus', see --help"
+        );
+        assert!(error("--size 0x600").starts_with("invalid size"));
+        assert!(error("--size 800").starts_with("invalid size"));
+        assert!(error("--spp 17").starts_with("invalid value '17' for --spp"));
+        assert!(error("--camera-position 1,2").starts_with("invalid vector"));
+        assert!(error("--quality best").starts_with("invalid quality"));
+        assert!(error("--post bloom,blur").starts_with("invalid post effect 'blur'"));
+    }
+
+    #[test]
+    fn rejects_non_finite_numbers() {
+        for args in ["--exposure NaN", "--time inf", "--exposure -inf"] {
+            assert!(error(args).ends_with("expected a finite number"), "{args}");
+        }
+        assert!(error("--fov NaN").starts_with("invalid field of view"));
+        assert!(error("--fov inf").starts_with("invalid field of view"));
+        assert!(error("--camera-look-at 0,nan,0").starts_with("invalid vector"));
+        for rate in ["inf", "NaN", "1e30", "0.5"] {
+            let args = format!("--tick-rate {rate}");
+            assert!(error(&args).starts_with("invalid tick rate"), "{args}");
+        }
+    }
+
+    #[test]
+    fn rejects_conflicting_options() {
+        assert_eq!(error("--cpu"), "--cpu can only be used with --headless");
+        assert_eq!(
+            error("--headless a.png --cpu --quality low"),
+            "--cpu only supports the high quality"
+        );
+        assert_eq!(error("--post color-grade"), "color-grade needs a --lut");
+        assert_eq!(
+            error("--camera-position 1,1,1 --camera-look-at 1,1,1"),
+            "the camera cannot look at its own position"
+        );
+        assert_eq!(
+            error("--camera-look-at 0,5,0 --camera-position 0,0,0 --camera-up 0,1,0"),
+            "the camera up direction cannot be parallel to the view"
+        );
+        assert_eq!(
+            error("--camera-position 0,5,0 --camera-look-at 0,0,0"),
+            "the camera up direction cannot be parallel to the view"
+        );
+        assert_eq!(
+            error("--camera-up 0,0,0"),
+            "the camera up direction cannot be parallel to the view"
+        );
+    }
+}
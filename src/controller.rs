@@ -0,0 +1,302 @@
+use glam::{vec3, Quat, Vec3};
+use std::fmt;
+
+use crate::camera::{Camera, CameraEvent, DEGREES, UP};
+
+const SPEED: f32 = 7.;
+/// Degrees per unit of `CameraEvent::RotateXY` delta.
+const ROTATION_SPEED: f32 = 2.;
+/// Degrees per second for `CameraEvent::RollLeft`/`RollRight`.
+const ROLL_SPEED: f32 = 60.;
+/// Keeps pitch away from the poles, where the basis built from `UP` degenerates.
+const MAX_PITCH: f32 = 89. * DEGREES;
+
+/// Turns input events into camera movement.
+pub trait CameraController {
+    fn kind(&self) -> ControllerKind;
+
+    /// Applies `events`, `ts` seconds after the previous update.
+    fn update(&mut self, camera: &mut Camera, events: &[CameraEvent], ts: f32);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControllerKind {
+    FreeFly,
+    Orbit,
+    FirstPerson,
+}
+
+impl ControllerKind {
+    /// The controller after this one when cycling through them at runtime.
+    pub fn next(self) -> Self {
+        match self {
+            ControllerKind::FreeFly => ControllerKind::Orbit,
+            ControllerKind::Orbit => ControllerKind::FirstPerson,
+            ControllerKind::FirstPerson => ControllerKind::FreeFly,
+        }
+    }
+
+    /// Creates a controller that starts from the current state of `camera`.
+    pub fn create(self, camera: &mut Camera) -> Box<dyn CameraController> {
+        match self {
+            ControllerKind::FreeFly => Box::new(FreeFly),
+            ControllerKind::Orbit => Box::new(Orbit::new(camera)),
+            ControllerKind::FirstPerson => Box::new(FirstPerson::new(camera)),
+        }
+    }
+}
+
+impl fmt::Display for ControllerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControllerKind::FreeFly => write!(f, "free-fly"),
+            ControllerKind::Orbit => write!(f, "orbit"),
+            ControllerKind::FirstPerson => write!(f, "first-person"),
+        }
+    }
+}
+
+/// Moves along and rotates around the camera's own axes, so it can also fly upside down.
+pub struct FreeFly;
+
+impl CameraController for FreeFly {
+    fn kind(&self) -> ControllerKind {
+        ControllerKind::FreeFly
+    }
+
+    fn update(&mut self, camera: &mut Camera, events: &[CameraEvent], ts: f32) {
+        for event in events {
+            match event {
+                CameraEvent::Up => camera.position += camera.ww * SPEED * ts,
+                CameraEvent::Down => camera.position -= camera.ww * SPEED * ts,
+                CameraEvent::Left => camera.position -= camera.uu * SPEED * ts,
+                CameraEvent::Right => camera.position += camera.uu * SPEED * ts,
+                CameraEvent::Ascend => camera.position += camera.vv * SPEED * ts,
+                CameraEvent::Descend => camera.position -= camera.vv * SPEED * ts,
+                CameraEvent::RotateXY { delta } => {
+                    let yaw = Quat::from_axis_angle(camera.vv, -delta.x * ROTATION_SPEED * DEGREES);
+                    let pitch =
+                        Quat::from_axis_angle(camera.uu, -delta.y * ROTATION_SPEED * DEGREES);
+                    rotate(camera, pitch * yaw);
+                }
+                CameraEvent::RollLeft => rotate(
+                    camera,
+                    Quat::from_axis_angle(camera.ww, -ROLL_SPEED * DEGREES * ts),
+                ),
+                CameraEvent::RollRight => rotate(
+                    camera,
+                    Quat::from_axis_angle(camera.ww, ROLL_SPEED * DEGREES * ts),
+                ),
+                CameraEvent::Resize { .. } | CameraEvent::Zoom { .. } => {}
+            }
+        }
+    }
+}
+
+/// Rotates the camera basis, re-orthonormalizing it so rounding errors do not accumulate.
+fn rotate(camera: &mut Camera, rotation: Quat) {
+    let ww = (rotation * camera.ww).normalize();
+    let vv = rotation * camera.vv;
+    camera.uu = ww.cross(vv).normalize();
+    camera.vv = camera.uu.cross(ww);
+    camera.ww = ww;
+}
+
+/// Circles around a target point, zooming with the scroll wheel.
+pub struct Orbit {
+    target: Vec3,
+    distance: f32,
+    yaw: f32,
+    pitch: f32,
+}
+
+impl Orbit {
+    /// Orbits around the point the camera looks at on the ground, or a point in front of it
+    /// when it looks above the horizon.
+    pub fn new(camera: &mut Camera) -> Self {
+        let distance = if camera.ww.y < -0.1 {
+            (camera.position.y / -camera.ww.y).clamp(1., 50.)
+        } else {
+            8.
+        };
+        let target = camera.position + camera.ww * distance;
+        let (yaw, pitch) = yaw_pitch(-camera.ww);
+        let orbit = Orbit {
+            target,
+            distance,
+            yaw,
+            pitch: pitch.clamp(-MAX_PITCH, MAX_PITCH),
+        };
+        orbit.apply(camera);
+        orbit
+    }
+
+    fn apply(&self, camera: &mut Camera) {
+        camera.position = self.target + direction(self.yaw, self.pitch) * self.distance;
+        camera.look(self.target - camera.position);
+    }
+}
+
+impl CameraController for Orbit {
+    fn kind(&self) -> ControllerKind {
+        ControllerKind::Orbit
+    }
+
+    fn update(&mut self, camera: &mut Camera, events: &[CameraEvent], ts: f32) {
+        let orbit_speed = 90. * DEGREES;
+        for event in events {
+            match event {
+                CameraEvent::Up => self.distance -= SPEED * ts,
+                CameraEvent::Down => self.distance += SPEED * ts,
+                CameraEvent::Left => self.yaw += orbit_speed * ts,
+                CameraEvent::Right => self.yaw -= orbit_speed * ts,
+                CameraEvent::Ascend => self.target.y += SPEED * ts,
+                CameraEvent::Descend => self.target.y -= SPEED * ts,
+                CameraEvent::RotateXY { delta } => {
+                    self.yaw -= delta.x * ROTATION_SPEED * DEGREES;
+                    self.pitch += delta.y * ROTATION_SPEED * DEGREES;
+                }
+                CameraEvent::Zoom { delta } => self.distance *= 0.9f32.powf(*delta),
+                CameraEvent::Resize { .. } | CameraEvent::RollLeft | CameraEvent::RollRight => {}
+            }
+        }
+        self.distance = self.distance.clamp(0.5, 100.);
+        self.pitch = self.pitch.clamp(-MAX_PITCH, MAX_PITCH);
+        self.apply(camera);
+    }
+}
+
+/// Walks on a horizontal plane at a fixed eye height, looking around with a fixed up vector.
+pub struct FirstPerson {
+    eye_height: f32,
+    yaw: f32,
+    pitch: f32,
+}
+
+impl FirstPerson {
+    pub fn new(camera: &mut Camera) -> Self {
+        let (yaw, pitch) = yaw_pitch(camera.ww);
+        let first_person = FirstPerson {
+            eye_height: camera.position.y,
+            yaw,
+            pitch: pitch.clamp(-MAX_PITCH, MAX_PITCH),
+        };
+        first_person.apply(camera);
+        first_person
+    }
+
+    fn apply(&self, camera: &mut Camera) {
+        camera.position.y = self.eye_height;
+        camera.look(direction(self.yaw, self.pitch));
+    }
+}
+
+impl CameraController for FirstPerson {
+    fn kind(&self) -> ControllerKind {
+        ControllerKind::FirstPerson
+    }
+
+    fn update(&mut self, camera: &mut Camera, events: &[CameraEvent], ts: f32) {
+        for event in events {
+            let forward = direction(self.yaw, 0.);
+            let right = forward.cross(UP);
+            match event {
+                CameraEvent::Up => camera.position += forward * SPEED * ts,
+                CameraEvent::Down => camera.position -= forward * SPEED * ts,
+                CameraEvent::Left => camera.position -= right * SPEED * ts,
+                CameraEvent::Right => camera.position += right * SPEED * ts,
+                CameraEvent::RotateXY { delta } => {
+                    self.yaw -= delta.x * ROTATION_SPEED * DEGREES;
+                    self.pitch -= delta.y * ROTATION_SPEED * DEGREES;
+                    self.pitch = self.pitch.clamp(-MAX_PITCH, MAX_PITCH);
+                }
+                _ => {}
+            }
+        }
+        self.apply(camera);
+    }
+}
+
+/// Unit vector for a yaw around `UP`, zero looking down +z, and a pitch above the horizon.
+fn direction(yaw: f32, pitch: f32) -> Vec3 {
+    vec3(
+        pitch.cos() * yaw.sin(),
+        pitch.sin(),
+        pitch.cos() * yaw.cos(),
+    )
+}
+
+/// Inverse of `direction`.
+fn yaw_pitch(direction: Vec3) -> (f32, f32) {
+    let d = direction.normalize();
+    (d.x.atan2(d.z), d.y.clamp(-1., 1.).asin())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::vec2;
+
+    fn camera() -> Camera {
+        Camera::new_with_pos(vec3(-0.5, 3.0, 8.0), vec3(0.0, -1.0, -5.0))
+    }
+
+    #[test]
+    fn orbit_keeps_its_distance_to_the_target() {
+        let mut camera = camera();
+        let mut orbit = Orbit::new(&mut camera);
+        // The camera looked down at the ground, which becomes the target.
+        assert!(orbit.target.y.abs() < 1e-4);
+        for events in [
+            vec![CameraEvent::Left],
+            vec![CameraEvent::Right, CameraEvent::Right],
+            vec![CameraEvent::RotateXY {
+                delta: vec2(10.0, -3.0),
+            }],
+        ] {
+            orbit.update(&mut camera, &events, 0.1);
+            assert!((camera.position.distance(orbit.target) - orbit.distance).abs() < 1e-4);
+            let to_target = (orbit.target - camera.position).normalize();
+            assert!(camera.ww.abs_diff_eq(to_target, 1e-5));
+        }
+        orbit.update(&mut camera, &[CameraEvent::Zoom { delta: 1000.0 }], 0.1);
+        assert_eq!(orbit.distance, 0.5);
+        assert!((camera.position.distance(orbit.target) - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn first_person_clamps_the_pitch() {
+        let mut camera = camera();
+        let mut first_person = FirstPerson::new(&mut camera);
+        for (delta, pitch) in [(-1000.0, MAX_PITCH), (1000.0, -MAX_PITCH)] {
+            let events = [CameraEvent::RotateXY {
+                delta: vec2(0.0, delta),
+            }];
+            first_person.update(&mut camera, &events, 0.1);
+            assert_eq!(first_person.pitch, pitch);
+            assert!((camera.ww.y - pitch.sin()).abs() < 1e-5);
+            assert!(camera.uu.is_finite() && camera.vv.y > 0.0);
+        }
+        // Walking stays at eye height, whichever way the camera looks.
+        first_person.update(&mut camera, &[CameraEvent::Up], 1.0);
+        assert_eq!(camera.position.y, 3.0);
+    }
+
+    #[test]
+    fn switching_keeps_the_view() {
+        let start = camera();
+        let mut kind = ControllerKind::FreeFly;
+        for _ in 0..3 {
+            let mut camera = start.clone();
+            let controller = kind.create(&mut camera);
+            assert_eq!(controller.kind(), kind);
+            // Orbit can only move the camera along its line of sight, when it clamps the distance.
+            let offset = camera.position - start.position;
+            assert!(offset.cross(start.ww).length() < 1e-4, "{kind}: {offset}");
+            assert!(camera.ww.abs_diff_eq(start.ww, 1e-5), "{kind}");
+            assert!(camera.uu.abs_diff_eq(start.uu, 1e-5), "{kind}");
+            kind = kind.next();
+        }
+        assert_eq!(kind, ControllerKind::FreeFly);
+    }
+}
@@ -0,0 +1,151 @@
+use std::{error::Error, fmt, sync::Arc};
+use vulkano::{
+    device::{
+        physical::{PhysicalDevice, PhysicalDeviceType},
+        DeviceFeatures,
+    },
+    instance::{Instance, InstanceCreateFlags, InstanceCreateInfo},
+    VulkanLibrary,
+};
+
+/// A device picked on the command line, either by its index in `list-devices` or by a
+/// case-insensitive substring of its name.
+#[derive(Debug, Clone)]
+pub enum DeviceSelector {
+    Index(usize),
+    Name(String),
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceSelector::Index(i) => write!(f, "device {i}"),
+            DeviceSelector::Name(name) => write!(f, "device \"{name}\""),
+        }
+    }
+}
+
+/// Features every renderer enables. The fragment shader writes the accumulation image, which
+/// needs `fragment_stores_and_atomics`.
+pub fn required_features() -> DeviceFeatures {
+    DeviceFeatures {
+        fragment_stores_and_atomics: true,
+        ..DeviceFeatures::empty()
+    }
+}
+
+// We assign a lower score to device types that are likely to be faster/better.
+pub fn device_type_score(device_type: PhysicalDeviceType) -> u32 {
+    match device_type {
+        PhysicalDeviceType::DiscreteGpu => 0,
+        PhysicalDeviceType::IntegratedGpu => 1,
+        PhysicalDeviceType::VirtualGpu => 2,
+        PhysicalDeviceType::Cpu => 3,
+        PhysicalDeviceType::Other => 4,
+        _ => 5,
+    }
+}
+
+/// Returns the device to render with and its queue family. `queue_family` picks the queue
+/// family to use on a device, or returns `None` if the device is unsuitable. Without a selector
+/// the best suitable device is chosen by type.
+pub fn select_physical_device(
+    instance: &Arc<Instance>,
+    selector: Option<&DeviceSelector>,
+    queue_family: impl Fn(&Arc<PhysicalDevice>) -> Option<u32>,
+) -> Result<(Arc<PhysicalDevice>, u32), String> {
+    let devices: Vec<_> = instance
+        .enumerate_physical_devices()
+        .map_err(|e| format!("cannot enumerate devices: {e}"))?
+        .collect();
+
+    let Some(selector) = selector else {
+        return devices
+            .into_iter()
+            .filter_map(|p| queue_family(&p).map(|i| (p, i)))
+            .min_by_key(|(p, _)| device_type_score(p.properties().device_type))
+            .ok_or_else(|| "no suitable physical device found".to_owned());
+    };
+
+    let physical_device = match selector {
+        DeviceSelector::Index(i) => devices.get(*i),
+        DeviceSelector::Name(name) => devices.iter().find(|p| {
+            p.properties()
+                .device_name
+                .to_lowercase()
+                .contains(&name.to_lowercase())
+        }),
+    }
+    .ok_or_else(|| format!("{selector} not found, see --list-devices"))?;
+
+    let queue_family_index = queue_family(physical_device).ok_or_else(|| {
+        format!(
+            "{} cannot be used for rendering",
+            physical_device.properties().device_name
+        )
+    })?;
+
+    Ok((physical_device.clone(), queue_family_index))
+}
+
+/// Prints every physical device with its queue families and the limits that matter here.
+pub fn list_devices() -> Result<(), Box<dyn Error>> {
+    let library = VulkanLibrary::new()?;
+    let instance = Instance::new(
+        library,
+        InstanceCreateInfo {
+            flags: InstanceCreateFlags::ENUMERATE_PORTABILITY,
+            ..Default::default()
+        },
+    )?;
+
+    for (i, p) in instance.enumerate_physical_devices()?.enumerate() {
+        let properties = p.properties();
+        println!(
+            "{i}: {} ({:?}, Vulkan {})",
+            properties.device_name, properties.device_type, properties.api_version
+        );
+        if let Some(driver) = &properties.driver_name {
+            println!(
+                "   driver: {driver} {}",
+                properties.driver_info.as_deref().unwrap_or_default()
+            );
+        }
+
+        println!("   queue families:");
+        for (j, q) in p.queue_family_properties().iter().enumerate() {
+            println!("     {j}: {} x {:?}", q.queue_count, q.queue_flags);
+        }
+
+        println!("   limits:");
+        println!(
+            "     max image dimension 2D:        {}",
+            properties.max_image_dimension2_d
+        );
+        println!(
+            "     max push constants size:       {}",
+            properties.max_push_constants_size
+        );
+        println!(
+            "     max uniform buffer range:      {}",
+            properties.max_uniform_buffer_range
+        );
+        println!(
+            "     max storage buffer range:      {}",
+            properties.max_storage_buffer_range
+        );
+        println!(
+            "     max bound descriptor sets:     {}",
+            properties.max_bound_descriptor_sets
+        );
+        println!(
+            "     max compute invocations:       {}",
+            properties.max_compute_work_group_invocations
+        );
+        println!(
+            "     timestamp period:              {} ns",
+            properties.timestamp_period
+        );
+    }
+    Ok(())
+}
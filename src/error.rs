@@ -0,0 +1,77 @@
+use std::{error::Error, fmt};
+use vulkano::{swapchain::FromWindowError, LoadingError, Validated, ValidationError, VulkanError};
+
+#[derive(Debug)]
+pub enum AppError {
+    Library(LoadingError),
+    Instance(Validated<VulkanError>),
+    NoDevice(String),
+    Device(Validated<VulkanError>),
+    Window(String),
+    Surface(FromWindowError),
+    Swapchain(Validated<VulkanError>),
+    Shader(String),
+    Pipeline(String),
+    /// The device stopped responding, everything created from it has to be recreated.
+    DeviceLost,
+    /// The surface is no longer usable, the swapchain has to be recreated on a new one.
+    SurfaceLost,
+    Render(String),
+}
+
+impl AppError {
+    /// Maps device and surface loss to their own variants and anything else with `other`.
+    pub fn vulkan(
+        e: Validated<VulkanError>,
+        other: fn(Validated<VulkanError>) -> AppError,
+    ) -> Self {
+        match e {
+            Validated::Error(VulkanError::DeviceLost) => AppError::DeviceLost,
+            Validated::Error(VulkanError::SurfaceLost) => AppError::SurfaceLost,
+            e => other(e),
+        }
+    }
+}
+
+impl From<Validated<VulkanError>> for AppError {
+    fn from(e: Validated<VulkanError>) -> Self {
+        AppError::vulkan(e, |e| AppError::Render(e.to_string()))
+    }
+}
+
+/// Recording a command that is invalid for its state, which is a bug.
+impl From<Box<ValidationError>> for AppError {
+    fn from(e: Box<ValidationError>) -> Self {
+        AppError::Render(e.to_string())
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Library(e) => write!(f, "cannot load the Vulkan library: {e}"),
+            AppError::Instance(e) => write!(f, "cannot create Vulkan instance: {e}"),
+            AppError::NoDevice(msg) => write!(f, "{msg}"),
+            AppError::Device(e) => write!(f, "cannot create device: {e}"),
+            AppError::Window(e) => write!(f, "cannot create window: {e}"),
+            AppError::Surface(e) => write!(f, "cannot create surface: {e}"),
+            AppError::Swapchain(e) => write!(f, "cannot create swapchain: {e}"),
+            AppError::Shader(msg) => write!(f, "cannot load shader: {msg}"),
+            AppError::Pipeline(msg) => write!(f, "cannot create pipeline: {msg}"),
+            AppError::DeviceLost => write!(f, "the device was lost"),
+            AppError::SurfaceLost => write!(f, "the surface was lost"),
+            AppError::Render(msg) => write!(f, "rendering failed: {msg}"),
+        }
+    }
+}
+
+impl Error for AppError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AppError::Library(e) => Some(e),
+            AppError::Instance(e) | AppError::Device(e) | AppError::Swapchain(e) => Some(e),
+            AppError::Surface(e) => Some(e),
+            _ => None,
+        }
+    }
+}
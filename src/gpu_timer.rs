@@ -0,0 +1,115 @@
+use std::{sync::Arc, time::Duration};
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    device::Queue,
+    query::{QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType},
+    sync::PipelineStage,
+};
+
+use crate::error::AppError;
+
+/// Frames measured at once, more than can be in flight, so the results of a frame are read
+/// when its queries are about to be reused.
+const SLOTS: u32 = 4;
+
+/// Measures the GPU time of frames with timestamp queries. Results arrive a few frames late.
+pub struct GpuTimer {
+    pool: Arc<QueryPool>,
+    /// Nanoseconds per timestamp tick.
+    period: f64,
+    /// Timestamps have this many valid bits, the rest are garbage.
+    mask: u64,
+    next: u32,
+    /// Slots with timestamps recorded since their last read.
+    pending: [bool; SLOTS as usize],
+    last: Option<Duration>,
+}
+
+impl GpuTimer {
+    /// A timer for command buffers submitted to `queue`, `None` if its queue family has no
+    /// timestamps.
+    pub fn new(queue: &Arc<Queue>) -> Result<Option<Self>, AppError> {
+        let device = queue.device();
+        let physical_device = device.physical_device();
+        let Some(valid_bits) = physical_device.queue_family_properties()
+            [queue.queue_family_index() as usize]
+            .timestamp_valid_bits
+        else {
+            return Ok(None);
+        };
+
+        let pool = QueryPool::new(
+            device.clone(),
+            QueryPoolCreateInfo {
+                query_count: SLOTS * 2,
+                ..QueryPoolCreateInfo::query_type(QueryType::Timestamp)
+            },
+        )
+        .map_err(|e| AppError::Render(e.to_string()))?;
+
+        Ok(Some(GpuTimer {
+            pool,
+            period: physical_device.properties().timestamp_period as f64,
+            mask: u64::MAX >> (64 - valid_bits),
+            next: 0,
+            pending: [false; SLOTS as usize],
+            last: None,
+        }))
+    }
+
+    /// The GPU time of the latest frame measured so far.
+    pub fn last(&self) -> Option<Duration> {
+        self.last
+    }
+
+    /// Records the start of a frame. Returns the time of the frame that last used the queries,
+    /// if it has finished.
+    pub fn begin(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<Option<Duration>, AppError> {
+        let slot = self.next;
+        let queries = slot * 2..slot * 2 + 2;
+        let mut measured = None;
+        if std::mem::take(&mut self.pending[slot as usize]) {
+            let mut timestamps = [0u64; 2];
+            if let Ok(true) =
+                self.pool
+                    .get_results(queries.clone(), &mut timestamps, QueryResultFlags::empty())
+            {
+                let ticks =
+                    (timestamps[1] & self.mask).wrapping_sub(timestamps[0] & self.mask) & self.mask;
+                measured = Some(Duration::from_nanos((ticks as f64 * self.period) as u64));
+                self.last = measured;
+            }
+        }
+
+        // Safety: the queries are reset before they are written, and a frame using them is
+        // recorded only after the previous one was submitted.
+        unsafe {
+            builder
+                .reset_query_pool(self.pool.clone(), queries.clone())?
+                .write_timestamp(self.pool.clone(), queries.start, PipelineStage::TopOfPipe)?;
+        }
+        Ok(measured)
+    }
+
+    /// Records the end of the frame started with `begin`.
+    pub fn end(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<(), AppError> {
+        let slot = self.next;
+        // Safety: see `begin`.
+        unsafe {
+            builder.write_timestamp(
+                self.pool.clone(),
+                slot * 2 + 1,
+                PipelineStage::BottomOfPipe,
+            )?;
+        }
+        self.pending[slot as usize] = true;
+        self.next = (slot + 1) % SLOTS;
+        Ok(())
+    }
+}
@@ -0,0 +1,321 @@
+use std::{fs::File, io, io::BufWriter, path::Path, sync::Arc};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
+        CopyImageToBufferInfo,
+    },
+    device::{Device, DeviceCreateInfo, Queue, QueueCreateInfo, QueueFlags},
+    format::Format,
+    image::{view::ImageView, Image, ImageCreateInfo, ImageType, ImageUsage},
+    instance::{Instance, InstanceCreateFlags, InstanceCreateInfo},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass},
+    shader::ShaderModule,
+    sync::{self, GpuFuture},
+    VulkanLibrary,
+};
+
+use crate::accumulation::Accumulation;
+use crate::camera::Camera;
+use crate::device::{self, DeviceSelector};
+use crate::error::AppError;
+use crate::light::{Light, LightList};
+use crate::params::FrameTime;
+use crate::post::{PostChain, PostSettings};
+use crate::quality::Quality;
+use crate::render::{self, Backend, MyVertex, RenderSettings, ScenePipeline, ShaderResources};
+use crate::scene::Scene;
+
+// sRGB, like the swapchains the window prefers, so the PNG looks the same as the window.
+const FORMAT: Format = Format::R8G8B8A8_SRGB;
+
+pub struct HeadlessRenderer {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    vertex_buffer: Subbuffer<[MyVertex]>,
+    render_pass: Arc<RenderPass>,
+    scene_shader: Arc<ShaderModule>,
+    pipeline: ScenePipeline,
+    /// The preset `pipeline` is specialized for, it is rebuilt when the settings ask for another.
+    quality: Quality,
+    post: PostChain,
+    post_settings: PostSettings,
+    resources: ShaderResources,
+    /// Single frames never accumulate, this only fills the binding.
+    accumulation: Accumulation,
+}
+
+impl HeadlessRenderer {
+    pub fn new(
+        selector: Option<&DeviceSelector>,
+        backend: Backend,
+        scene: Option<Scene>,
+    ) -> Result<Self, AppError> {
+        let library = VulkanLibrary::new().map_err(AppError::Library)?;
+
+        let instance = Instance::new(
+            library,
+            InstanceCreateInfo {
+                flags: InstanceCreateFlags::ENUMERATE_PORTABILITY,
+                ..Default::default()
+            },
+        )
+        .map_err(AppError::Instance)?;
+
+        // No surface is involved, so any device with a graphics queue will do. This includes
+        // software implementations such as lavapipe.
+        let (physical_device, queue_family_index) =
+            device::select_physical_device(&instance, selector, |p| {
+                if !p
+                    .supported_features()
+                    .contains(&device::required_features())
+                {
+                    return None;
+                }
+                p.queue_family_properties()
+                    .iter()
+                    .position(|q| {
+                        q.queue_flags
+                            .contains(QueueFlags::GRAPHICS | QueueFlags::COMPUTE)
+                    })
+                    .map(|i| i as u32)
+            })
+            .map_err(AppError::NoDevice)?;
+
+        println!(
+            "Using device: {} (type: {:?})",
+            physical_device.properties().device_name,
+            physical_device.properties().device_type,
+        );
+
+        let (device, mut queues) = Device::new(
+            physical_device,
+            DeviceCreateInfo {
+                enabled_features: device::required_features(),
+                queue_create_infos: vec![QueueCreateInfo {
+                    queue_family_index,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        )
+        .map_err(AppError::Device)?;
+
+        let queue = queues
+            .next()
+            .ok_or_else(|| AppError::NoDevice("the device has no queue".into()))?;
+
+        let scene_shader = render::scene_shader(device.clone(), backend, scene.as_ref())?;
+        let scene = scene.unwrap_or_else(Scene::builtin);
+        Self::with_device(device, queue, scene_shader, &scene)
+    }
+
+    /// Renders on an existing device, e.g. the one of the interactive window. `scene_shader`
+    /// has to be compiled for `scene`, for either backend.
+    pub fn with_device(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        scene_shader: Arc<ShaderModule>,
+        scene: &Scene,
+    ) -> Result<Self, AppError> {
+        let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+
+        let command_buffer_allocator = Arc::new(StandardCommandBufferAllocator::new(
+            device.clone(),
+            Default::default(),
+        ));
+
+        let vertex_buffer = render::fullscreen_quad(memory_allocator.clone())?;
+
+        let render_pass = render::render_pass(device.clone(), render::HDR_FORMAT)?;
+        let resources = ShaderResources::new(device.clone(), memory_allocator.clone(), scene);
+        let quality = resources.settings.quality;
+        let pipeline = ScenePipeline::new(
+            device.clone(),
+            render_pass.clone(),
+            scene_shader.clone(),
+            quality,
+        )?;
+        let post = PostChain::new(device.clone(), memory_allocator.clone(), FORMAT)?;
+
+        let accumulation = Accumulation::new(memory_allocator.clone(), [1, 1])?;
+
+        Ok(HeadlessRenderer {
+            device,
+            queue,
+            memory_allocator,
+            command_buffer_allocator,
+            vertex_buffer,
+            render_pass,
+            scene_shader,
+            pipeline,
+            quality,
+            post,
+            post_settings: PostSettings::default(),
+            resources,
+            accumulation,
+        })
+    }
+
+    /// Replaces the lights of the scene.
+    pub fn set_lights(&mut self, lights: Vec<Light>) {
+        self.resources.lights = LightList::new(lights);
+    }
+
+    pub fn set_settings(&mut self, settings: RenderSettings) {
+        self.resources.settings = settings;
+    }
+
+    pub fn set_post_settings(&mut self, post_settings: PostSettings) {
+        self.post_settings = post_settings;
+    }
+
+    /// Renders one frame with the params and lights animated to `frame` and returns it as
+    /// tightly packed RGBA8 rows.
+    pub fn render(
+        &mut self,
+        camera: &Camera,
+        frame: FrameTime,
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<u8>, AppError> {
+        let max_size = self
+            .device
+            .physical_device()
+            .properties()
+            .max_image_dimension2_d;
+        if width > max_size || height > max_size {
+            return Err(AppError::Render(format!(
+                "{width}x{height} exceeds the maximum image size of {max_size}"
+            )));
+        }
+
+        let image = Image::new(
+            self.memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: FORMAT,
+                extent: [width, height, 1],
+                usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .map_err(|e| AppError::Render(e.to_string()))?;
+
+        let hdr = render::scene_framebuffer(
+            self.memory_allocator.clone(),
+            self.render_pass.clone(),
+            [width, height],
+        )?;
+        let framebuffer = Framebuffer::new(
+            self.post.render_pass().clone(),
+            FramebufferCreateInfo {
+                attachments: vec![ImageView::new_default(image.clone())?],
+                ..Default::default()
+            },
+        )?;
+
+        let output = Buffer::new_slice::<u8>(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            (width as u64) * (height as u64) * 4,
+        )
+        .map_err(|e| AppError::Render(e.to_string()))?;
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.command_buffer_allocator.clone(),
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        let quality = self.resources.settings.quality;
+        if quality != self.quality {
+            self.pipeline = ScenePipeline::new(
+                self.device.clone(),
+                self.render_pass.clone(),
+                self.scene_shader.clone(),
+                quality,
+            )?;
+            self.quality = quality;
+        }
+
+        let mut camera = camera.clone();
+        camera.resize(width as usize, height as usize);
+        self.resources.animate(frame);
+        let descriptor_set = self.resources.descriptor_set(
+            self.pipeline.layout(),
+            [width as f32, height as f32],
+            &camera,
+            self.accumulation.image(),
+            None,
+        )?;
+
+        self.pipeline.record(
+            &mut builder,
+            hdr.clone(),
+            [width, height],
+            self.vertex_buffer.clone(),
+            descriptor_set,
+        )?;
+        self.post.draw(
+            &mut builder,
+            framebuffer,
+            hdr.attachments()[0].clone(),
+            [width, height],
+            self.vertex_buffer.clone(),
+            &self.resources.settings,
+            &self.post_settings,
+            frame.frame,
+        )?;
+
+        builder.copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(image, output.clone()))?;
+
+        let command_buffer = builder.build()?;
+
+        sync::now(self.device.clone())
+            .then_execute(self.queue.clone(), command_buffer)
+            .map_err(|e| AppError::Render(e.to_string()))?
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+
+        let pixels = output
+            .read()
+            .map_err(|e| AppError::Render(e.to_string()))?
+            .to_vec();
+        Ok(pixels)
+    }
+}
+
+/// Writes `rgba` as a PNG with a text chunk for every keyword and value in `text`.
+pub fn save_png(
+    path: &Path,
+    width: u32,
+    height: u32,
+    rgba: &[u8],
+    text: &[(String, String)],
+) -> io::Result<()> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    for (keyword, value) in text {
+        encoder.add_itxt_chunk(keyword.clone(), value.clone())?;
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    writer.finish()?;
+    Ok(())
+}
@@ -0,0 +1,539 @@
+use glam::{vec3, Vec3};
+use serde::Deserialize;
+use std::{f32::consts::TAU, sync::Arc};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    device::DeviceOwned,
+    memory::allocator::{AllocationCreateInfo, MemoryAllocator, MemoryTypeFilter},
+};
+
+use crate::error::AppError;
+use crate::params::{ParamValues, Value};
+use crate::shaders::fragment;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Light {
+    #[serde(flatten)]
+    pub kind: LightKind,
+    #[serde(default = "white")]
+    pub color: Vec3,
+    #[serde(default = "one")]
+    pub intensity: f32,
+    /// Penumbra sharpness passed to `shadow()`, larger values give harder shadows.
+    #[serde(default = "default_shadow_softness")]
+    pub shadow_softness: f32,
+    #[serde(default)]
+    pub animation: Option<Animation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LightKind {
+    Directional {
+        direction: Value<Vec3>,
+    },
+    /// Falls off with the square of the distance, reaching zero at `range`.
+    Point {
+        position: Vec3,
+        range: f32,
+    },
+    /// A point light restricted to a cone. Angles are in degrees from the axis, the light fades
+    /// out between `inner_angle` and `outer_angle`.
+    Spot {
+        position: Vec3,
+        direction: Value<Vec3>,
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+    /// A spherical light.
+    Area {
+        position: Vec3,
+        radius: f32,
+        range: f32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Animation {
+    /// Moves the light on a horizontal circle around `center`, one turn every `period` seconds.
+    Orbit {
+        center: Vec3,
+        radius: f32,
+        period: f32,
+    },
+}
+
+fn white() -> Vec3 {
+    Vec3::ONE
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+fn default_shadow_softness() -> f32 {
+    32.0
+}
+
+/// Also false for NaN, which every comparison rejects.
+fn positive(x: f32) -> bool {
+    x > 0.0 && x.is_finite()
+}
+
+impl Light {
+    /// The light used when a scene does not declare any.
+    pub fn sun() -> Self {
+        Light {
+            kind: LightKind::Directional {
+                direction: Value::Const(vec3(-3., -1.5, -2.)),
+            },
+            color: vec3(1., 0.85, 0.70),
+            intensity: 1.0,
+            shadow_softness: default_shadow_softness(),
+            animation: None,
+        }
+    }
+
+    pub fn point(position: Vec3, range: f32, color: Vec3, intensity: f32) -> Self {
+        Light {
+            kind: LightKind::Point { position, range },
+            color,
+            intensity,
+            shadow_softness: default_shadow_softness(),
+            animation: None,
+        }
+    }
+
+    fn position_mut(&mut self) -> Option<&mut Vec3> {
+        match &mut self.kind {
+            LightKind::Directional { .. } => None,
+            LightKind::Point { position, .. }
+            | LightKind::Spot { position, .. }
+            | LightKind::Area { position, .. } => Some(position),
+        }
+    }
+
+    /// The parameter driving the direction, if any.
+    pub fn param(&self) -> Option<&str> {
+        match &self.kind {
+            LightKind::Directional { direction } | LightKind::Spot { direction, .. } => {
+                direction.param()
+            }
+            LightKind::Point { .. } | LightKind::Area { .. } => None,
+        }
+    }
+
+    /// Returns the light as it is in the frame `values` were evaluated for.
+    pub fn at(&self, values: &ParamValues) -> Light {
+        let mut light = self.clone();
+        if let LightKind::Directional { direction } | LightKind::Spot { direction, .. } =
+            &mut light.kind
+        {
+            direction.update(values);
+        }
+        if let Some(Animation::Orbit {
+            center,
+            radius,
+            period,
+        }) = self.animation
+        {
+            let angle = TAU * values.frame.time / period;
+            if let Some(position) = light.position_mut() {
+                *position = center + radius * vec3(angle.cos(), 0.0, angle.sin());
+            }
+        }
+        light
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let (range, radius) = match &self.kind {
+            LightKind::Directional { direction } => {
+                if direction.get() == Vec3::ZERO && direction.param().is_none() {
+                    return Err("directional light needs a direction".into());
+                }
+                (1.0, 1.0)
+            }
+            LightKind::Point { range, .. } => (*range, 1.0),
+            LightKind::Spot {
+                direction,
+                range,
+                inner_angle,
+                outer_angle,
+                ..
+            } => {
+                if direction.get() == Vec3::ZERO && direction.param().is_none() {
+                    return Err("spot light needs a direction".into());
+                }
+                if !(0.0 <= *inner_angle && inner_angle <= outer_angle && *outer_angle <= 180.0) {
+                    return Err(
+                        "spot light angles must satisfy 0 <= inner angle <= outer angle <= 180"
+                            .into(),
+                    );
+                }
+                (*range, 1.0)
+            }
+            LightKind::Area { radius, range, .. } => (*range, *radius),
+        };
+        if !positive(range) || !positive(radius) {
+            return Err("light range and radius must be positive".into());
+        }
+        let color = self.color * self.intensity;
+        if !(color.is_finite() && self.intensity >= 0.0 && self.color.cmpge(Vec3::ZERO).all()) {
+            return Err("light color and intensity cannot be negative".into());
+        }
+        if let Some(Animation::Orbit { radius, period, .. }) = self.animation {
+            if !positive(period) || !radius.is_finite() {
+                return Err("orbit needs a finite radius and a positive period".into());
+            }
+        }
+        Ok(())
+    }
+
+    fn to_gpu(&self) -> fragment::Light {
+        let mut gpu = fragment::Light {
+            position: [0.0; 3],
+            kind: 0,
+            direction: [0.0; 3],
+            range: 0.0,
+            color: self.color.to_array(),
+            intensity: self.intensity,
+            cos_inner: 0.0,
+            cos_outer: 0.0,
+            radius: 0.0,
+            shadow_softness: self.shadow_softness,
+        };
+        // Kinds match the LIGHT_* defines in `common.glsl`.
+        // A parameter can pass through zero, which leaves the light pointing down.
+        match &self.kind {
+            LightKind::Directional { direction } => {
+                gpu.kind = 0;
+                gpu.direction = direction.get().normalize_or(Vec3::NEG_Y).to_array();
+            }
+            LightKind::Point { position, range } => {
+                gpu.kind = 1;
+                gpu.position = position.to_array();
+                gpu.range = *range;
+            }
+            LightKind::Spot {
+                position,
+                direction,
+                range,
+                inner_angle,
+                outer_angle,
+            } => {
+                gpu.kind = 2;
+                gpu.position = position.to_array();
+                gpu.direction = direction.get().normalize_or(Vec3::NEG_Y).to_array();
+                gpu.range = *range;
+                gpu.cos_inner = inner_angle.to_radians().cos();
+                gpu.cos_outer = outer_angle.to_radians().cos();
+            }
+            LightKind::Area {
+                position,
+                radius,
+                range,
+            } => {
+                gpu.kind = 3;
+                gpu.position = position.to_array();
+                gpu.radius = *radius;
+                gpu.range = *range;
+            }
+        }
+        gpu
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LightId(u32);
+
+/// The lights of the scene, mirrored in a storage buffer.
+pub struct LightList {
+    lights: Vec<(LightId, Light)>,
+    next_id: u32,
+    values: ParamValues,
+    buffer: Option<Subbuffer<[fragment::Light]>>,
+}
+
+impl LightList {
+    pub fn new(lights: Vec<Light>) -> Self {
+        let mut list = LightList {
+            lights: vec![],
+            next_id: 0,
+            values: ParamValues::default(),
+            buffer: None,
+        };
+        for light in lights {
+            list.add(light);
+        }
+        list
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Light> {
+        self.lights.iter().map(|(_, l)| l)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn add(&mut self, light: Light) -> LightId {
+        let id = LightId(self.next_id);
+        self.next_id += 1;
+        self.lights.push((id, light));
+        self.buffer = None;
+        id
+    }
+
+    pub fn remove(&mut self, id: LightId) -> Option<Light> {
+        let index = self.lights.iter().position(|(i, _)| *i == id)?;
+        self.buffer = None;
+        Some(self.lights.remove(index).1)
+    }
+
+    /// Advances animated lights to the frame `values` were evaluated for.
+    pub fn animate(&mut self, values: &ParamValues) {
+        if self
+            .lights
+            .iter()
+            .any(|(_, l)| l.at(values) != l.at(&self.values))
+        {
+            self.buffer = None;
+        }
+        self.values = values.clone();
+    }
+
+    /// Whether the lights changed since they were last uploaded.
+    pub fn is_dirty(&self) -> bool {
+        self.buffer.is_none()
+    }
+
+    /// Returns the storage buffer, uploading the lights first if they changed.
+    pub fn buffer(
+        &mut self,
+        memory_allocator: Arc<dyn MemoryAllocator>,
+    ) -> Result<Subbuffer<[fragment::Light]>, AppError> {
+        if self
+            .buffer
+            .as_ref()
+            .is_some_and(|b| b.device() != memory_allocator.device())
+        {
+            // Left over from a lost device.
+            self.buffer = None;
+        }
+        if let Some(buffer) = &self.buffer {
+            return Ok(buffer.clone());
+        }
+        let mut gpu: Vec<_> = self
+            .lights
+            .iter()
+            .map(|(_, l)| l.at(&self.values).to_gpu())
+            .collect();
+        if gpu.is_empty() {
+            // Storage buffers cannot be empty, `light_count` keeps this one unused.
+            gpu.push(Light::sun().to_gpu());
+        }
+        let buffer = upload(memory_allocator, gpu)?;
+        self.buffer = Some(buffer.clone());
+        Ok(buffer)
+    }
+}
+
+fn upload(
+    memory_allocator: Arc<dyn MemoryAllocator>,
+    lights: Vec<fragment::Light>,
+) -> Result<Subbuffer<[fragment::Light]>, AppError> {
+    Buffer::from_iter(
+        memory_allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::STORAGE_BUFFER,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        lights,
+    )
+    .map_err(|e| AppError::Render(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::FrameTime;
+
+    fn light(json: &str) -> Light {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn error(json: &str) -> String {
+        light(json).validate().unwrap_err()
+    }
+
+    fn at_time(time: f32) -> ParamValues {
+        ParamValues::evaluate(
+            &[],
+            FrameTime {
+                time,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn valid_lights() {
+        for json in [
+            r#"{"directional": {"direction": [0, -1, 0]}}"#,
+            r#"{"point": {"position": [0, 1, 0], "range": 5}, "intensity": 0}"#,
+            r#"{"spot": {"position": [0, 1, 0], "direction": [0, -1, 0], "range": 5,
+                "inner_angle": 0, "outer_angle": 180}}"#,
+            r#"{"area": {"position": [0, 1, 0], "radius": 0.5, "range": 5},
+                "animation": {"orbit": {"center": [0, 0, 0], "radius": 0, "period": 2}}}"#,
+        ] {
+            assert_eq!(light(json).validate(), Ok(()), "{json}");
+        }
+        assert_eq!(Light::sun().validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_rejects_bad_lights() {
+        assert!(error(r#"{"directional": {"direction": [0, 0, 0]}}"#).contains("direction"));
+        assert!(error(r#"{"point": {"position": [0, 0, 0], "range": 0}}"#).contains("range"));
+        assert!(
+            error(r#"{"area": {"position": [0, 0, 0], "radius": -1, "range": 5}}"#)
+                .contains("radius")
+        );
+        for angles in [(30, 20), (-10, 20), (20, 190)] {
+            let json = format!(
+                r#"{{"spot": {{"position": [0, 0, 0], "direction": [0, -1, 0], "range": 5,
+                    "inner_angle": {}, "outer_angle": {}}}}}"#,
+                angles.0, angles.1
+            );
+            assert!(error(&json).contains("angle"), "{angles:?}");
+        }
+        assert!(
+            error(r#"{"point": {"position": [0, 0, 0], "range": 5}, "intensity": -1}"#)
+                .contains("intensity")
+        );
+        assert!(
+            error(r#"{"point": {"position": [0, 0, 0], "range": 5}, "color": [1, -1, 1]}"#)
+                .contains("color")
+        );
+        assert!(error(
+            r#"{"point": {"position": [0, 0, 0], "range": 5},
+                "animation": {"orbit": {"center": [0, 0, 0], "radius": 1, "period": 0}}}"#
+        )
+        .contains("period"));
+    }
+
+    #[test]
+    fn validate_rejects_non_finite_numbers() {
+        // JSON has no NaN or infinity, scenes can still build lights in code.
+        let mut point = Light::point(Vec3::ZERO, f32::NAN, Vec3::ONE, 1.0);
+        assert!(point.validate().is_err());
+        point.kind = LightKind::Point {
+            position: Vec3::ZERO,
+            range: f32::INFINITY,
+        };
+        assert!(point.validate().is_err());
+        let bright = Light::point(Vec3::ZERO, 5.0, Vec3::ONE, f32::INFINITY);
+        assert!(bright.validate().is_err());
+        let colored = Light::point(Vec3::ZERO, 5.0, Vec3::new(1.0, f32::NAN, 1.0), 1.0);
+        assert!(colored.validate().is_err());
+        let mut spot = light(
+            r#"{"spot": {"position": [0, 0, 0], "direction": [0, -1, 0], "range": 5,
+                "inner_angle": 10, "outer_angle": 20}}"#,
+        );
+        if let LightKind::Spot { inner_angle, .. } = &mut spot.kind {
+            *inner_angle = f32::NAN;
+        }
+        assert!(spot.validate().is_err());
+        let mut orbiting = Light::point(Vec3::ZERO, 5.0, Vec3::ONE, 1.0);
+        orbiting.animation = Some(Animation::Orbit {
+            center: Vec3::ZERO,
+            radius: 1.0,
+            period: f32::NAN,
+        });
+        assert!(orbiting.validate().is_err());
+    }
+
+    #[test]
+    fn orbit_moves_the_light_around_the_center() {
+        let mut orbiting = Light::point(Vec3::ZERO, 5.0, Vec3::ONE, 1.0);
+        orbiting.animation = Some(Animation::Orbit {
+            center: vec3(1.0, 2.0, 3.0),
+            radius: 2.0,
+            period: 4.0,
+        });
+        let position = |time| match orbiting.at(&at_time(time)).kind {
+            LightKind::Point { position, .. } => position,
+            _ => unreachable!(),
+        };
+        assert!(position(0.0).abs_diff_eq(vec3(3.0, 2.0, 3.0), 1e-5));
+        assert!(position(1.0).abs_diff_eq(vec3(1.0, 2.0, 5.0), 1e-5));
+        assert!(position(4.0).abs_diff_eq(position(0.0), 1e-5));
+    }
+
+    #[test]
+    fn add_and_remove_keep_ids_stable() {
+        let mut list = LightList::new(vec![Light::sun()]);
+        let a = list.add(Light::point(Vec3::X, 1.0, Vec3::ONE, 1.0));
+        let b = list.add(Light::point(Vec3::Y, 1.0, Vec3::ONE, 1.0));
+        assert_ne!(a, b);
+        assert_eq!(list.len(), 3);
+        assert!(list.is_dirty());
+        assert_eq!(
+            list.remove(a),
+            Some(Light::point(Vec3::X, 1.0, Vec3::ONE, 1.0))
+        );
+        assert_eq!(list.remove(a), None);
+        assert_eq!(list.len(), 2);
+        // Removing `a` does not shift the id of a later light.
+        assert_eq!(
+            list.remove(b),
+            Some(Light::point(Vec3::Y, 1.0, Vec3::ONE, 1.0))
+        );
+        let c = list.add(Light::sun());
+        assert_ne!(c, a);
+        assert_ne!(c, b);
+    }
+
+    #[test]
+    fn animate_keeps_the_frame_for_the_upload() {
+        let mut orbiting = Light::point(Vec3::ZERO, 5.0, Vec3::ONE, 1.0);
+        orbiting.animation = Some(Animation::Orbit {
+            center: Vec3::ZERO,
+            radius: 1.0,
+            period: 4.0,
+        });
+        let mut list = LightList::new(vec![orbiting.clone()]);
+        list.animate(&at_time(1.0));
+        assert_eq!(list.values.frame.time, 1.0);
+        // `buffer` uploads the lights as they are at the animated frame.
+        let uploaded: Vec<_> = list
+            .lights
+            .iter()
+            .map(|(_, l)| l.at(&list.values).to_gpu())
+            .collect();
+        let expected = orbiting.at(&at_time(1.0)).to_gpu();
+        assert_eq!(uploaded.len(), 1);
+        assert_eq!(uploaded[0].position, expected.position);
+        assert!(Vec3::from(uploaded[0].position).abs_diff_eq(Vec3::Z, 1e-5));
+    }
+
+    #[test]
+    fn spot_angles_become_cosines() {
+        let spot = light(
+            r#"{"spot": {"position": [0, 0, 0], "direction": [0, -2, 0], "range": 5,
+                "inner_angle": 0, "outer_angle": 60}}"#,
+        )
+        .to_gpu();
+        assert_eq!(spot.kind, 2);
+        assert_eq!(spot.direction, [0.0, -1.0, 0.0]);
+        assert!((spot.cos_inner - 1.0).abs() < 1e-6);
+        assert!((spot.cos_outer - 0.5).abs() < 1e-6);
+    }
+}
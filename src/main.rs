@@ -1,16 +1,127 @@
 use app::App;
-use std::error::Error;
+use camera_path::CameraPath;
+use cli::Command;
+use headless::HeadlessRenderer;
+use params::FrameTime;
+use scene::Scene;
+use std::{error::Error, process::ExitCode};
 use winit::event_loop::EventLoop;
 
+mod accumulation;
 mod app;
+mod bvh;
 mod camera;
+mod camera_path;
+mod capture;
+mod cli;
+mod controller;
+mod device;
+mod error;
+mod gpu_timer;
+mod headless;
+mod light;
+mod material;
+mod params;
+mod picking;
+mod post;
+mod quality;
+mod reference;
+mod render;
+mod resolution;
+mod scene;
 mod shaders;
+mod timestep;
+mod tonemap;
 
-fn main() -> Result<(), impl Error> {
-    let event_loop = EventLoop::new().unwrap();
-    
-    let mut app = App::new(&event_loop);
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let options = match cli::parse(std::env::args().skip(1)) {
+        Ok(Command::Run(options)) => *options,
+        Ok(Command::ListDevices) => return device::list_devices(),
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(2);
+        }
+    };
+
+    let scene = options.scene.as_deref().map(Scene::load).transpose()?;
+    let post_settings = options.post_settings()?;
+
+    if let Some(output) = &options.headless {
+        let [width, height] = options.size;
+        let camera = options.camera();
+        let ss = options.supersample;
+        let frame = FrameTime {
+            time: options.time,
+            ..Default::default()
+        };
+
+        let pixels = if options.cpu {
+            let scene = scene.unwrap_or_else(Scene::builtin);
+            reference::render(
+                &scene,
+                &camera,
+                frame,
+                &options.render_settings,
+                width * ss,
+                height * ss,
+            )
+        } else {
+            let mut renderer =
+                HeadlessRenderer::new(options.device.as_ref(), options.backend, scene)?;
+            renderer.set_settings(options.render_settings);
+            renderer.set_post_settings(post_settings.clone());
+            renderer.render(&camera, frame, width * ss, height * ss)?
+        };
+        let pixels = capture::downsample(&pixels, width, height, ss);
+        let text = capture::metadata(
+            &camera,
+            options.time,
+            options.scene.as_deref(),
+            options.size,
+            ss,
+            &options.render_settings,
+            &post_settings,
+        );
+        headless::save_png(output, width, height, &pixels, &text)?;
+        println!("Wrote {}x{} frame to {}", width, height, output.display());
+        return Ok(());
+    }
+
+    // Recorded keyframes are added to an existing path file.
+    let camera_path = if options.play || options.benchmark || options.camera_path.exists() {
+        CameraPath::load(&options.camera_path)?
+    } else {
+        CameraPath::default()
+    };
+    if (options.play || options.benchmark) && camera_path.keyframes.len() < 2 {
+        return Err(format!(
+            "{} needs at least 2 keyframes to play",
+            options.camera_path.display()
+        )
+        .into());
+    }
+
+    let event_loop = EventLoop::new()?;
 
-    event_loop.run_app(&mut app)
+    let mut app = App::new(&event_loop, &options, scene, camera_path, post_settings)?;
 
+    event_loop.run_app(&mut app)?;
+    match app.take_error() {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
 }
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    device::DeviceOwned,
+    memory::allocator::{AllocationCreateInfo, MemoryAllocator, MemoryTypeFilter},
+    padded::Padded,
+};
+
+use crate::error::AppError;
+use crate::params::ParamValues;
+use crate::scene::Material;
+use crate::shaders::fragment;
+
+/// Element type of the `Materials` storage buffer, padded to the std430 array stride.
+pub type GpuMaterial = Padded<fragment::Material, 4>;
+
+/// Holds the scene materials and keeps a copy of them in a storage buffer.
+pub struct MaterialLibrary {
+    materials: Vec<Material>,
+    buffer: Option<Subbuffer<[GpuMaterial]>>,
+}
+
+impl MaterialLibrary {
+    pub fn new(materials: Vec<Material>) -> Self {
+        MaterialLibrary {
+            materials,
+            buffer: None,
+        }
+    }
+
+    pub fn replace(&mut self, materials: Vec<Material>) {
+        self.materials = materials;
+        self.buffer = None;
+    }
+
+    /// Updates base colors driven by parameters, uploading the materials again if any changed.
+    pub fn animate(&mut self, values: &ParamValues) {
+        for material in &mut self.materials {
+            let previous = material.base_color.get();
+            material.base_color.update(values);
+            if material.base_color.get() != previous {
+                self.buffer = None;
+            }
+        }
+    }
+
+    /// Whether the materials changed since they were last uploaded.
+    pub fn is_dirty(&self) -> bool {
+        self.buffer.is_none()
+    }
+
+    /// Returns the storage buffer, uploading the materials first if they changed since the
+    /// last call.
+    pub fn buffer(
+        &mut self,
+        memory_allocator: Arc<dyn MemoryAllocator>,
+    ) -> Result<Subbuffer<[GpuMaterial]>, AppError> {
+        if self
+            .buffer
+            .as_ref()
+            .is_some_and(|b| b.device() != memory_allocator.device())
+        {
+            // Left over from a lost device.
+            self.buffer = None;
+        }
+        if let Some(buffer) = &self.buffer {
+            return Ok(buffer.clone());
+        }
+        // A fresh buffer is allocated on every change, frames still in flight keep the old one.
+        let buffer = upload(memory_allocator, &self.materials)?;
+        self.buffer = Some(buffer.clone());
+        Ok(buffer)
+    }
+}
+
+fn upload(
+    memory_allocator: Arc<dyn MemoryAllocator>,
+    materials: &[Material],
+) -> Result<Subbuffer<[GpuMaterial]>, AppError> {
+    Buffer::from_iter(
+        memory_allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::STORAGE_BUFFER,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        materials.iter().map(|m| {
+            fragment::Material {
+                base_color: m.base_color.get().to_array(),
+                metallic: m.metallic,
+                emissive: m.emissive.to_array(),
+                roughness: m.roughness,
+                absorption: m.absorption().to_array(),
+                transmission: m.transmission,
+                clearcoat: m.clearcoat,
+                clearcoat_roughness: m.clearcoat_roughness,
+                ior: m.ior,
+            }
+            .into()
+        }),
+    )
+    .map_err(|e| AppError::Render(e.to_string()))
+}
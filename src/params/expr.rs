@@ -0,0 +1,367 @@
+use std::{f32::consts::PI, iter::Peekable, str::CharIndices};
+
+use super::FrameTime;
+
+/// An arithmetic expression over the frame time, parsed from a scene file.
+///
+/// Supports numbers, `+ - * / % ^`, parentheses, the variables `t` (or `time`), `dt`, `frame`
+/// and `pi`, and the functions listed in `Func`.
+#[derive(Debug, Clone)]
+pub struct Expr(Node);
+
+#[derive(Debug, Clone)]
+enum Node {
+    Number(f32),
+    Var(Var),
+    Neg(Box<Node>),
+    Binary(Op, Box<Node>, Box<Node>),
+    Call(Func, Vec<Node>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Var {
+    Time,
+    DeltaTime,
+    Frame,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Func {
+    Sin,
+    Cos,
+    Tan,
+    Abs,
+    Floor,
+    Fract,
+    Sqrt,
+    Exp,
+    Ln,
+    Min,
+    Max,
+    Clamp,
+    Mix,
+    Step,
+    Smoothstep,
+}
+
+impl Func {
+    fn parse(name: &str) -> Option<(Func, usize)> {
+        let func = match name {
+            "sin" => (Func::Sin, 1),
+            "cos" => (Func::Cos, 1),
+            "tan" => (Func::Tan, 1),
+            "abs" => (Func::Abs, 1),
+            "floor" => (Func::Floor, 1),
+            "fract" => (Func::Fract, 1),
+            "sqrt" => (Func::Sqrt, 1),
+            "exp" => (Func::Exp, 1),
+            "ln" => (Func::Ln, 1),
+            "min" => (Func::Min, 2),
+            "max" => (Func::Max, 2),
+            "step" => (Func::Step, 2),
+            "clamp" => (Func::Clamp, 3),
+            "mix" => (Func::Mix, 3),
+            "smoothstep" => (Func::Smoothstep, 3),
+            _ => return None,
+        };
+        Some(func)
+    }
+
+    /// Same definitions as the GLSL built-ins of the same name.
+    fn apply(self, a: &[f32]) -> f32 {
+        match self {
+            Func::Sin => a[0].sin(),
+            Func::Cos => a[0].cos(),
+            Func::Tan => a[0].tan(),
+            Func::Abs => a[0].abs(),
+            Func::Floor => a[0].floor(),
+            Func::Fract => a[0] - a[0].floor(),
+            Func::Sqrt => a[0].sqrt(),
+            Func::Exp => a[0].exp(),
+            Func::Ln => a[0].ln(),
+            Func::Min => a[0].min(a[1]),
+            Func::Max => a[0].max(a[1]),
+            Func::Step => (a[1] >= a[0]) as u32 as f32,
+            Func::Clamp => a[0].max(a[1]).min(a[2]),
+            Func::Mix => a[0] + (a[1] - a[0]) * a[2],
+            Func::Smoothstep => {
+                let t = ((a[2] - a[0]) / (a[1] - a[0])).clamp(0.0, 1.0);
+                t * t * (3.0 - 2.0 * t)
+            }
+        }
+    }
+}
+
+impl Expr {
+    pub fn evaluate(&self, frame: &FrameTime) -> f32 {
+        self.0.evaluate(frame)
+    }
+}
+
+impl Node {
+    fn evaluate(&self, frame: &FrameTime) -> f32 {
+        match self {
+            Node::Number(x) => *x,
+            Node::Var(Var::Time) => frame.time,
+            Node::Var(Var::DeltaTime) => frame.delta_time,
+            Node::Var(Var::Frame) => frame.frame as f32,
+            Node::Neg(a) => -a.evaluate(frame),
+            Node::Binary(op, a, b) => {
+                let (a, b) = (a.evaluate(frame), b.evaluate(frame));
+                match op {
+                    Op::Add => a + b,
+                    Op::Sub => a - b,
+                    Op::Mul => a * b,
+                    Op::Div => a / b,
+                    // GLSL `mod`, the result has the sign of `b`.
+                    Op::Mod => a - b * (a / b).floor(),
+                    Op::Pow => a.powf(b),
+                }
+            }
+            Node::Call(func, args) => {
+                let args: Vec<f32> = args.iter().map(|a| a.evaluate(frame)).collect();
+                func.apply(&args)
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for Expr {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        let mut parser = Parser {
+            source: &source,
+            chars: source.char_indices().peekable(),
+        };
+        let node = parser.expr(0)?;
+        parser.skip_whitespace();
+        match parser.chars.peek() {
+            None => Ok(Expr(node)),
+            Some(&(i, c)) => Err(parser.error(i, &format!("unexpected '{c}'"))),
+        }
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl Parser<'_> {
+    fn error(&self, at: usize, msg: &str) -> String {
+        format!("{msg} at column {} of expression '{}'", at + 1, self.source)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+    }
+
+    /// The binary operator at the current position, with its precedence and whether it is
+    /// right associative.
+    fn peek_op(&mut self) -> Option<(Op, u8, bool)> {
+        self.skip_whitespace();
+        let op = match self.chars.peek()?.1 {
+            '+' => (Op::Add, 1, false),
+            '-' => (Op::Sub, 1, false),
+            '*' => (Op::Mul, 2, false),
+            '/' => (Op::Div, 2, false),
+            '%' => (Op::Mod, 2, false),
+            '^' => (Op::Pow, 4, true),
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// Parses operators binding tighter than `min_precedence` by precedence climbing.
+    fn expr(&mut self, min_precedence: u8) -> Result<Node, String> {
+        let mut lhs = self.unary()?;
+        while let Some((op, precedence, right)) = self.peek_op() {
+            if precedence <= min_precedence {
+                break;
+            }
+            self.chars.next();
+            let rhs = self.expr(if right { precedence - 1 } else { precedence })?;
+            lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        self.skip_whitespace();
+        if self.chars.next_if(|(_, c)| *c == '-').is_some() {
+            // Binds looser than `^`, so `-x^2` is `-(x^2)`.
+            return Ok(Node::Neg(Box::new(self.expr(3)?)));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let Some(&(start, c)) = self.chars.peek() else {
+            return Err(self.error(self.source.len(), "missing operand"));
+        };
+        if c == '(' {
+            self.chars.next();
+            let node = self.expr(0)?;
+            self.expect(')')?;
+            return Ok(node);
+        }
+        if c.is_ascii_digit() || c == '.' {
+            let end = self.take_while(|c| c.is_ascii_digit() || c == '.');
+            let number = &self.source[start..end];
+            return number
+                .parse()
+                .map(Node::Number)
+                .map_err(|_| self.error(start, &format!("invalid number '{number}'")));
+        }
+        if c.is_ascii_alphabetic() || c == '_' {
+            let end = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+            return self.identifier(start, end);
+        }
+        Err(self.error(start, &format!("unexpected '{c}'")))
+    }
+
+    fn identifier(&mut self, start: usize, end: usize) -> Result<Node, String> {
+        let name = &self.source[start..end];
+        let node = match name {
+            "t" | "time" => Node::Var(Var::Time),
+            "dt" => Node::Var(Var::DeltaTime),
+            "frame" => Node::Var(Var::Frame),
+            "pi" => Node::Number(PI),
+            _ => {
+                let (func, arity) = Func::parse(name)
+                    .ok_or_else(|| self.error(start, &format!("unknown name '{name}'")))?;
+                let mut args = vec![];
+                self.skip_whitespace();
+                self.expect('(')?;
+                loop {
+                    args.push(self.expr(0)?);
+                    self.skip_whitespace();
+                    if self.chars.next_if(|(_, c)| *c == ',').is_none() {
+                        break;
+                    }
+                }
+                self.expect(')')?;
+                if args.len() != arity {
+                    return Err(self.error(
+                        start,
+                        &format!("{name} takes {arity} arguments, got {}", args.len()),
+                    ));
+                }
+                Node::Call(func, args)
+            }
+        };
+        Ok(node)
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> usize {
+        while self.chars.next_if(|(_, c)| f(*c)).is_some() {}
+        self.chars.peek().map_or(self.source.len(), |(i, _)| *i)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            Some((i, c)) => Err(self.error(i, &format!("expected '{expected}', found '{c}'"))),
+            None => Err(self.error(self.source.len(), &format!("expected '{expected}'"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str) -> f32 {
+        let frame = FrameTime {
+            time: 2.0,
+            delta_time: 0.5,
+            frame: 10,
+        };
+        Expr::try_from(source.to_string()).unwrap().evaluate(&frame)
+    }
+
+    fn error(source: &str) -> String {
+        Expr::try_from(source.to_string()).unwrap_err()
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("8 - 3 - 2"), 3.0);
+        assert_eq!(eval("16 / 4 / 2"), 2.0);
+        assert_eq!(eval("2 ^ 3 ^ 2"), 512.0);
+        assert_eq!(eval("-2 ^ 2"), -4.0);
+        assert_eq!(eval("2 * -3"), -6.0);
+        assert_eq!(eval("--1"), 1.0);
+    }
+
+    #[test]
+    fn variables_and_functions() {
+        assert_eq!(eval("t * 2 + time"), 6.0);
+        assert_eq!(eval("dt + frame"), 10.5);
+        assert_eq!(eval("sin(pi / 2)"), 1.0);
+        assert_eq!(eval("clamp(t, 0, 1)"), 1.0);
+        assert_eq!(eval("mix(0, 10, 0.25)"), 2.5);
+        assert_eq!(eval("step(1, t)"), 1.0);
+        assert_eq!(eval("smoothstep(0, 4, t)"), 0.5);
+        assert_eq!(eval("fract(2.75) + floor(-0.5)"), -0.25);
+        assert_eq!(eval("max(min(1, 2), sqrt(9))"), 3.0);
+    }
+
+    #[test]
+    fn modulo_takes_the_sign_of_the_divisor() {
+        assert_eq!(eval("5 % 3"), 2.0);
+        assert_eq!(eval("-1 % 3"), 2.0);
+        assert_eq!(eval("1 % -3"), -2.0);
+    }
+
+    #[test]
+    fn reports_errors_with_columns() {
+        assert_eq!(error(""), "missing operand at column 1 of expression ''");
+        assert_eq!(
+            error("1 +"),
+            "missing operand at column 4 of expression '1 +'"
+        );
+        assert_eq!(
+            error("2 $ 3"),
+            "unexpected '$' at column 3 of expression '2 $ 3'"
+        );
+        assert_eq!(
+            error("(1 + 2"),
+            "expected ')' at column 7 of expression '(1 + 2'"
+        );
+        assert_eq!(
+            error("1 + 2)"),
+            "unexpected ')' at column 6 of expression '1 + 2)'"
+        );
+        assert_eq!(
+            error("1.2.3"),
+            "invalid number '1.2.3' at column 1 of expression '1.2.3'"
+        );
+        assert_eq!(
+            error("speed * t"),
+            "unknown name 'speed' at column 1 of expression 'speed * t'"
+        );
+        assert_eq!(
+            error("sin t"),
+            "expected '(', found 't' at column 5 of expression 'sin t'"
+        );
+        assert_eq!(
+            error("min(1)"),
+            "min takes 2 arguments, got 1 at column 1 of expression 'min(1)'"
+        );
+    }
+}
@@ -0,0 +1,380 @@
+use glam::{Vec3, Vec4};
+use serde::{de, Deserialize, Deserializer};
+
+mod expr;
+
+pub use expr::Expr;
+
+/// Size of the `params` array in the `Globals` uniform, see `common.glsl`.
+pub const MAX_PARAMS: usize = 16;
+
+/// The clock of the frame being rendered.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameTime {
+    /// Seconds since the start of the animation.
+    pub time: f32,
+    /// Seconds since the previous frame.
+    pub delta_time: f32,
+    /// Frames rendered before this one.
+    pub frame: u32,
+}
+
+/// A named value of up to four components, recomputed from the frame time every frame. Shaders
+/// read it from `globals.params`, scene files refer to it by name.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Param {
+    pub name: String,
+    #[serde(flatten)]
+    pub driver: Driver,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Driver {
+    /// A constant, for values only changed from Rust.
+    Value(#[serde(deserialize_with = "one_or_more")] Vec<f32>),
+    /// One expression per component, see `Expr`.
+    Expression(#[serde(deserialize_with = "expressions")] Vec<Expr>),
+    /// Linear interpolation between keys sorted by time, holding the end values outside them.
+    Keyframes {
+        keys: Vec<ParamKey>,
+        /// Repeat the keys instead of holding the last value.
+        #[serde(default, rename = "loop")]
+        repeat: bool,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ParamKey {
+    pub time: f32,
+    #[serde(deserialize_with = "one_or_more")]
+    pub value: Vec<f32>,
+}
+
+/// Accepts a single value as well as a list.
+fn one_or_more<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMore<T> {
+        One(T),
+        More(Vec<T>),
+    }
+    Ok(match OneOrMore::deserialize(deserializer)? {
+        OneOrMore::One(value) => vec![value],
+        OneOrMore::More(values) => values,
+    })
+}
+
+fn expressions<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Expr>, D::Error> {
+    one_or_more::<D, String>(deserializer)?
+        .into_iter()
+        .map(|source| Expr::try_from(source).map_err(de::Error::custom))
+        .collect()
+}
+
+impl Param {
+    pub fn validate(&self) -> Result<(), String> {
+        let invalid = |msg: &str| Err(format!("param '{}': {msg}", self.name));
+        let components = match &self.driver {
+            Driver::Value(value) => vec![value.len()],
+            Driver::Expression(exprs) => vec![exprs.len()],
+            Driver::Keyframes { keys, .. } => {
+                if keys.is_empty() {
+                    return invalid("needs at least one key");
+                }
+                if keys.iter().any(|k| !k.time.is_finite()) {
+                    return invalid("key times must be finite");
+                }
+                if keys.windows(2).any(|k| k[1].time <= k[0].time) {
+                    return invalid("key times must be increasing");
+                }
+                keys.iter().map(|k| k.value.len()).collect()
+            }
+        };
+        if components.iter().any(|n| !(1..=4).contains(n)) {
+            return invalid("values need 1 to 4 components");
+        }
+        Ok(())
+    }
+
+    pub fn evaluate(&self, frame: &FrameTime) -> Vec4 {
+        match &self.driver {
+            Driver::Value(value) => to_vec4(value.iter().copied()),
+            Driver::Expression(exprs) => to_vec4(exprs.iter().map(|e| e.evaluate(frame))),
+            Driver::Keyframes { keys, repeat } => {
+                let (first, last) = (&keys[0], &keys[keys.len() - 1]);
+                let mut time = frame.time;
+                if *repeat && last.time > first.time {
+                    time = first.time + (time - first.time).rem_euclid(last.time - first.time);
+                }
+                let next = keys.iter().position(|k| k.time > time);
+                match next {
+                    Some(0) => to_vec4(first.value.iter().copied()),
+                    None => to_vec4(last.value.iter().copied()),
+                    Some(i) => {
+                        let (a, b) = (&keys[i - 1], &keys[i]);
+                        let t = (time - a.time) / (b.time - a.time);
+                        let a = to_vec4(a.value.iter().copied());
+                        let b = to_vec4(b.value.iter().copied());
+                        a.lerp(b, t)
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn to_vec4(components: impl Iterator<Item = f32>) -> Vec4 {
+    let mut v = [0.0; 4];
+    for (c, x) in v.iter_mut().zip(components) {
+        *c = x;
+    }
+    Vec4::from_array(v)
+}
+
+/// The parameter values of one frame.
+#[derive(Debug, Clone, Default)]
+pub struct ParamValues {
+    pub frame: FrameTime,
+    names: Vec<String>,
+    values: Vec<Vec4>,
+}
+
+impl ParamValues {
+    pub fn evaluate(params: &[Param], frame: FrameTime) -> Self {
+        ParamValues {
+            frame,
+            names: params.iter().map(|p| p.name.clone()).collect(),
+            values: params.iter().map(|p| p.evaluate(&frame)).collect(),
+        }
+    }
+
+    /// Whether both hold the same values, regardless of the frame.
+    pub fn same_values(&self, other: &ParamValues) -> bool {
+        self.names == other.names && self.values == other.values
+    }
+
+    pub fn get(&self, name: &str) -> Option<Vec4> {
+        let index = self.names.iter().position(|n| n == name)?;
+        Some(self.values[index])
+    }
+
+    /// The contents of `globals.params`.
+    pub fn to_gpu(&self) -> [[f32; 4]; MAX_PARAMS] {
+        let mut gpu = [[0.0; 4]; MAX_PARAMS];
+        for (g, v) in gpu.iter_mut().zip(&self.values) {
+            *g = v.to_array();
+        }
+        gpu
+    }
+}
+
+/// A number or vector in a scene file, either written out or the name of a `Param`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value<T> {
+    Const(T),
+    /// Holds the value of the parameter as of the last `update`.
+    Param {
+        name: String,
+        value: T,
+    },
+}
+
+impl<T: Copy + FromParam> Value<T> {
+    pub fn get(&self) -> T {
+        match self {
+            Value::Const(value) | Value::Param { value, .. } => *value,
+        }
+    }
+
+    /// The parameter name, `None` for constants.
+    pub fn param(&self) -> Option<&str> {
+        match self {
+            Value::Const(_) => None,
+            Value::Param { name, .. } => Some(name),
+        }
+    }
+
+    pub fn update(&mut self, values: &ParamValues) {
+        if let Value::Param { name, value } = self {
+            if let Some(v) = values.get(name) {
+                *value = T::from_param(v);
+            }
+        }
+    }
+}
+
+impl<T: Default> Default for Value<T> {
+    fn default() -> Self {
+        Value::Const(T::default())
+    }
+}
+
+impl<'de, T: Deserialize<'de> + Default> Deserialize<'de> for Value<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw<T> {
+            Const(T),
+            Param(String),
+        }
+        Ok(match Raw::deserialize(deserializer)? {
+            Raw::Const(value) => Value::Const(value),
+            Raw::Param(name) => Value::Param {
+                name,
+                value: T::default(),
+            },
+        })
+    }
+}
+
+/// Types a `Value` can take from the components of a parameter.
+pub trait FromParam {
+    fn from_param(v: Vec4) -> Self;
+}
+
+impl FromParam for f32 {
+    fn from_param(v: Vec4) -> Self {
+        v.x
+    }
+}
+
+impl FromParam for Vec3 {
+    fn from_param(v: Vec4) -> Self {
+        v.truncate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(json: &str) -> Param {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn at(param: &Param, time: f32) -> Vec4 {
+        param.evaluate(&FrameTime {
+            time,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn values_and_expressions() {
+        let value = param(r#"{"name": "a", "value": 0.5}"#);
+        assert_eq!(at(&value, 3.0), Vec4::new(0.5, 0.0, 0.0, 0.0));
+        let vector = param(r#"{"name": "a", "value": [1, 2, 3]}"#);
+        assert_eq!(at(&vector, 3.0), Vec4::new(1.0, 2.0, 3.0, 0.0));
+        let expression = param(r#"{"name": "a", "expression": ["t", "t * 2"]}"#);
+        assert_eq!(at(&expression, 3.0), Vec4::new(3.0, 6.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn invalid_expressions_fail_to_parse() {
+        let error = serde_json::from_str::<Param>(r#"{"name": "a", "expression": "t +"}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("missing operand"), "{error}");
+    }
+
+    #[test]
+    fn keyframes_interpolate_and_hold() {
+        let keys = param(
+            r#"{"name": "a", "keyframes": {"keys": [
+                {"time": 1, "value": [0, 10]},
+                {"time": 3, "value": [4, 20]},
+                {"time": 4, "value": [0, 0]}
+            ]}}"#,
+        );
+        assert!(keys.validate().is_ok());
+        assert_eq!(at(&keys, 0.0), Vec4::new(0.0, 10.0, 0.0, 0.0));
+        assert_eq!(at(&keys, 2.0), Vec4::new(2.0, 15.0, 0.0, 0.0));
+        assert_eq!(at(&keys, 3.5), Vec4::new(2.0, 10.0, 0.0, 0.0));
+        assert_eq!(at(&keys, 9.0), Vec4::ZERO);
+    }
+
+    #[test]
+    fn keyframes_loop() {
+        let keys = param(
+            r#"{"name": "a", "keyframes": {"loop": true, "keys": [
+                {"time": 1, "value": 0},
+                {"time": 3, "value": 4}
+            ]}}"#,
+        );
+        assert_eq!(at(&keys, 2.0).x, 2.0);
+        assert_eq!(at(&keys, 4.0).x, 2.0);
+        assert_eq!(at(&keys, 6.5).x, 3.0);
+        assert_eq!(at(&keys, 0.0).x, 2.0);
+    }
+
+    #[test]
+    fn validate_rejects_bad_keys() {
+        let error = |json: &str| param(json).validate().unwrap_err();
+        assert_eq!(
+            error(r#"{"name": "a", "keyframes": {"keys": []}}"#),
+            "param 'a': needs at least one key"
+        );
+        assert_eq!(
+            error(
+                r#"{"name": "a", "keyframes": {"keys": [
+                    {"time": 1, "value": 0}, {"time": 1, "value": 1}
+                ]}}"#
+            ),
+            "param 'a': key times must be increasing"
+        );
+        // JSON numbers past the range of `f32` parse as infinity.
+        assert_eq!(
+            error(
+                r#"{"name": "a", "keyframes": {"keys": [
+                    {"time": 0, "value": 0}, {"time": 1e39, "value": 1}
+                ]}}"#
+            ),
+            "param 'a': key times must be finite"
+        );
+        let mut nan = param(r#"{"name": "a", "keyframes": {"keys": [{"time": 0, "value": 0}]}}"#);
+        if let Driver::Keyframes { keys, .. } = &mut nan.driver {
+            keys[0].time = f32::NAN;
+        }
+        assert_eq!(
+            nan.validate().unwrap_err(),
+            "param 'a': key times must be finite"
+        );
+        assert_eq!(
+            error(r#"{"name": "a", "value": [1, 2, 3, 4, 5]}"#),
+            "param 'a': values need 1 to 4 components"
+        );
+        assert_eq!(
+            error(r#"{"name": "a", "value": []}"#),
+            "param 'a': values need 1 to 4 components"
+        );
+    }
+
+    #[test]
+    fn values_follow_their_param() {
+        let params = [param(
+            r#"{"name": "radius", "expression": ["t", "1", "2"]}"#,
+        )];
+        let values = ParamValues::evaluate(
+            &params,
+            FrameTime {
+                time: 0.5,
+                ..Default::default()
+            },
+        );
+        let mut radius: Value<f32> = serde_json::from_str(r#""radius""#).unwrap();
+        let mut offset: Value<Vec3> = serde_json::from_str(r#""radius""#).unwrap();
+        let mut constant: Value<f32> = serde_json::from_str("3").unwrap();
+        radius.update(&values);
+        offset.update(&values);
+        constant.update(&values);
+        assert_eq!(radius.get(), 0.5);
+        assert_eq!(offset.get(), Vec3::new(0.5, 1.0, 2.0));
+        assert_eq!(constant.get(), 3.0);
+        assert_eq!(constant.param(), None);
+    }
+}
@@ -0,0 +1,55 @@
+use glam::{IVec3, Vec2, Vec3};
+use std::fmt;
+
+use crate::camera::Camera;
+use crate::reference::{ray_marching, scene::closest_primitive};
+use crate::scene::Scene;
+
+/// What is under a pixel, found by marching the same ray as the GPU on the CPU.
+#[derive(Debug, Clone, Copy)]
+pub struct Pick {
+    pub position: Vec3,
+    pub normal: Vec3,
+    /// Distance from the camera along the view ray.
+    pub distance: f32,
+    pub material_index: usize,
+    /// Index of the primitive in a depth-first walk of the scene tree.
+    pub object_id: usize,
+    /// The `repeat` cell of the primitive, if it is repeated.
+    pub instance: Option<IVec3>,
+}
+
+/// Picks the surface seen through `coord`, a framebuffer position as in `gl_FragCoord`. Returns
+/// `None` when the ray reaches the sky.
+pub fn pick(scene: &Scene, camera: &Camera, coord: Vec2) -> Option<Pick> {
+    let ray = camera.ray_for_pixel(coord);
+    let hit = ray_marching::ray_march(scene, ray, camera.near, camera.far);
+    if !hit.hit {
+        return None;
+    }
+
+    let position = ray.origin + ray.direction * hit.dist;
+    let (object_id, instance) = closest_primitive(scene, position);
+    Some(Pick {
+        position,
+        normal: ray_marching::normal(scene, position),
+        distance: hit.dist,
+        material_index: hit.material_index,
+        object_id,
+        instance,
+    })
+}
+
+impl fmt::Display for Pick {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "object {}", self.object_id)?;
+        if let Some(cell) = self.instance {
+            write!(f, " cell {cell}")?;
+        }
+        write!(
+            f,
+            ", material {}, position {:.3}, normal {:.3}, distance {:.3}",
+            self.material_index, self.position, self.normal, self.distance
+        )
+    }
+}
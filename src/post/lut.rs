@@ -0,0 +1,195 @@
+use half::f16;
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Largest `LUT_3D_SIZE` accepted, as in the .cube specification.
+const MAX_SIZE: usize = 256;
+
+/// A 3D color lookup table from a .cube file, mapping sRGB encoded colors in [0, 1] to graded
+/// ones.
+#[derive(Debug, Clone)]
+pub struct Lut {
+    pub path: PathBuf,
+    /// Entries along each axis.
+    pub size: usize,
+    /// `size`³ entries, red changing fastest, then green, then blue.
+    pub entries: Vec<[f32; 3]>,
+}
+
+#[derive(Debug)]
+pub enum LutError {
+    Io(io::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for LutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LutError::Io(e) => write!(f, "cannot read LUT file: {e}"),
+            LutError::Invalid(msg) => write!(f, "invalid LUT file: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for LutError {}
+
+impl Lut {
+    pub fn load(path: &Path) -> Result<Lut, LutError> {
+        let text = fs::read_to_string(path).map_err(LutError::Io)?;
+        let (size, entries) = parse(&text).map_err(LutError::Invalid)?;
+        Ok(Lut {
+            path: path.to_owned(),
+            size,
+            entries,
+        })
+    }
+
+    /// The entries as the bits of RGBA16F texels, in the layout of a 3D image. Half floats keep
+    /// the precision of smooth gradients and any values outside [0, 1].
+    pub fn to_rgba16f(&self) -> Vec<u16> {
+        self.entries
+            .iter()
+            .flat_map(|&[r, g, b]| [r, g, b, 1.0].map(|c| f16::from_f32(c).to_bits()))
+            .collect()
+    }
+}
+
+/// Parses the 3D LUT subset of the Adobe .cube format.
+fn parse(text: &str) -> Result<(usize, Vec<[f32; 3]>), String> {
+    let mut size = None;
+    let mut entries = vec![];
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |msg: &str| format!("line {}: {msg}", i + 1);
+        let mut words = line.split_whitespace();
+        let keyword = words.next().unwrap_or_default();
+        match keyword {
+            "TITLE" => {}
+            "LUT_1D_SIZE" => return Err(invalid("1D LUTs are not supported")),
+            "LUT_3D_SIZE" => {
+                let n = words
+                    .next()
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .filter(|n| (2..=MAX_SIZE).contains(n))
+                    .ok_or_else(|| invalid(&format!("expected a size from 2 to {MAX_SIZE}")))?;
+                size = Some(n);
+            }
+            "DOMAIN_MIN" | "DOMAIN_MAX" => {
+                let expected = if keyword == "DOMAIN_MIN" { 0.0 } else { 1.0 };
+                let values = numbers(words).ok_or_else(|| invalid("expected 3 numbers"))?;
+                if values != [expected; 3] {
+                    return Err(invalid("only the domain from 0 to 1 is supported"));
+                }
+            }
+            _ => {
+                let values = numbers(line.split_whitespace())
+                    .ok_or_else(|| invalid(&format!("unexpected '{line}'")))?;
+                entries.push(values);
+            }
+        }
+    }
+
+    let size = size.ok_or("LUT_3D_SIZE is missing")?;
+    if entries.len() != size * size * size {
+        return Err(format!(
+            "expected {} entries for LUT_3D_SIZE {size}, found {}",
+            size * size * size,
+            entries.len()
+        ));
+    }
+    Ok((size, entries))
+}
+
+fn numbers<'a>(words: impl Iterator<Item = &'a str>) -> Option<[f32; 3]> {
+    let values = words
+        .map(|w| w.parse::<f32>().ok().filter(|x| x.is_finite()))
+        .collect::<Option<Vec<_>>>()?;
+    values.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A size 2 LUT with `header` lines before the entries of the identity.
+    fn cube(header: &str) -> String {
+        let entries: Vec<_> = (0..8)
+            .map(|i| format!("{} {} {}", i & 1, (i >> 1) & 1, (i >> 2) & 1))
+            .collect();
+        format!("{header}\n{}\n", entries.join("\n"))
+    }
+
+    #[test]
+    fn parses_a_cube_file() {
+        let text = cube(
+            "# comment\nTITLE \"identity\"\nLUT_3D_SIZE 2\nDOMAIN_MIN 0 0 0\nDOMAIN_MAX 1.0 1 1",
+        );
+        let (size, entries) = parse(&text).unwrap();
+        assert_eq!(size, 2);
+        assert_eq!(entries.len(), 8);
+        assert_eq!(entries[1], [1.0, 0.0, 0.0]);
+        assert_eq!(entries[6], [0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn rejects_malformed_sizes() {
+        for size in ["1", "257", "two", "-2", ""] {
+            let error = parse(&cube(&format!("LUT_3D_SIZE {size}"))).unwrap_err();
+            assert_eq!(error, "line 1: expected a size from 2 to 256", "{size}");
+        }
+        assert_eq!(parse(&cube("")).unwrap_err(), "LUT_3D_SIZE is missing");
+        assert_eq!(
+            parse(&cube("LUT_1D_SIZE 2")).unwrap_err(),
+            "line 1: 1D LUTs are not supported"
+        );
+    }
+
+    #[test]
+    fn rejects_a_wrong_entry_count() {
+        let mut text = cube("LUT_3D_SIZE 2");
+        text.push_str("0 0 0\n");
+        assert_eq!(
+            parse(&text).unwrap_err(),
+            "expected 8 entries for LUT_3D_SIZE 2, found 9"
+        );
+        assert_eq!(
+            parse(&cube("LUT_3D_SIZE 3")).unwrap_err(),
+            "expected 27 entries for LUT_3D_SIZE 3, found 8"
+        );
+        let error = parse(&cube("LUT_3D_SIZE 2\n0 0")).unwrap_err();
+        assert_eq!(error, "line 2: unexpected '0 0'");
+        let error = parse(&cube("LUT_3D_SIZE 2\n0 NaN 0")).unwrap_err();
+        assert_eq!(error, "line 2: unexpected '0 NaN 0'");
+    }
+
+    #[test]
+    fn accepts_only_the_unit_domain() {
+        for header in ["DOMAIN_MIN 0 0 -0.5", "DOMAIN_MAX 1 1 2"] {
+            let error = parse(&cube(&format!("LUT_3D_SIZE 2\n{header}"))).unwrap_err();
+            assert_eq!(error, "line 2: only the domain from 0 to 1 is supported");
+        }
+        let error = parse(&cube("LUT_3D_SIZE 2\nDOMAIN_MAX 1 1")).unwrap_err();
+        assert_eq!(error, "line 2: expected 3 numbers");
+    }
+
+    #[test]
+    fn converts_to_half_floats() {
+        let lut = Lut {
+            path: PathBuf::new(),
+            size: 1,
+            entries: vec![[0.25, 1.5, -0.125]],
+        };
+        let texel: Vec<_> = lut
+            .to_rgba16f()
+            .into_iter()
+            .map(|bits| f16::from_bits(bits).to_f32())
+            .collect();
+        assert_eq!(texel, [0.25, 1.5, -0.125, 1.0]);
+    }
+}
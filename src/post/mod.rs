@@ -0,0 +1,517 @@
+use half::f16;
+use std::{fmt, sync::Arc};
+use vulkano::{
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{AutoCommandBufferBuilder, CopyBufferToImageInfo, PrimaryAutoCommandBuffer},
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    device::Device,
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{graphics::viewport::Viewport, GraphicsPipeline, Pipeline},
+    render_pass::{Framebuffer, RenderPass},
+};
+
+use crate::error::AppError;
+use crate::render::{self, MyVertex, RenderSettings, HDR_FORMAT};
+use crate::shaders;
+use crate::tonemap;
+
+mod lut;
+
+pub use lut::{Lut, LutError};
+
+/// The composited image is kept in this format for FXAA, which reads it back in linear color.
+const LDR_FORMAT: Format = Format::R8G8B8A8_SRGB;
+
+/// A screen-space effect of the post chain, in the order they are applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    Bloom,
+    ChromaticAberration,
+    ColorGrade,
+    Vignette,
+    Grain,
+    Fxaa,
+}
+
+impl Effect {
+    pub const ALL: [Effect; 6] = [
+        Effect::Bloom,
+        Effect::ChromaticAberration,
+        Effect::ColorGrade,
+        Effect::Vignette,
+        Effect::Grain,
+        Effect::Fxaa,
+    ];
+
+    /// The `EFFECT_*` bit in `composite.glsl`, FXAA is a pass of its own.
+    fn bit(self) -> u32 {
+        match self {
+            Effect::Bloom => 1,
+            Effect::ChromaticAberration => 2,
+            Effect::ColorGrade => 4,
+            Effect::Vignette => 8,
+            Effect::Grain => 16,
+            Effect::Fxaa => 0,
+        }
+    }
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Effect::Bloom => write!(f, "bloom"),
+            Effect::ChromaticAberration => write!(f, "chromatic-aberration"),
+            Effect::ColorGrade => write!(f, "color-grade"),
+            Effect::Vignette => write!(f, "vignette"),
+            Effect::Grain => write!(f, "grain"),
+            Effect::Fxaa => write!(f, "fxaa"),
+        }
+    }
+}
+
+/// How the composite pass scales up a scene rendered below the output resolution, passed as
+/// the `UPSCALE_*` values in `composite.glsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Upscale {
+    Bilinear = 0,
+    /// Bilinear within surfaces, keeps the edges between them sharp.
+    EdgeAware = 1,
+}
+
+impl Upscale {
+    pub fn next(self) -> Self {
+        match self {
+            Upscale::Bilinear => Upscale::EdgeAware,
+            Upscale::EdgeAware => Upscale::Bilinear,
+        }
+    }
+}
+
+impl fmt::Display for Upscale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Upscale::Bilinear => write!(f, "bilinear"),
+            Upscale::EdgeAware => write!(f, "edge-aware"),
+        }
+    }
+}
+
+/// Which effects `PostChain` applies and how strongly. All are off by default.
+#[derive(Debug, Clone)]
+pub struct PostSettings {
+    pub enabled: Vec<Effect>,
+    /// Brightness above which HDR colors glow.
+    pub bloom_threshold: f32,
+    /// Share of the glow added to the image.
+    pub bloom_intensity: f32,
+    /// Spacing of the blur taps, in pixels of the quarter resolution bloom image.
+    pub bloom_radius: f32,
+    /// Offset of red and blue in the corners, as a fraction of the image size.
+    pub chromatic_aberration: f32,
+    /// The grade of `Effect::ColorGrade`, which leaves colors as they are without one.
+    pub lut: Option<Arc<Lut>>,
+    /// Exponent of the darkening towards the edges.
+    pub vignette: f32,
+    /// Brightness variation of the grain, relative to the pixel.
+    pub grain: f32,
+    pub upscale: Upscale,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        PostSettings {
+            enabled: vec![],
+            bloom_threshold: 1.0,
+            bloom_intensity: 0.3,
+            bloom_radius: 1.5,
+            chromatic_aberration: 0.01,
+            lut: None,
+            vignette: 0.25,
+            grain: 0.08,
+            upscale: Upscale::Bilinear,
+        }
+    }
+}
+
+impl PostSettings {
+    pub fn is_enabled(&self, effect: Effect) -> bool {
+        self.enabled.contains(&effect)
+    }
+
+    pub fn set(&mut self, effect: Effect, enabled: bool) {
+        self.enabled.retain(|e| *e != effect);
+        if enabled {
+            self.enabled.push(effect);
+        }
+    }
+
+    /// Switches `effect` on or off and returns whether it is now on.
+    pub fn toggle(&mut self, effect: Effect) -> bool {
+        let enabled = !self.is_enabled(effect);
+        self.set(effect, enabled);
+        enabled
+    }
+
+    /// The enabled effects in chain order, separated by commas as `--post` takes them.
+    pub fn enabled_list(&self) -> String {
+        Effect::ALL
+            .iter()
+            .filter(|e| self.is_enabled(**e))
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// The images the chain renders to in between, sized for one output extent.
+struct Targets {
+    extent: [u32; 2],
+    /// Quarter resolution, the blur goes back and forth between them.
+    bloom: [Arc<Framebuffer>; 2],
+    /// The composited image FXAA reads.
+    ldr: Arc<Framebuffer>,
+}
+
+/// The passes from the HDR image of the scene to the output: bloom, the composite pass, which
+/// tone maps and applies the other effects, and FXAA.
+pub struct PostChain {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    sampler: Arc<Sampler>,
+    hdr_render_pass: Arc<RenderPass>,
+    ldr_render_pass: Arc<RenderPass>,
+    output_render_pass: Arc<RenderPass>,
+    bloom: Arc<GraphicsPipeline>,
+    /// Composites into the LDR target for FXAA.
+    composite_ldr: Arc<GraphicsPipeline>,
+    /// Composites straight into the output.
+    composite: Arc<GraphicsPipeline>,
+    fxaa: Arc<GraphicsPipeline>,
+    /// Whether the output format leaves the sRGB encoding to the shaders.
+    encode_srgb: bool,
+    targets: Option<Targets>,
+    lut: Arc<ImageView>,
+    /// The LUT the image was made from, `None` for the identity.
+    lut_source: Option<Arc<Lut>>,
+    /// Copied into `lut` before the next draw.
+    lut_upload: Option<Subbuffer<[u16]>>,
+}
+
+impl PostChain {
+    /// A chain drawing into images of `format`.
+    pub fn new(
+        device: Arc<Device>,
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        format: Format,
+    ) -> Result<Self, AppError> {
+        let hdr_render_pass = render::render_pass(device.clone(), HDR_FORMAT)?;
+        let ldr_render_pass = render::render_pass(device.clone(), LDR_FORMAT)?;
+        let output_render_pass = render::render_pass(device.clone(), format)?;
+
+        let bloom_shader =
+            shaders::bloom::load(device.clone()).map_err(|e| AppError::Shader(e.to_string()))?;
+        let composite_shader = shaders::composite::load(device.clone())
+            .map_err(|e| AppError::Shader(e.to_string()))?;
+        let fxaa_shader =
+            shaders::fxaa::load(device.clone()).map_err(|e| AppError::Shader(e.to_string()))?;
+
+        let bloom = render::create_pipeline(device.clone(), hdr_render_pass.clone(), bloom_shader)?;
+        let composite_ldr = render::create_pipeline(
+            device.clone(),
+            ldr_render_pass.clone(),
+            composite_shader.clone(),
+        )?;
+        let composite =
+            render::create_pipeline(device.clone(), output_render_pass.clone(), composite_shader)?;
+        let fxaa =
+            render::create_pipeline(device.clone(), output_render_pass.clone(), fxaa_shader)?;
+
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(
+            device,
+            Default::default(),
+        ));
+
+        let (lut, lut_upload) = lut_image(&memory_allocator, None)?;
+
+        Ok(PostChain {
+            memory_allocator,
+            descriptor_set_allocator,
+            sampler,
+            hdr_render_pass,
+            ldr_render_pass,
+            output_render_pass,
+            bloom,
+            composite_ldr,
+            composite,
+            fxaa,
+            encode_srgb: !tonemap::is_srgb(format),
+            targets: None,
+            lut,
+            lut_source: None,
+            lut_upload: Some(lut_upload),
+        })
+    }
+
+    /// The render pass of the output framebuffers `draw` takes.
+    pub fn render_pass(&self) -> &Arc<RenderPass> {
+        &self.output_render_pass
+    }
+
+    /// Records the passes from `hdr` to `output`. The scene fills `scene_extent` pixels of `hdr`
+    /// from the top left, which is scaled up if it is smaller than `output`. `frame` seeds the
+    /// grain.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        output: Arc<Framebuffer>,
+        hdr: Arc<ImageView>,
+        scene_extent: [u32; 2],
+        vertex_buffer: Subbuffer<[MyVertex]>,
+        settings: &RenderSettings,
+        post: &PostSettings,
+        frame: u32,
+    ) -> Result<(), AppError> {
+        let extent = output.extent();
+        let targets = match self.targets.take() {
+            Some(targets) if targets.extent == extent => targets,
+            _ => self.create_targets(extent)?,
+        };
+        let lut_changed = match (&self.lut_source, &post.lut) {
+            (Some(a), Some(b)) => !Arc::ptr_eq(a, b),
+            (a, b) => a.is_some() != b.is_some(),
+        };
+        if lut_changed {
+            let (lut, upload) = lut_image(&self.memory_allocator, post.lut.as_deref())?;
+            self.lut = lut;
+            self.lut_source = post.lut.clone();
+            self.lut_upload = Some(upload);
+        }
+        if let Some(upload) = self.lut_upload.take() {
+            builder.copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(
+                upload,
+                self.lut.image().clone(),
+            ))?;
+        }
+        let hdr_extent = hdr.image().extent();
+        let scene_scale = [0, 1].map(|i| scene_extent[i] as f32 / hdr_extent[i] as f32);
+
+        let bloom = post.is_enabled(Effect::Bloom);
+        if bloom {
+            let [a, b] = &targets.bloom;
+            let size = a.extent().map(|x| x as f32);
+            let params = |direction: [f32; 2], prefilter: bool| shaders::bloom::Params {
+                target_size: size,
+                source_scale: if prefilter { scene_scale } else { [1.0; 2] },
+                direction,
+                threshold: post.bloom_threshold,
+                prefilter: prefilter as u32,
+            };
+            let radius = post.bloom_radius;
+            self.pass(
+                builder,
+                &self.bloom,
+                a,
+                [hdr.clone()],
+                params([0.0; 2], true),
+                &vertex_buffer,
+            )?;
+            self.pass(
+                builder,
+                &self.bloom,
+                b,
+                [attachment(a)],
+                params([radius, 0.0], false),
+                &vertex_buffer,
+            )?;
+            self.pass(
+                builder,
+                &self.bloom,
+                a,
+                [attachment(b)],
+                params([0.0, radius], false),
+                &vertex_buffer,
+            )?;
+        }
+
+        let fxaa = post.is_enabled(Effect::Fxaa);
+        let (pipeline, target, encode_srgb) = if fxaa {
+            (&self.composite_ldr, &targets.ldr, false)
+        } else {
+            (&self.composite, &output, self.encode_srgb)
+        };
+        let effects = Effect::ALL
+            .iter()
+            .filter(|e| post.is_enabled(**e))
+            .fold(0, |bits, e| bits | e.bit());
+        // Without bloom its images may never have been drawn, any image will do in their place.
+        let bloom_image = if bloom {
+            attachment(&targets.bloom[0])
+        } else {
+            hdr.clone()
+        };
+        self.pass(
+            builder,
+            pipeline,
+            target,
+            [hdr, bloom_image, self.lut.clone()],
+            shaders::composite::Params {
+                output_size: extent.map(|x| x as f32),
+                scene_size: scene_extent.map(|x| x as f32),
+                upscale: post.upscale as u32,
+                exposure: settings.exposure.exp2(),
+                tonemap: settings.tonemap as u32,
+                encode_srgb: encode_srgb as u32,
+                effects,
+                bloom_intensity: post.bloom_intensity,
+                chromatic_aberration: post.chromatic_aberration,
+                vignette: post.vignette,
+                grain: post.grain,
+                frame,
+            },
+            &vertex_buffer,
+        )?;
+
+        if fxaa {
+            self.pass(
+                builder,
+                &self.fxaa,
+                &output,
+                [attachment(&targets.ldr)],
+                shaders::fxaa::Params {
+                    encode_srgb: self.encode_srgb as u32,
+                },
+                &vertex_buffer,
+            )?;
+        }
+        self.targets = Some(targets);
+        Ok(())
+    }
+
+    fn create_targets(&self, extent: [u32; 2]) -> Result<Targets, AppError> {
+        let quarter = extent.map(|x| x.div_ceil(4));
+        let bloom_target = || {
+            render::target_framebuffer(
+                self.memory_allocator.clone(),
+                self.hdr_render_pass.clone(),
+                quarter,
+            )
+        };
+        Ok(Targets {
+            extent,
+            bloom: [bloom_target()?, bloom_target()?],
+            ldr: render::target_framebuffer(
+                self.memory_allocator.clone(),
+                self.ldr_render_pass.clone(),
+                extent,
+            )?,
+        })
+    }
+
+    /// Draws the full-screen quad into `target`, with `images` bound in order for sampling.
+    fn pass<Pc: BufferContents>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: &Arc<GraphicsPipeline>,
+        target: &Arc<Framebuffer>,
+        images: impl IntoIterator<Item = Arc<ImageView>>,
+        push_constants: Pc,
+        vertex_buffer: &Subbuffer<[MyVertex]>,
+    ) -> Result<(), AppError> {
+        let descriptor_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            pipeline.layout().set_layouts()[0].clone(),
+            images.into_iter().enumerate().map(|(i, image)| {
+                WriteDescriptorSet::image_view_sampler(i as u32, image, self.sampler.clone())
+            }),
+            [],
+        )?;
+
+        builder.push_constants(pipeline.layout().clone(), 0, push_constants)?;
+
+        let [width, height] = target.extent();
+        render::record_draw(
+            builder,
+            target.clone(),
+            Viewport {
+                offset: [0.0, 0.0],
+                extent: [width as f32, height as f32],
+                depth_range: 0.0..=1.0,
+            },
+            pipeline.clone(),
+            vertex_buffer.clone(),
+            descriptor_set,
+        )
+    }
+}
+
+fn attachment(framebuffer: &Arc<Framebuffer>) -> Arc<ImageView> {
+    framebuffer.attachments()[0].clone()
+}
+
+/// A 3D image for `lut`, or the identity if there is none, and the buffer to fill it from.
+fn lut_image(
+    memory_allocator: &Arc<StandardMemoryAllocator>,
+    lut: Option<&Lut>,
+) -> Result<(Arc<ImageView>, Subbuffer<[u16]>), AppError> {
+    let (size, texels) = match lut {
+        Some(lut) => (lut.size as u32, lut.to_rgba16f()),
+        None => (2, identity_rgba16f()),
+    };
+    let image = Image::new(
+        memory_allocator.clone(),
+        ImageCreateInfo {
+            image_type: ImageType::Dim3d,
+            format: Format::R16G16B16A16_SFLOAT,
+            extent: [size, size, size],
+            usage: ImageUsage::SAMPLED | ImageUsage::TRANSFER_DST,
+            ..Default::default()
+        },
+        AllocationCreateInfo::default(),
+    )
+    .map_err(|e| AppError::Render(e.to_string()))?;
+
+    let upload = Buffer::from_iter(
+        memory_allocator.clone(),
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_SRC,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        texels,
+    )
+    .map_err(|e| AppError::Render(e.to_string()))?;
+
+    Ok((ImageView::new_default(image)?, upload))
+}
+
+/// The 2x2x2 LUT that maps every color to itself.
+fn identity_rgba16f() -> Vec<u16> {
+    (0..8)
+        .flat_map(|i| {
+            [i & 1, (i >> 1) & 1, (i >> 2) & 1, 1].map(|c| f16::from_f32(c as f32).to_bits())
+        })
+        .collect()
+}
@@ -0,0 +1,96 @@
+use std::fmt;
+use vulkano::shader::SpecializationConstant;
+
+/// Presets for how carefully the scene is ray marched. Their `MarchSettings` are specialization
+/// constants of `ray_marching.glsl`, so each preset needs its own pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quality {
+    Low,
+    Medium,
+    High,
+    Ultra,
+}
+
+/// The limits of the marching loops in `ray_marching.glsl`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarchSettings {
+    /// Steps a ray takes at most before it counts as a miss.
+    pub max_steps: u32,
+    /// Distance to the surface at which a ray counts as a hit.
+    pub hit_precision: f32,
+    /// How far reflections, refractions and directional light shadows reach.
+    pub max_distance: f32,
+    /// Steps of a soft shadow ray.
+    pub shadow_steps: u32,
+    /// Samples along the normal in `occlusion()`, spread over the same distance at every
+    /// preset.
+    pub occlusion_samples: u32,
+}
+
+impl Quality {
+    pub fn next(self) -> Self {
+        match self {
+            Quality::Low => Quality::Medium,
+            Quality::Medium => Quality::High,
+            Quality::High => Quality::Ultra,
+            Quality::Ultra => Quality::Low,
+        }
+    }
+
+    pub fn march_settings(self) -> MarchSettings {
+        match self {
+            Quality::Low => MarchSettings {
+                max_steps: 96,
+                hit_precision: 0.004,
+                max_distance: 50.0,
+                shadow_steps: 16,
+                occlusion_samples: 2,
+            },
+            Quality::Medium => MarchSettings {
+                max_steps: 160,
+                hit_precision: 0.002,
+                max_distance: 75.0,
+                shadow_steps: 32,
+                occlusion_samples: 3,
+            },
+            // The defaults in `ray_marching.glsl` and the CPU reference.
+            Quality::High => MarchSettings {
+                max_steps: 300,
+                hit_precision: 0.001,
+                max_distance: 100.0,
+                shadow_steps: 64,
+                occlusion_samples: 5,
+            },
+            Quality::Ultra => MarchSettings {
+                max_steps: 600,
+                hit_precision: 0.0005,
+                max_distance: 150.0,
+                shadow_steps: 128,
+                occlusion_samples: 6,
+            },
+        }
+    }
+
+    /// The values of the `constant_id`s declared in `ray_marching.glsl`.
+    pub fn specialization(self) -> [(u32, SpecializationConstant); 5] {
+        let s = self.march_settings();
+        [
+            (0, SpecializationConstant::I32(s.max_steps as i32)),
+            (1, SpecializationConstant::F32(s.hit_precision)),
+            (2, SpecializationConstant::F32(s.max_distance)),
+            (3, SpecializationConstant::I32(s.shadow_steps as i32)),
+            (4, SpecializationConstant::I32(s.occlusion_samples as i32)),
+        ]
+    }
+}
+
+impl fmt::Display for Quality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Quality::Low => write!(f, "low"),
+            Quality::Medium => write!(f, "medium"),
+            Quality::High => write!(f, "high"),
+            Quality::Ultra => write!(f, "ultra"),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use glam::{vec2, vec4, Vec3};
+use std::f32::consts::PI;
+
+use crate::scene::Material;
+
+/// Below this the GGX lobe gets too narrow for a float.
+pub const MIN_ROUGHNESS: f32 = 0.045;
+/// Reflectance of the clearcoat at normal incidence.
+pub const DIELECTRIC_F0: f32 = 0.04;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Surface {
+    pub diffuse: Vec3,
+    pub transmission: Vec3,
+    pub f0: Vec3,
+    pub roughness: f32,
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    pub emissive: Vec3,
+    pub ior: f32,
+    pub absorption: Vec3,
+}
+
+pub fn ior_f0(ior: f32) -> f32 {
+    let r = (ior - 1.0) / (ior + 1.0);
+    r * r
+}
+
+pub fn surface(material: &Material, color: Vec3) -> Surface {
+    let base_color = color.clamp(Vec3::ZERO, Vec3::ONE);
+    let dielectric = base_color * (1.0 - material.metallic);
+    Surface {
+        diffuse: dielectric * (1.0 - material.transmission),
+        transmission: dielectric * material.transmission,
+        f0: Vec3::splat(ior_f0(material.ior)).lerp(base_color, material.metallic),
+        roughness: material.roughness.max(MIN_ROUGHNESS),
+        clearcoat: material.clearcoat,
+        clearcoat_roughness: material.clearcoat_roughness.max(MIN_ROUGHNESS),
+        emissive: material.emissive,
+        ior: material.ior,
+        absorption: material.absorption(),
+    }
+}
+
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let sin_t2 = eta * eta * (1.0 - cos_i * cos_i);
+    if sin_t2 >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin_t2).sqrt();
+    let rs = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let rp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+pub fn d_ggx(n_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_h * n_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+pub fn v_smith(n_v: f32, n_l: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let v = n_l * (n_v * n_v * (1.0 - a2) + a2).sqrt();
+    let l = n_v * (n_l * n_l * (1.0 - a2) + a2).sqrt();
+    0.5 / (v + l).max(1e-5)
+}
+
+pub fn f_schlick(f0: Vec3, cos_theta: f32) -> Vec3 {
+    f0 + (1.0 - f0) * (1.0 - cos_theta.clamp(0.0, 1.0)).powf(5.0)
+}
+
+pub fn env_brdf(f0: Vec3, roughness: f32, n_v: f32) -> Vec3 {
+    let r = roughness * vec4(-1.0, -0.0275, -0.572, 0.022) + vec4(1.0, 0.0425, 1.04, -0.04);
+    let a004 = (r.x * r.x).min((-9.28 * n_v).exp2()) * r.x + r.y;
+    let ab = vec2(-1.04, 1.04) * a004 + vec2(r.z, r.w);
+    f0 * ab.x + ab.y
+}
+
+pub fn clearcoat_fresnel(s: &Surface, cos_theta: f32) -> f32 {
+    s.clearcoat * f_schlick(Vec3::splat(DIELECTRIC_F0), cos_theta).x
+}
+
+pub fn brdf(s: &Surface, n: Vec3, v: Vec3, l: Vec3) -> Vec3 {
+    let h = (v + l).normalize();
+    let n_v = n.dot(v).max(1e-4);
+    let n_l = n.dot(l).max(0.0);
+    let n_h = n.dot(h).max(0.0);
+    let v_h = v.dot(h).max(0.0);
+
+    let alpha = s.roughness * s.roughness;
+    let f = f_schlick(s.f0, v_h);
+    let specular = d_ggx(n_h, alpha) * v_smith(n_v, n_l, alpha) * f;
+    let diffuse = (1.0 - f) * s.diffuse / PI;
+
+    let coat_alpha = s.clearcoat_roughness * s.clearcoat_roughness;
+    let fc = clearcoat_fresnel(s, v_h);
+    let coat = d_ggx(n_h, coat_alpha) * v_smith(n_v, n_l, coat_alpha) * fc;
+
+    (diffuse + specular) * (1.0 - fc) + coat
+}
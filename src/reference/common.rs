@@ -0,0 +1,146 @@
+use glam::{vec2, vec3, Vec3, Vec3Swizzles};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+    pub dist: f32,
+    pub material_index: usize,
+    pub color: Vec3,
+    pub hit: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample {
+    pub direction: Vec3,
+    pub dist: f32,
+    pub radiance: Vec3,
+}
+
+pub fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a * (1.0 - t) + b * t
+}
+
+pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+pub fn sphere_sdf(p: Vec3, r: f32) -> f32 {
+    p.length() - r
+}
+
+pub fn box_sdf(p: Vec3, dimension: Vec3, corner_radius: f32) -> f32 {
+    let q = p.abs() - dimension + corner_radius;
+    q.max(Vec3::ZERO).length() + q.max_element().min(0.0) - corner_radius
+}
+
+pub fn cylinder_sdf(p: Vec3, radius: f32, height: f32, corner_radius: f32) -> f32 {
+    let d = vec2(p.xz().length(), p.y.abs()) - vec2(radius, height * 0.5) + corner_radius;
+    d.max(glam::Vec2::ZERO).length() + d.x.max(d.y).min(0.0) - corner_radius
+}
+
+pub fn line_sdf(p: Vec3, a: Vec3, b: Vec3, r: f32) -> f32 {
+    let pa = p - a;
+    let ba = b - a;
+    let h = (pa.dot(ba) / ba.dot(ba)).clamp(0.0, 1.0);
+    (pa - h * ba).length() - r
+}
+
+pub fn plane_sdf(p: Vec3, n: Vec3, offset: f32) -> f32 {
+    p.dot(n) - offset
+}
+
+/// Objects farther than this from their bounding box are replaced by the distance to it.
+pub const BOUND_MARGIN: f32 = 1.0;
+
+pub fn smooth_min(d1: f32, d2: f32, k: f32) -> f32 {
+    let h = (0.5 + 0.5 * (d2 - d1) / k).clamp(0., 1.);
+    mix(d2, d1, h) - k * h * (1. - h)
+}
+
+pub fn repeat_xz(p: Vec3, s: f32, lima: f32, limb: f32) -> Vec3 {
+    let cell = repeat_xz_cell(p, s, lima, limb);
+    vec3(p.x - s * cell.x, p.y, p.z - s * cell.z)
+}
+
+pub fn repeat_xz_cell(p: Vec3, s: f32, lima: f32, limb: f32) -> Vec3 {
+    vec3(
+        (p.x / s).round().clamp(lima, limb),
+        0.,
+        (p.z / s).round().clamp(lima, limb),
+    )
+}
+
+pub fn cell_color(cell: Vec3) -> Vec3 {
+    cell.abs() + 0.3
+}
+
+pub fn waves(p: Vec3) -> f32 {
+    let v = 28.0 * (p.x * 4.).sin() + 28.0 * (p.y * 4.).sin() + 28.0 * (p.z * 4.).sin();
+    0.2 * (-1. + 2. * smoothstep(-0.2, 0.2, v))
+}
+
+pub fn hit_union(a: Hit, b: Hit) -> Hit {
+    if a.dist < b.dist {
+        a
+    } else {
+        b
+    }
+}
+
+pub fn hit_smooth_union(a: Hit, b: Hit, k: f32) -> Hit {
+    let h = (0.5 + 0.5 * (b.dist - a.dist) / k).clamp(0., 1.);
+    Hit {
+        dist: smooth_min(a.dist, b.dist, k),
+        material_index: if h > 0.5 {
+            a.material_index
+        } else {
+            b.material_index
+        },
+        color: b.color.lerp(a.color, h),
+        hit: true,
+    }
+}
+
+pub fn hit_intersection(a: Hit, b: Hit) -> Hit {
+    if a.dist > b.dist {
+        a
+    } else {
+        b
+    }
+}
+
+pub fn hit_smooth_intersection(a: Hit, b: Hit, k: f32) -> Hit {
+    let h = (0.5 - 0.5 * (b.dist - a.dist) / k).clamp(0., 1.);
+    let d = mix(b.dist, a.dist, h) + k * h * (1. - h);
+    Hit {
+        dist: d,
+        material_index: if h > 0.5 {
+            a.material_index
+        } else {
+            b.material_index
+        },
+        color: b.color.lerp(a.color, h),
+        hit: true,
+    }
+}
+
+pub fn hit_subtraction(a: Hit, b: Hit) -> Hit {
+    Hit {
+        dist: a.dist.max(-b.dist),
+        ..a
+    }
+}
+
+pub fn hit_smooth_subtraction(a: Hit, b: Hit, k: f32) -> Hit {
+    let h = (0.5 - 0.5 * (a.dist + b.dist) / k).clamp(0., 1.);
+    Hit {
+        dist: mix(a.dist, -b.dist, h) + k * h * (1. - h),
+        ..a
+    }
+}
@@ -0,0 +1,63 @@
+//! CPU implementation of the fragment shader, mirroring `common.glsl`, `scene.glsl`,
+//! `brdf.glsl`, `ray_marching.glsl` and `tonemap.glsl` function by function so individual pixels can be compared against the GPU.
+
+use glam::{vec2, Vec3};
+use std::thread;
+
+use crate::camera::Camera;
+use crate::params::FrameTime;
+use crate::render::RenderSettings;
+use crate::scene::Scene;
+
+pub mod brdf;
+pub mod common;
+pub mod ray_marching;
+pub mod scene;
+pub mod tonemap;
+
+pub use ray_marching::run;
+
+/// Renders a whole frame on all available cores, with the scene animated to `frame` and tone
+/// mapped as `settings` say, and returns tightly packed RGBA8 rows in the same layout as
+/// `HeadlessRenderer::render`.
+pub fn render(
+    scene: &Scene,
+    camera: &Camera,
+    frame: FrameTime,
+    settings: &RenderSettings,
+    width: u32,
+    height: u32,
+) -> Vec<u8> {
+    let scene = &scene.at(frame);
+    let mut camera = camera.clone();
+    camera.resize(width as usize, height as usize);
+    let camera = &camera;
+
+    let mut pixels = vec![0u8; (width * height * 4) as usize];
+
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let rows_per_chunk = (height as usize).div_ceil(threads).max(1);
+    let row_len = width as usize * 4;
+
+    thread::scope(|s| {
+        for (chunk_index, chunk) in pixels.chunks_mut(rows_per_chunk * row_len).enumerate() {
+            s.spawn(move || {
+                for (i, row) in chunk.chunks_mut(row_len).enumerate() {
+                    let y = chunk_index * rows_per_chunk + i;
+                    for (x, pixel) in row.chunks_mut(4).enumerate() {
+                        let coord = vec2(x as f32 + 0.5, y as f32 + 0.5);
+                        let color = tonemap::display(run(scene, coord, camera), settings);
+                        pixel.copy_from_slice(&to_rgba8(color));
+                    }
+                }
+            });
+        }
+    });
+
+    pixels
+}
+
+fn to_rgba8(color: Vec3) -> [u8; 4] {
+    let c = (color.clamp(Vec3::ZERO, Vec3::ONE) * 255.0).round();
+    [c.x as u8, c.y as u8, c.z as u8, 255]
+}
@@ -0,0 +1,373 @@
+use glam::{vec3, Vec2, Vec3};
+use std::f32::consts::PI;
+
+use super::brdf::{brdf, clearcoat_fresnel, env_brdf, fresnel_dielectric, surface, Surface};
+use super::common::*;
+use super::scene::sdf;
+use crate::camera::Camera;
+use crate::light::{Light, LightKind};
+use crate::scene::Scene;
+
+// The high `Quality` preset, which the specialization constants of `ray_marching.glsl` default to.
+pub const MAX_STEPS: usize = 300;
+pub const HIT_PRECISION: f32 = 0.001;
+pub const MAX_DISTANCE: f32 = 100.0;
+pub const SHADOW_STEPS: usize = 64;
+pub const OCCLUSION_SAMPLES: usize = 5;
+
+pub fn normal(scene: &Scene, p: Vec3) -> Vec3 {
+    let k = 0.5773 * 0.0005;
+    let xyy = vec3(1., -1., -1.);
+    let yyx = vec3(-1., -1., 1.);
+    let yxy = vec3(-1., 1., -1.);
+    let xxx = vec3(1., 1., 1.);
+
+    let eval = |d: Vec3| {
+        sdf(
+            scene,
+            Ray {
+                origin: p,
+                direction: d,
+            },
+            k,
+        )
+        .dist
+    };
+
+    (xyy * eval(xyy) + yyx * eval(yyx) + yxy * eval(yxy) + xxx * eval(xxx)).normalize()
+}
+
+pub fn occlusion(scene: &Scene, pos: Vec3, nor: Vec3) -> f32 {
+    let spacing = 4.0 / OCCLUSION_SAMPLES.saturating_sub(1).max(1) as f32;
+    let mut occ = 0.0;
+    let mut total = 0.0;
+    for i in 0..OCCLUSION_SAMPLES {
+        let fi = i as f32 * spacing;
+        let hr = 0.02 + 0.025 * (fi * fi);
+        let hit = sdf(
+            scene,
+            Ray {
+                origin: pos,
+                direction: nor,
+            },
+            hr,
+        );
+        let sca = 0.85f32.powf(i as f32 * spacing);
+        occ += -(hit.dist - hr) * sca;
+        total += sca;
+    }
+    let high_total = (1.0 - 0.85f32.powi(5)) / 0.15;
+    1.0 - (occ / total * high_total).clamp(0.0, 1.0)
+}
+
+pub fn shadow(scene: &Scene, ray: Ray, k: f32, max_t: f32) -> f32 {
+    let mut res: f32 = 1.0;
+    let mut t = 0.01;
+    for _ in 0..SHADOW_STEPS {
+        let h = sdf(scene, ray, t).dist;
+        res = res.min(k * (h.max(0.0) / t));
+        if res < 0.0001 {
+            break;
+        }
+        t += h.clamp(0.01, 5.0);
+        if t > max_t {
+            break;
+        }
+    }
+    res
+}
+
+pub fn ray_march(scene: &Scene, ray: Ray, t_min: f32, t_max: f32) -> Hit {
+    let mut t = t_min;
+    for _ in 0..MAX_STEPS {
+        if t > t_max {
+            break;
+        }
+        let h = sdf(scene, ray, t);
+        t += h.dist;
+        if h.dist < HIT_PRECISION {
+            return Hit {
+                dist: t,
+                hit: true,
+                ..h
+            };
+        }
+    }
+    Hit {
+        dist: t,
+        material_index: 0,
+        color: Vec3::ZERO,
+        hit: false,
+    }
+}
+
+pub fn ray_march_inside(scene: &Scene, ray: Ray, t_max: f32) -> Hit {
+    let mut t = 0.0;
+    for _ in 0..MAX_STEPS {
+        if t > t_max {
+            break;
+        }
+        let h = sdf(scene, ray, t);
+        t -= h.dist;
+        if -h.dist < HIT_PRECISION {
+            return Hit {
+                dist: t,
+                hit: true,
+                ..h
+            };
+        }
+    }
+    Hit {
+        dist: t,
+        material_index: 0,
+        color: Vec3::ZERO,
+        hit: false,
+    }
+}
+
+pub fn pass_through(
+    scene: &Scene,
+    ray: &mut Ray,
+    weight: &mut Vec3,
+    p: Vec3,
+    n: Vec3,
+    s: &Surface,
+) -> bool {
+    *ray = Ray {
+        origin: p - n * 0.01,
+        direction: refract(ray.direction, n, 1.0 / s.ior),
+    };
+
+    for _ in 0..5 {
+        let hit = ray_march_inside(scene, *ray, MAX_DISTANCE);
+        if !hit.hit {
+            return false;
+        }
+        *weight *= (-s.absorption * hit.dist).exp();
+
+        let q = ray.origin + ray.direction * hit.dist;
+        let exit_normal = normal(scene, q);
+        let fresnel = fresnel_dielectric(ray.direction.dot(exit_normal), s.ior);
+        if fresnel < 1.0 {
+            *weight *= 1.0 - fresnel;
+            *ray = Ray {
+                origin: q + exit_normal * 0.01,
+                direction: refract(ray.direction, -exit_normal, s.ior),
+            };
+            return true;
+        }
+        *ray = Ray {
+            origin: q - exit_normal * 0.01,
+            direction: reflect(ray.direction, exit_normal),
+        };
+    }
+    false
+}
+
+pub fn falloff(d: f32, range: f32) -> f32 {
+    let w = (1.0 - (d / range).powf(4.0)).clamp(0.0, 1.0);
+    w * w / (d * d).max(0.0001)
+}
+
+pub fn sample_light(light: &Light, p: Vec3) -> LightSample {
+    let radiance = light.color * light.intensity;
+    let (position, range) = match &light.kind {
+        LightKind::Directional { direction } => {
+            return LightSample {
+                direction: -direction.get().normalize_or(Vec3::NEG_Y),
+                dist: MAX_DISTANCE,
+                radiance,
+            };
+        }
+        LightKind::Point { position, range }
+        | LightKind::Spot {
+            position, range, ..
+        }
+        | LightKind::Area {
+            position, range, ..
+        } => (*position, *range),
+    };
+
+    let to_light = position - p;
+    let d = to_light.length();
+    let l = to_light / d;
+
+    match &light.kind {
+        LightKind::Area { radius, .. } => LightSample {
+            direction: l,
+            dist: (d - radius).max(0.0),
+            radiance: radiance * falloff(d.max(*radius), range),
+        },
+        LightKind::Spot {
+            direction,
+            inner_angle,
+            outer_angle,
+            ..
+        } => LightSample {
+            direction: l,
+            dist: d,
+            radiance: radiance
+                * falloff(d, range)
+                * smoothstep(
+                    outer_angle.to_radians().cos(),
+                    inner_angle.to_radians().cos(),
+                    (-l).dot(direction.get().normalize_or(Vec3::NEG_Y)),
+                ),
+        },
+        _ => LightSample {
+            direction: l,
+            dist: d,
+            radiance: radiance * falloff(d, range),
+        },
+    }
+}
+
+pub fn sky_color(direction: Vec3) -> Vec3 {
+    let sky = (vec3(0.5, 0.8, 1.) - 0.7 * direction.y).clamp(Vec3::ZERO, Vec3::ONE);
+    sky.lerp(vec3(0.5, 0.7, 0.9), (-10.0 * direction.y.max(0.0)).exp())
+}
+
+pub fn background(scene: &Scene, direction: Vec3) -> Vec3 {
+    let mut res = sky_color(direction);
+
+    for light in &scene.lights {
+        let LightKind::Directional { direction: sun } = &light.kind else {
+            continue;
+        };
+        let sundot = direction
+            .dot(-sun.get().normalize_or(Vec3::NEG_Y))
+            .clamp(0.0, 1.0);
+        let intensity = light.intensity;
+
+        res += 0.25 * vec3(1.0, 0.7, 0.4) * sundot.powf(5.0) * intensity;
+        res += 0.25 * vec3(1.0, 0.6, 0.6) * sundot.powf(64.0) * intensity;
+        res += 0.25 * vec3(1.0, 0.9, 0.6) * sundot.powf(512.0) * intensity;
+    }
+    res
+}
+
+pub fn path_trace(scene: &Scene, mut ray: Ray, res: Vec3, sky: Vec3, near: f32, far: f32) -> Vec3 {
+    let mut col = Vec3::ZERO;
+    let mut weight = Vec3::ONE;
+
+    for bounce in 0..3 {
+        let hit = if bounce == 0 {
+            ray_march(scene, ray, near, far)
+        } else {
+            ray_march(scene, ray, 0.0, MAX_DISTANCE)
+        };
+
+        if !hit.hit {
+            col += weight
+                * if bounce == 0 {
+                    res
+                } else {
+                    background(scene, ray.direction)
+                };
+            break;
+        }
+
+        let p = ray.origin + ray.direction * hit.dist;
+        let n = normal(scene, p);
+        let v = -ray.direction;
+        let occlusion = occlusion(scene, p, n);
+
+        let s = surface(&scene.materials[hit.material_index], hit.color);
+
+        let n_v = n.dot(v).max(1e-4);
+        let fc = clearcoat_fresnel(&s, n_v);
+        let env = env_brdf(s.f0, s.roughness, n_v);
+        let specular = env * (1.0 - fc);
+        let sharpness = 1.0 - s.roughness;
+        let coat_sharpness = 1.0 - s.clearcoat_roughness;
+
+        let ambient = sky * vec3(0.16, 0.20, 0.28) * occlusion;
+        let mut light = s.emissive
+            + ambient
+                * (s.diffuse * (1.0 - env) * (1.0 - fc)
+                    + specular * (1.0 - sharpness)
+                    + fc * (1.0 - coat_sharpness));
+
+        for source in &scene.lights {
+            let ls = sample_light(source, p);
+            let visibility = shadow(
+                scene,
+                Ray {
+                    origin: p + n * 0.0001,
+                    direction: ls.direction,
+                },
+                source.shadow_softness,
+                ls.dist,
+            );
+            let n_l = n.dot(ls.direction).clamp(0.0, 1.0);
+
+            light += brdf(&s, n, v, ls.direction)
+                * n_l
+                * PI
+                * ls.radiance
+                * vec3(
+                    visibility.powf(1.3),
+                    visibility.powf(1.2),
+                    visibility.powf(1.5),
+                );
+
+            if let LightKind::Directional { .. } = source.kind {
+                let indirect = 0.1
+                    * n.dot((ls.direction * vec3(-1.0, 0.0, -1.0)).normalize())
+                        .clamp(0.0, 1.0);
+                light +=
+                    indirect * vec3(0.40, 0.28, 0.20) * occlusion * source.intensity * s.diffuse;
+            }
+        }
+
+        col += weight * light;
+
+        let reflection = specular * sharpness + fc * coat_sharpness;
+        let refl = reflect(ray.direction, n).normalize();
+        if s.transmission != Vec3::ZERO {
+            col += weight * reflection * background(scene, refl);
+            weight *= s.transmission * (1.0 - env) * (1.0 - fc);
+            if !pass_through(scene, &mut ray, &mut weight, p, n, &s) {
+                break;
+            }
+        } else {
+            weight *= reflection;
+            ray = Ray {
+                origin: p + n * 0.01,
+                direction: refl,
+            };
+        }
+        if weight.max_element() < 0.01 {
+            break;
+        }
+    }
+    col
+}
+
+/// Shades one pixel, `coord` being the framebuffer position as in `gl_FragCoord`.
+pub fn run(scene: &Scene, coord: Vec2, camera: &Camera) -> Vec3 {
+    let ray = camera.ray_for_pixel(coord);
+    path_trace(
+        scene,
+        ray,
+        background(scene, ray.direction),
+        sky_color(ray.direction),
+        camera.near,
+        camera.far,
+    )
+}
+
+fn reflect(i: Vec3, n: Vec3) -> Vec3 {
+    i - 2.0 * n.dot(i) * n
+}
+
+/// GLSL `refract`, zero on total internal reflection.
+fn refract(i: Vec3, n: Vec3, eta: f32) -> Vec3 {
+    let d = n.dot(i);
+    let k = 1.0 - eta * eta * (1.0 - d * d);
+    if k < 0.0 {
+        Vec3::ZERO
+    } else {
+        eta * i - (eta * d + k.sqrt()) * n
+    }
+}
@@ -0,0 +1,245 @@
+use glam::{EulerRot, IVec3, Mat3, Vec3};
+
+use super::common::*;
+use crate::params::Value;
+use crate::scene::{Aabb, Material, Node, Pattern, Scene};
+
+/// Mirrors `sdf()` in `scene.glsl`, or the code generated for `scene` by `generate_glsl`.
+/// Every object is evaluated, with only the bounds checks of the generated code culling the far
+/// ones. The BVH is not traversed, it skips objects that cannot be the nearest and finds the
+/// same distance.
+pub fn sdf(scene: &Scene, ray: Ray, t: f32) -> Hit {
+    let p = ray.origin + ray.direction * t;
+    scene
+        .objects
+        .iter()
+        .map(|(object, bounds)| object_sdf(object, *bounds, &scene.materials, p))
+        .reduce(hit_union)
+        .unwrap()
+}
+
+/// One object of the scene, or the distance to its `bounds` when they are farther than
+/// `BOUND_MARGIN`.
+pub fn object_sdf(object: &Node, bounds: Option<Aabb>, materials: &[Material], p: Vec3) -> Hit {
+    match bounds.map(|b| b.distance(p)) {
+        Some(dist) if dist > BOUND_MARGIN => Hit {
+            dist,
+            material_index: 0,
+            color: Vec3::ZERO,
+            hit: true,
+        },
+        _ => eval(object, materials, p, p, Vec3::ZERO),
+    }
+}
+
+fn leaf(
+    dist: f32,
+    material: usize,
+    pattern: Pattern,
+    materials: &[Material],
+    world: Vec3,
+    cell: Vec3,
+) -> Hit {
+    let color = match pattern {
+        Pattern::None => materials[material].base_color.get(),
+        Pattern::Cells => cell_color(cell),
+        Pattern::Waves => materials[material].base_color.get() + 0.4 * waves(world),
+    };
+    Hit {
+        dist,
+        material_index: material,
+        color,
+        hit: true,
+    }
+}
+
+fn fold(
+    children: &[Node],
+    materials: &[Material],
+    world: Vec3,
+    p: Vec3,
+    cell: Vec3,
+    op: impl Fn(Hit, Hit) -> Hit,
+) -> Hit {
+    children
+        .iter()
+        .map(|c| eval(c, materials, world, p, cell))
+        .reduce(op)
+        .unwrap()
+}
+
+/// Distance, material and pattern of a primitive node, `None` for operators.
+fn primitive(node: &Node, p: Vec3) -> Option<(f32, usize, Pattern)> {
+    let (dist, material, pattern) = match node {
+        Node::Sphere {
+            radius,
+            material,
+            pattern,
+        } => (sphere_sdf(p, *radius), material, pattern),
+        Node::Box {
+            size,
+            corner_radius,
+            material,
+            pattern,
+        } => (box_sdf(p, *size, *corner_radius), material, pattern),
+        Node::Cylinder {
+            radius,
+            height,
+            corner_radius,
+            material,
+            pattern,
+        } => (
+            cylinder_sdf(p, *radius, *height, *corner_radius),
+            material,
+            pattern,
+        ),
+        Node::Line {
+            a,
+            b,
+            radius,
+            material,
+            pattern,
+        } => (line_sdf(p, *a, *b, *radius), material, pattern),
+        Node::Plane {
+            normal,
+            offset,
+            material,
+            pattern,
+        } => (plane_sdf(p, normal.normalize(), *offset), material, pattern),
+        _ => return None,
+    };
+    Some((dist, *material, *pattern))
+}
+
+fn eval(node: &Node, materials: &[Material], world: Vec3, p: Vec3, cell: Vec3) -> Hit {
+    if let Some((dist, material, pattern)) = primitive(node, p) {
+        return leaf(dist, material, pattern, materials, world, cell);
+    }
+    match node {
+        Node::Union(children) => fold(children, materials, world, p, cell, hit_union),
+        Node::Intersection(children) => fold(children, materials, world, p, cell, hit_intersection),
+        Node::SmoothUnion { k, children } => fold(children, materials, world, p, cell, |a, b| {
+            hit_smooth_union(a, b, *k)
+        }),
+        Node::SmoothIntersection { k, children } => {
+            fold(children, materials, world, p, cell, |a, b| {
+                hit_smooth_intersection(a, b, *k)
+            })
+        }
+        Node::Subtraction { base, cut } => hit_subtraction(
+            eval(base, materials, world, p, cell),
+            eval(cut, materials, world, p, cell),
+        ),
+        Node::SmoothSubtraction { k, base, cut } => hit_smooth_subtraction(
+            eval(base, materials, world, p, cell),
+            eval(cut, materials, world, p, cell),
+            *k,
+        ),
+        Node::Transform {
+            translate,
+            rotate,
+            scale,
+            child,
+        } => {
+            let q = to_local(translate, rotate, scale, p);
+            let mut h = eval(child, materials, world, q, cell);
+            h.dist *= scale.get();
+            h
+        }
+        Node::Repeat {
+            spacing,
+            min,
+            max,
+            child,
+        } => {
+            let c = repeat_xz_cell(p, *spacing, *min, *max);
+            let q = repeat_xz(p, *spacing, *min, *max);
+            eval(child, materials, world, q, c)
+        }
+        _ => unreachable!("primitives are handled above"),
+    }
+}
+
+/// Maps `p` into the space of a `transform` node's child.
+fn to_local(translate: &Value<Vec3>, rotate: &Value<Vec3>, scale: &Value<f32>, p: Vec3) -> Vec3 {
+    let rotate = rotate.get();
+    let rotation = Mat3::from_euler(
+        EulerRot::XYZ,
+        rotate.x.to_radians(),
+        rotate.y.to_radians(),
+        rotate.z.to_radians(),
+    );
+    rotation.transpose() * (p - translate.get()) / scale.get()
+}
+
+/// The primitive whose surface is closest to `p`, as its index in a depth-first walk of the
+/// scene tree, and the `repeat` cell it is in. For a point on the scene surface this is the
+/// primitive that was hit.
+pub fn closest_primitive(scene: &Scene, p: Vec3) -> (usize, Option<IVec3>) {
+    let mut closest = Closest {
+        next_id: 0,
+        dist: f32::INFINITY,
+        id: 0,
+        cell: None,
+    };
+    closest.visit(&scene.root, p, None, 1.0);
+    (closest.id, closest.cell)
+}
+
+struct Closest {
+    next_id: usize,
+    dist: f32,
+    id: usize,
+    cell: Option<IVec3>,
+}
+
+impl Closest {
+    /// Walks the tree with the same coordinate transforms as `eval`. `scale` converts local
+    /// distances back to world units so primitives under different transforms compare correctly.
+    fn visit(&mut self, node: &Node, p: Vec3, cell: Option<Vec3>, scale: f32) {
+        if let Some((dist, _, _)) = primitive(node, p) {
+            let dist = dist.abs() * scale;
+            if dist < self.dist {
+                self.dist = dist;
+                self.id = self.next_id;
+                self.cell = cell.map(|c| c.round().as_ivec3());
+            }
+            self.next_id += 1;
+            return;
+        }
+        match node {
+            Node::Union(children)
+            | Node::Intersection(children)
+            | Node::SmoothUnion { children, .. }
+            | Node::SmoothIntersection { children, .. } => {
+                for child in children {
+                    self.visit(child, p, cell, scale);
+                }
+            }
+            Node::Subtraction { base, cut } | Node::SmoothSubtraction { base, cut, .. } => {
+                self.visit(base, p, cell, scale);
+                self.visit(cut, p, cell, scale);
+            }
+            Node::Transform {
+                translate,
+                rotate,
+                scale: s,
+                child,
+            } => {
+                let q = to_local(translate, rotate, s, p);
+                self.visit(child, q, cell, scale * s.get());
+            }
+            Node::Repeat {
+                spacing,
+                min,
+                max,
+                child,
+            } => {
+                let c = repeat_xz_cell(p, *spacing, *min, *max);
+                let q = repeat_xz(p, *spacing, *min, *max);
+                self.visit(child, q, Some(c), scale);
+            }
+            _ => unreachable!("primitives are handled above"),
+        }
+    }
+}
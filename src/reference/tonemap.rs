@@ -0,0 +1,67 @@
+use glam::{vec3, Mat3, Vec3};
+
+use crate::render::RenderSettings;
+use crate::tonemap::{linear_to_srgb, Tonemap};
+
+pub fn reinhard(x: Vec3) -> Vec3 {
+    x / (1.0 + x)
+}
+
+pub fn aces(x: Vec3) -> Vec3 {
+    (x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14)).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+pub fn agx_contrast(x: Vec3) -> Vec3 {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+        - 0.00232
+}
+
+pub fn agx(x: Vec3) -> Vec3 {
+    let inset = Mat3::from_cols_array(&[
+        0.84247906, 0.04232824, 0.04237565, 0.0784336, 0.87846864, 0.0784336, 0.07922375,
+        0.07916613, 0.879143,
+    ]);
+    let outset = Mat3::from_cols_array(&[
+        1.196879,
+        -0.05289685,
+        -0.05297164,
+        -0.09802088,
+        1.1519031,
+        -0.09804345,
+        -0.09902974,
+        -0.09896118,
+        1.1510737,
+    ]);
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    let x = (inset * x).max(Vec3::splat(1e-10));
+    let x =
+        vec3(x.x.log2(), x.y.log2(), x.z.log2()).clamp(Vec3::splat(min_ev), Vec3::splat(max_ev));
+    let x = agx_contrast((x - min_ev) / (max_ev - min_ev));
+    (outset * x).clamp(Vec3::ZERO, Vec3::ONE).powf(2.2)
+}
+
+pub fn tonemap(x: Vec3, tonemap: Tonemap) -> Vec3 {
+    match tonemap {
+        Tonemap::Reinhard => reinhard(x),
+        Tonemap::Aces => aces(x),
+        Tonemap::AgX => agx(x),
+        Tonemap::None => x.clamp(Vec3::ZERO, Vec3::ONE),
+    }
+}
+
+/// The `main` of `tonemap.glsl` for an sRGB target, `hdr` being a pixel of the scene pass.
+pub fn display(hdr: Vec3, settings: &RenderSettings) -> Vec3 {
+    let col = tonemap(
+        (hdr * settings.exposure.exp2()).max(Vec3::ZERO),
+        settings.tonemap,
+    );
+    vec3(
+        linear_to_srgb(col.x),
+        linear_to_srgb(col.y),
+        linear_to_srgb(col.z),
+    )
+}
@@ -0,0 +1,625 @@
+use std::{fmt, sync::Arc};
+use vulkano::{
+    buffer::{
+        allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo},
+        Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer,
+    },
+    command_buffer::{
+        AutoCommandBufferBuilder, PrimaryAutoCommandBuffer, RenderPassBeginInfo, SubpassBeginInfo,
+        SubpassContents,
+    },
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    device::Device,
+    format::Format,
+    image::{view::ImageView, Image, ImageCreateInfo, ImageType, ImageUsage},
+    memory::allocator::{
+        AllocationCreateInfo, MemoryAllocator, MemoryTypeFilter, StandardMemoryAllocator,
+    },
+    pipeline::{
+        compute::ComputePipelineCreateInfo,
+        graphics::{
+            color_blend::{ColorBlendAttachmentState, ColorBlendState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::{Vertex, VertexDefinition},
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint,
+        PipelineLayout, PipelineShaderStageCreateInfo,
+    },
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+    shader::{spirv::ExecutionModel, EntryPoint, ShaderModule},
+};
+
+use crate::accumulation::Sample;
+use crate::bvh::Bvh;
+use crate::camera::Camera;
+use crate::error::AppError;
+use crate::light::LightList;
+use crate::material::MaterialLibrary;
+use crate::params::{FrameTime, Param, ParamValues};
+use crate::quality::Quality;
+use crate::scene::{self, Scene};
+use crate::shaders::{compiler, compute, fragment, vertex};
+use crate::tonemap::Tonemap;
+
+/// The scene is rendered in linear color to a target of this format, which `PostChain` maps to
+/// the output.
+pub const HDR_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+#[derive(BufferContents, Vertex)]
+#[repr(C)]
+pub struct MyVertex {
+    #[format(R32G32_SFLOAT)]
+    position: [f32; 2],
+}
+
+pub fn fullscreen_quad(
+    memory_allocator: Arc<dyn MemoryAllocator>,
+) -> Result<Subbuffer<[MyVertex]>, AppError> {
+    let vertices = [
+        MyVertex {
+            position: [-1.0, -1.0],
+        },
+        MyVertex {
+            position: [-1.0, 1.0],
+        },
+        MyVertex {
+            position: [1.0, -1.0],
+        },
+        MyVertex {
+            position: [1.0, 1.0],
+        },
+        MyVertex {
+            position: [1.0, -1.0],
+        },
+        MyVertex {
+            position: [-1.0, 1.0],
+        },
+    ];
+    Buffer::from_iter(
+        memory_allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::VERTEX_BUFFER,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        vertices,
+    )
+    .map_err(|e| AppError::Render(e.to_string()))
+}
+
+/// Width and height of the compute backend's workgroups, the `local_size` of `cs.glsl`. Each
+/// of the 8x8 invocations renders one pixel on its own.
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Which kind of shader ray marches the scene. Both render the same image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// `fs.glsl`, drawn over a full-screen quad.
+    Fragment,
+    /// `cs.glsl`, dispatched in tiles and writing a storage image.
+    Compute,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Fragment => write!(f, "fragment"),
+            Backend::Compute => write!(f, "compute"),
+        }
+    }
+}
+
+/// The shader of `backend`. Uses the compiled-in `scene.glsl` unless a scene description was
+/// loaded.
+pub fn scene_shader(
+    device: Arc<Device>,
+    backend: Backend,
+    scene: Option<&Scene>,
+) -> Result<Arc<ShaderModule>, AppError> {
+    let loaded = match (scene, backend) {
+        (Some(scene), _) => {
+            return compiler::compile_scene_shader(device, backend, &scene::generate_glsl(scene))
+                .map_err(|e| AppError::Shader(format!("generated scene shader: {e}")))
+        }
+        (None, Backend::Fragment) => fragment::load(device),
+        (None, Backend::Compute) => compute::load(device),
+    };
+    loaded.map_err(|e| AppError::Shader(e.to_string()))
+}
+
+/// A render pass drawing into a single color attachment of `format`, `HDR_FORMAT` for the
+/// scene.
+pub fn render_pass(device: Arc<Device>, format: Format) -> Result<Arc<RenderPass>, AppError> {
+    Ok(vulkano::single_pass_renderpass!(
+        device,
+        attachments: {
+            color: {
+                format: format,
+                samples: 1,
+                load_op: Clear,
+                store_op: Store,
+            },
+        },
+        pass: {
+            color: [color],
+            depth_stencil: {},
+        },
+    )?)
+}
+
+/// A framebuffer of `render_pass` over a new image of `extent`, its only attachment, which can
+/// be sampled by the following passes.
+pub fn target_framebuffer(
+    memory_allocator: Arc<dyn MemoryAllocator>,
+    render_pass: Arc<RenderPass>,
+    extent: [u32; 2],
+) -> Result<Arc<Framebuffer>, AppError> {
+    framebuffer(memory_allocator, render_pass, extent, ImageUsage::empty())
+}
+
+/// Like `target_framebuffer`, for the scene, which the compute backend writes as a storage
+/// image instead of drawing in `render_pass`.
+pub fn scene_framebuffer(
+    memory_allocator: Arc<dyn MemoryAllocator>,
+    render_pass: Arc<RenderPass>,
+    extent: [u32; 2],
+) -> Result<Arc<Framebuffer>, AppError> {
+    framebuffer(memory_allocator, render_pass, extent, ImageUsage::STORAGE)
+}
+
+fn framebuffer(
+    memory_allocator: Arc<dyn MemoryAllocator>,
+    render_pass: Arc<RenderPass>,
+    extent: [u32; 2],
+    usage: ImageUsage,
+) -> Result<Arc<Framebuffer>, AppError> {
+    let format = render_pass.attachments()[0].format;
+    let image = Image::new(
+        memory_allocator,
+        ImageCreateInfo {
+            image_type: ImageType::Dim2d,
+            format,
+            extent: [extent[0], extent[1], 1],
+            usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED | usage,
+            ..Default::default()
+        },
+        AllocationCreateInfo::default(),
+    )
+    .map_err(|e| AppError::Render(e.to_string()))?;
+
+    Ok(Framebuffer::new(
+        render_pass,
+        FramebufferCreateInfo {
+            attachments: vec![ImageView::new_default(image)?],
+            ..Default::default()
+        },
+    )?)
+}
+
+pub fn create_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPass>,
+    fragment_shader: Arc<ShaderModule>,
+) -> Result<Arc<GraphicsPipeline>, AppError> {
+    let fs = fragment_shader
+        .entry_point("main")
+        .ok_or_else(|| AppError::Shader("fragment shader has no main".into()))?;
+    graphics_pipeline(device, render_pass, fs)
+}
+
+fn graphics_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPass>,
+    fs: EntryPoint,
+) -> Result<Arc<GraphicsPipeline>, AppError> {
+    let vs = vertex::load(device.clone())
+        .map_err(|e| AppError::Shader(e.to_string()))?
+        .entry_point("main")
+        .ok_or_else(|| AppError::Shader("vertex shader has no main".into()))?;
+
+    let vertex_input_state = MyVertex::per_vertex()
+        .definition(&vs)
+        .map_err(|e| AppError::Pipeline(e.to_string()))?;
+
+    let stages = [
+        PipelineShaderStageCreateInfo::new(vs),
+        PipelineShaderStageCreateInfo::new(fs),
+    ];
+
+    let layout = PipelineLayout::new(
+        device.clone(),
+        PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+            .into_pipeline_layout_create_info(device.clone())
+            .map_err(|e| AppError::Pipeline(e.to_string()))?,
+    )
+    .map_err(|e| AppError::Pipeline(e.to_string()))?;
+
+    let subpass = Subpass::from(render_pass, 0)
+        .ok_or_else(|| AppError::Pipeline("render pass has no subpass".into()))?;
+
+    GraphicsPipeline::new(
+        device,
+        None,
+        GraphicsPipelineCreateInfo {
+            stages: stages.into_iter().collect(),
+            vertex_input_state: Some(vertex_input_state),
+            input_assembly_state: Some(InputAssemblyState::default()),
+            viewport_state: Some(ViewportState::default()),
+            rasterization_state: Some(RasterizationState::default()),
+            multisample_state: Some(MultisampleState::default()),
+            color_blend_state: Some(ColorBlendState::with_attachment_states(
+                subpass.num_color_attachments(),
+                ColorBlendAttachmentState::default(),
+            )),
+            dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+            subpass: Some(subpass.into()),
+            ..GraphicsPipelineCreateInfo::layout(layout)
+        },
+    )
+    .map_err(|e| AppError::Pipeline(e.to_string()))
+}
+
+/// Ray marches the scene into the HDR image with the pipeline of a `Backend`.
+pub enum ScenePipeline {
+    Fragment(Arc<GraphicsPipeline>),
+    Compute {
+        pipeline: Arc<ComputePipeline>,
+        /// For set 1, which binds the image written to.
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    },
+}
+
+impl ScenePipeline {
+    /// A pipeline for `shader` specialized for `quality`, either a fragment shader drawing in
+    /// `render_pass` or a compute shader.
+    pub fn new(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        shader: Arc<ShaderModule>,
+        quality: Quality,
+    ) -> Result<Self, AppError> {
+        let entry_point = shader
+            .specialize(quality.specialization().into_iter().collect())
+            .map_err(|e| AppError::Shader(e.to_string()))?
+            .entry_point("main")
+            .ok_or_else(|| AppError::Shader("scene shader has no main".into()))?;
+        if entry_point.info().execution_model != ExecutionModel::GLCompute {
+            return Ok(ScenePipeline::Fragment(graphics_pipeline(
+                device,
+                render_pass,
+                entry_point,
+            )?));
+        }
+
+        let stage = PipelineShaderStageCreateInfo::new(entry_point);
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                .into_pipeline_layout_create_info(device.clone())
+                .map_err(|e| AppError::Pipeline(e.to_string()))?,
+        )
+        .map_err(|e| AppError::Pipeline(e.to_string()))?;
+        let pipeline = ComputePipeline::new(
+            device.clone(),
+            None,
+            ComputePipelineCreateInfo::stage_layout(stage, layout),
+        )
+        .map_err(|e| AppError::Pipeline(e.to_string()))?;
+
+        Ok(ScenePipeline::Compute {
+            pipeline,
+            descriptor_set_allocator: Arc::new(StandardDescriptorSetAllocator::new(
+                device,
+                Default::default(),
+            )),
+        })
+    }
+
+    pub fn layout(&self) -> &Arc<PipelineLayout> {
+        match self {
+            ScenePipeline::Fragment(pipeline) => pipeline.layout(),
+            ScenePipeline::Compute { pipeline, .. } => pipeline.layout(),
+        }
+    }
+
+    /// Records rendering the scene to the top left `extent` of the image of `target`, which
+    /// must come from `scene_framebuffer`.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        target: Arc<Framebuffer>,
+        extent: [u32; 2],
+        vertex_buffer: Subbuffer<[MyVertex]>,
+        descriptor_set: Arc<DescriptorSet>,
+    ) -> Result<(), AppError> {
+        match self {
+            ScenePipeline::Fragment(pipeline) => record_draw(
+                builder,
+                target,
+                Viewport {
+                    offset: [0.0, 0.0],
+                    extent: extent.map(|x| x as f32),
+                    depth_range: 0.0..=1.0,
+                },
+                pipeline.clone(),
+                vertex_buffer,
+                descriptor_set,
+            ),
+            ScenePipeline::Compute {
+                pipeline,
+                descriptor_set_allocator,
+            } => {
+                let layout = pipeline.layout().clone();
+                let target_set = DescriptorSet::new(
+                    descriptor_set_allocator.clone(),
+                    layout.set_layouts()[1].clone(),
+                    [WriteDescriptorSet::image_view(
+                        0,
+                        target.attachments()[0].clone(),
+                    )],
+                    [],
+                )?;
+
+                builder
+                    .bind_pipeline_compute(pipeline.clone())?
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Compute,
+                        layout,
+                        0,
+                        (descriptor_set, target_set),
+                    )?;
+
+                let groups = extent.map(|x| x.div_ceil(WORKGROUP_SIZE));
+                unsafe { builder.dispatch([groups[0], groups[1], 1]) }?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// How the fragment shader lights the scene, passed as the `MODE_*` values in `common.glsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    /// Direct lighting with sharp reflections.
+    Fast = 0,
+    /// Monte Carlo path tracing, noisy unless accumulated, see `path_tracing.glsl`.
+    PathTracing = 1,
+}
+
+impl RenderMode {
+    pub fn next(self) -> Self {
+        match self {
+            RenderMode::Fast => RenderMode::PathTracing,
+            RenderMode::PathTracing => RenderMode::Fast,
+        }
+    }
+}
+
+impl fmt::Display for RenderMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderMode::Fast => write!(f, "fast"),
+            RenderMode::PathTracing => write!(f, "path-tracing"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderSettings {
+    pub mode: RenderMode,
+    /// Surfaces a path tracing sample bounces off at most.
+    pub max_bounces: u32,
+    /// Path tracing samples taken per pixel and frame.
+    pub samples_per_pixel: u32,
+    pub tonemap: Tonemap,
+    /// In stops, each one doubling the brightness before tone mapping.
+    pub exposure: f32,
+    /// Specializes the scene pipeline, which has to be rebuilt when it changes.
+    pub quality: Quality,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            mode: RenderMode::Fast,
+            max_bounces: 8,
+            samples_per_pixel: 1,
+            tonemap: Tonemap::Aces,
+            exposure: 0.0,
+            quality: Quality::High,
+        }
+    }
+}
+
+/// The buffers read by the scene shader through descriptor set 0.
+pub struct ShaderResources {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    uniform_allocator: SubbufferAllocator,
+    pub params: Vec<Param>,
+    values: ParamValues,
+    pub materials: MaterialLibrary,
+    pub lights: LightList,
+    pub bvh: Bvh,
+    pub settings: RenderSettings,
+}
+
+impl ShaderResources {
+    pub fn new(
+        device: Arc<Device>,
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        scene: &Scene,
+    ) -> Self {
+        let (descriptor_set_allocator, uniform_allocator) =
+            allocators(device, memory_allocator.clone());
+
+        ShaderResources {
+            memory_allocator,
+            descriptor_set_allocator,
+            uniform_allocator,
+            params: scene.params.clone(),
+            values: ParamValues::default(),
+            materials: MaterialLibrary::new(scene.materials.clone()),
+            lights: LightList::new(scene.lights.clone()),
+            bvh: Bvh::new(&scene.root),
+            settings: RenderSettings::default(),
+        }
+    }
+
+    /// Moves the resources to a new device, keeping the current materials and lights. They are
+    /// uploaded again on the next `descriptor_set` call.
+    pub fn recreate(
+        &mut self,
+        device: Arc<Device>,
+        memory_allocator: Arc<StandardMemoryAllocator>,
+    ) {
+        (self.descriptor_set_allocator, self.uniform_allocator) =
+            allocators(device, memory_allocator.clone());
+        self.memory_allocator = memory_allocator;
+    }
+
+    /// Evaluates the params for `frame` and animates the materials, lights and object bounds
+    /// with them. Returns whether anything the shader reads changed, apart from the clock.
+    pub fn animate(&mut self, frame: FrameTime) -> bool {
+        let values = ParamValues::evaluate(&self.params, frame);
+        let changed = !values.same_values(&self.values);
+        self.values = values;
+        self.materials.animate(&self.values);
+        self.lights.animate(&self.values);
+        self.bvh.animate(&self.values);
+        changed || self.materials.is_dirty() || self.lights.is_dirty() || self.bvh.is_dirty()
+    }
+
+    /// Uploads this frame's globals, and the materials, lights and BVH if they changed, and
+    /// returns a descriptor set binding them for a pipeline with `layout`. With a `sample` the
+    /// frame is added to `accumulation`, which is otherwise left alone.
+    pub fn descriptor_set(
+        &mut self,
+        layout: &PipelineLayout,
+        screen: [f32; 2],
+        camera: &Camera,
+        accumulation: Arc<ImageView>,
+        sample: Option<Sample>,
+    ) -> Result<Arc<DescriptorSet>, AppError> {
+        let uniform_buffer = self
+            .uniform_allocator
+            .allocate_sized()
+            .map_err(|e| AppError::Render(e.to_string()))?;
+        *uniform_buffer
+            .write()
+            .map_err(|e| AppError::Render(e.to_string()))? = fragment::Globals {
+            screen: screen.into(),
+            cam_position: camera.position.to_array().into(),
+            cam_uu: camera.uu.to_array().into(),
+            cam_vv: camera.vv.to_array().into(),
+            cam_ww: camera.ww.to_array(),
+            cam_fov_y: camera.fov_y,
+            cam_aspect: camera.aspect,
+            cam_near: camera.near,
+            cam_far: camera.far,
+            light_count: self.lights.len() as u32,
+            time: self.values.frame.time,
+            delta_time: self.values.frame.delta_time,
+            frame: self.values.frame.frame,
+            accumulate: sample.is_some() as u32,
+            sample_index: sample.map_or(0, |s| s.index).into(),
+            jitter: sample.map_or([0.0; 2], |s| s.jitter),
+            mode: self.settings.mode as u32,
+            max_bounces: self.settings.max_bounces,
+            samples_per_pixel: self.settings.samples_per_pixel.into(),
+            params: self.values.to_gpu(),
+        };
+
+        let material_buffer = self.materials.buffer(self.memory_allocator.clone())?;
+        let light_buffer = self.lights.buffer(self.memory_allocator.clone())?;
+
+        let set_layout = layout.set_layouts()[0].clone();
+        let mut writes = vec![
+            WriteDescriptorSet::buffer(0, uniform_buffer),
+            WriteDescriptorSet::buffer(1, material_buffer),
+            WriteDescriptorSet::buffer(2, light_buffer),
+            WriteDescriptorSet::image_view(3, accumulation),
+        ];
+        // Only generated scenes with bounded objects read the BVH.
+        if set_layout.bindings().contains_key(&4) {
+            writes.push(WriteDescriptorSet::buffer(
+                4,
+                self.bvh.buffer(self.memory_allocator.clone())?,
+            ));
+        }
+
+        Ok(DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            set_layout,
+            writes,
+            [],
+        )?)
+    }
+}
+
+fn allocators(
+    device: Arc<Device>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+) -> (Arc<StandardDescriptorSetAllocator>, SubbufferAllocator) {
+    let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(
+        device,
+        Default::default(),
+    ));
+
+    let uniform_allocator = SubbufferAllocator::new(
+        memory_allocator,
+        SubbufferAllocatorCreateInfo {
+            buffer_usage: BufferUsage::UNIFORM_BUFFER,
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+    );
+
+    (descriptor_set_allocator, uniform_allocator)
+}
+
+pub fn record_draw(
+    builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    framebuffer: Arc<Framebuffer>,
+    viewport: Viewport,
+    pipeline: Arc<GraphicsPipeline>,
+    vertex_buffer: Subbuffer<[MyVertex]>,
+    descriptor_set: Arc<DescriptorSet>,
+) -> Result<(), AppError> {
+    let layout = pipeline.layout().clone();
+    let vertex_count = vertex_buffer.len() as u32;
+
+    builder
+        .begin_render_pass(
+            RenderPassBeginInfo {
+                clear_values: vec![Some([0.0, 0.0, 0.0, 1.0].into())],
+                ..RenderPassBeginInfo::framebuffer(framebuffer)
+            },
+            SubpassBeginInfo {
+                contents: SubpassContents::Inline,
+                ..Default::default()
+            },
+        )?
+        .set_viewport(0, [viewport].into_iter().collect())?
+        .bind_pipeline_graphics(pipeline)?
+        .bind_descriptor_sets(PipelineBindPoint::Graphics, layout, 0, descriptor_set)?
+        .bind_vertex_buffers(0, vertex_buffer)?;
+
+    // We add a draw command.
+    unsafe { builder.draw(vertex_count, 1, 0, 0) }?;
+
+    builder.end_render_pass(Default::default())?;
+    Ok(())
+}
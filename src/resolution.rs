@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+/// Smallest fraction of the window size the scene is rendered at.
+pub const MIN_SCALE: f32 = 0.25;
+
+/// Share of the way to the ideal scale taken per measured frame, slow enough not to oscillate
+/// on the latency of the measurements.
+const ADAPT_RATE: f32 = 0.25;
+
+/// The scale is kept while it is this close to the ideal one, so the resolution settles instead
+/// of jittering.
+const TOLERANCE: f32 = 0.01;
+
+/// The resolution the scene is ray marched at, as a fraction of the window size. The post chain
+/// scales it up to the window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderScale {
+    /// Fraction of the window width and height, from `MIN_SCALE` to 1.
+    pub scale: f32,
+    /// GPU frame time the scale is adjusted to hold, fixed if `None`.
+    pub target_frame_time: Option<Duration>,
+}
+
+impl Default for RenderScale {
+    fn default() -> Self {
+        RenderScale {
+            scale: 1.0,
+            target_frame_time: None,
+        }
+    }
+}
+
+impl RenderScale {
+    /// The size of the scene image for a window of `window_size`.
+    pub fn extent(&self, window_size: [u32; 2]) -> [u32; 2] {
+        window_size.map(|x| ((x as f32 * self.scale).round() as u32).clamp(1, x))
+    }
+
+    /// Moves the scale towards the one that renders in the target frame time, given that
+    /// the last measured frame took `frame_time`.
+    pub fn adapt(&mut self, frame_time: Duration) {
+        let Some(target) = self.target_frame_time else {
+            return;
+        };
+        // The time is mostly spent per pixel, so it goes with the square of the scale.
+        let ideal = self.scale * (target.as_secs_f32() / frame_time.as_secs_f32()).sqrt();
+        if (ideal - self.scale).abs() >= TOLERANCE {
+            self.scale = (self.scale + (ideal - self.scale) * ADAPT_RATE).clamp(MIN_SCALE, 1.0);
+        }
+    }
+
+    /// Changes the fixed scale by `step`, leaving the dynamic mode.
+    pub fn step(&mut self, step: f32) {
+        self.target_frame_time = None;
+        self.scale = (self.scale + step).clamp(MIN_SCALE, 1.0);
+    }
+}
@@ -0,0 +1,274 @@
+use glam::{EulerRot, Mat3, Vec3};
+
+use super::Node;
+
+/// An axis aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    fn around(center: Vec3, half_size: Vec3) -> Self {
+        Aabb {
+            min: center - half_size,
+            max: center + half_size,
+        }
+    }
+
+    pub fn union(self, other: Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// The overlap of both boxes, collapsed onto a face of `self` if there is none.
+    fn intersection(self, other: Aabb) -> Aabb {
+        let min = self.min.max(other.min);
+        Aabb {
+            min,
+            max: self.max.min(other.max).max(min),
+        }
+    }
+
+    fn expand(self, margin: f32) -> Aabb {
+        Aabb {
+            min: self.min - margin,
+            max: self.max + margin,
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// The box around this one after applying `m` and then `translate`.
+    fn transform(self, m: Mat3, translate: Vec3) -> Aabb {
+        let center = m * self.center() + translate;
+        let half_size = (self.max - self.min) * 0.5;
+        // Each axis of the result extends by the projections of all three rotated half sizes.
+        let extent = Mat3::from_cols(m.x_axis.abs(), m.y_axis.abs(), m.z_axis.abs()) * half_size;
+        Aabb::around(center, extent)
+    }
+
+    /// Distance from `p` to the box, 0 inside. Mirrors `box_distance` in `common.glsl`.
+    pub fn distance(&self, p: Vec3) -> f32 {
+        (self.min - p).max(p - self.max).max(Vec3::ZERO).length()
+    }
+}
+
+impl Node {
+    /// A box containing the surface of the node in its coordinates, with the current values of
+    /// its params. `None` if it is unbounded, as planes are.
+    pub fn bounds(&self) -> Option<Aabb> {
+        match self {
+            Node::Sphere { radius, .. } => Some(Aabb::around(Vec3::ZERO, Vec3::splat(*radius))),
+            Node::Box { size, .. } => Some(Aabb::around(Vec3::ZERO, *size)),
+            Node::Cylinder { radius, height, .. } => Some(Aabb::around(
+                Vec3::ZERO,
+                Vec3::new(*radius, height * 0.5, *radius),
+            )),
+            Node::Line { a, b, radius, .. } => Some(Aabb {
+                min: a.min(*b) - *radius,
+                max: a.max(*b) + *radius,
+            }),
+            Node::Plane { .. } => None,
+            Node::Union(children) => union(children),
+            // Each smooth minimum folded over the children pulls the surface out by at most a
+            // quarter of `k`.
+            Node::SmoothUnion { k, children } => union(children)
+                .map(|b| b.expand(children.len().saturating_sub(1) as f32 * k * 0.25)),
+            Node::Intersection(children) | Node::SmoothIntersection { children, .. } => children
+                .iter()
+                .filter_map(Node::bounds)
+                .reduce(Aabb::intersection),
+            Node::Subtraction { base, .. } | Node::SmoothSubtraction { base, .. } => base.bounds(),
+            Node::Transform {
+                translate,
+                rotate,
+                scale,
+                child,
+            } => {
+                let rotate = rotate.get();
+                let r = Mat3::from_euler(
+                    EulerRot::XYZ,
+                    rotate.x.to_radians(),
+                    rotate.y.to_radians(),
+                    rotate.z.to_radians(),
+                );
+                let bounds = child.bounds()?;
+                Some(bounds.transform(r * scale.get(), translate.get()))
+            }
+            Node::Repeat {
+                spacing,
+                min,
+                max,
+                child,
+            } => {
+                // The cells are offset on X and Z by `spacing` times `min` up to `max`.
+                let bounds = child.bounds()?;
+                let low = Vec3::new(spacing * min, 0.0, spacing * min);
+                let high = Vec3::new(spacing * max, 0.0, spacing * max);
+                Some(Aabb {
+                    min: bounds.min + low,
+                    max: bounds.max + high,
+                })
+            }
+        }
+    }
+}
+
+fn union(children: &[Node]) -> Option<Aabb> {
+    children
+        .iter()
+        .map(Node::bounds)
+        .reduce(|a, b| Some(a?.union(b?)))
+        .flatten()
+}
+
+/// The objects of the scene, the nodes combined by the unions at its root. Split into those
+/// with bounds, in the order of their entries in the BVH buffer, and the unbounded rest.
+pub fn objects(root: &Node) -> (Vec<&Node>, Vec<&Node>) {
+    let mut objects = vec![];
+    collect(root, &mut objects);
+    objects.into_iter().partition(|o| o.bounds().is_some())
+}
+
+fn collect<'a>(node: &'a Node, objects: &mut Vec<&'a Node>) {
+    match node {
+        Node::Union(children) => children.iter().for_each(|c| collect(c, objects)),
+        _ => objects.push(node),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::FrameTime;
+    use crate::reference::scene::object_sdf;
+    use crate::scene::{Scene, MIN_SCALE};
+
+    fn scene(root: &str) -> Scene {
+        Scene::parse(&format!(
+            r#"{{ "materials": [{{ "base_color": [1, 1, 1] }}], "root": {root} }}"#
+        ))
+        .unwrap()
+    }
+
+    /// Points spread over a cube of `size` around the origin.
+    fn points(size: f32) -> impl Iterator<Item = Vec3> {
+        let mut seed = 1u32;
+        let mut random = move || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            ((seed >> 8) as f32 / (1 << 24) as f32 - 0.5) * size
+        };
+        (0..20000).map(move |_| Vec3::new(random(), random(), random()))
+    }
+
+    #[test]
+    fn box_operations() {
+        let a = Aabb::around(Vec3::ZERO, Vec3::ONE);
+        let b = Aabb::around(Vec3::new(2.0, 0.0, 0.0), Vec3::splat(0.5));
+        assert_eq!(a.union(b).max, Vec3::new(2.5, 1.0, 1.0));
+        assert_eq!(a.intersection(b).min, Vec3::new(1.5, -0.5, -0.5));
+        assert_eq!(a.intersection(b).max, Vec3::new(1.5, 0.5, 0.5));
+        assert_eq!(a.distance(Vec3::new(0.5, 0.0, 0.0)), 0.0);
+        assert_eq!(a.distance(Vec3::new(4.0, 5.0, 1.0)), 5.0);
+    }
+
+    #[test]
+    fn transformed_boxes_contain_the_rotated_box() {
+        let cube = Aabb::around(Vec3::ZERO, Vec3::ONE);
+        let r = Mat3::from_rotation_y(45f32.to_radians());
+        let rotated = cube.transform(r * 2.0, Vec3::X);
+        let half = 2.0 * 2f32.sqrt();
+        assert!(rotated
+            .min
+            .abs_diff_eq(Vec3::new(1.0 - half, -2.0, -half), 1e-5));
+        assert!(rotated
+            .max
+            .abs_diff_eq(Vec3::new(1.0 + half, 2.0, half), 1e-5));
+    }
+
+    #[test]
+    fn bounds_contain_the_inside_of_nodes() {
+        let nodes = [
+            r#"{ "sphere": { "radius": 1.5 } }"#,
+            r#"{ "box": { "size": [1, 0.5, 2], "corner_radius": 0.2 } }"#,
+            r#"{ "cylinder": { "radius": 0.5, "height": 3 } }"#,
+            r#"{ "line": { "a": [-1, 0, 2], "b": [2, 1, -1], "radius": 0.3 } }"#,
+            r#"{ "smooth_union": { "k": 1.0, "children": [
+                { "sphere": { "radius": 1 } },
+                { "transform": { "translate": [1.8, 0, 0], "child": { "sphere": { "radius": 1 } } } }
+            ] } }"#,
+            r#"{ "smooth_union": { "k": 4.0, "children": [
+                { "transform": { "translate": [-0.6, 0, 0], "child": { "sphere": { "radius": 0.5 } } } },
+                { "transform": { "translate": [0.6, 0, 0], "child": { "sphere": { "radius": 0.5 } } } },
+                { "transform": { "translate": [0, 0, 1.1], "child": { "sphere": { "radius": 0.5 } } } }
+            ] } }"#,
+            r#"{ "intersection": [
+                { "sphere": { "radius": 2 } },
+                { "transform": { "translate": [1, 1, 0], "child": { "box": { "size": [1, 1, 1] } } } }
+            ] }"#,
+            r#"{ "smooth_subtraction": { "k": 0.5,
+                "base": { "box": { "size": [2, 1, 1] } },
+                "cut": { "sphere": { "radius": 1.2 } }
+            } }"#,
+            r#"{ "transform": { "translate": [0.5, -1, 0], "rotate": [30, 45, 10], "scale": 1.5,
+                "child": { "box": { "size": [1, 0.2, 0.6] } } } }"#,
+            r#"{ "repeat": { "spacing": 2, "min": -1, "max": 2,
+                "child": { "sphere": { "radius": 0.6 } } } }"#,
+        ];
+        for node in nodes {
+            let scene = scene(node);
+            let bounds = scene.root.bounds().unwrap();
+            for p in points(10.0) {
+                if object_sdf(&scene.root, None, &scene.materials, p).dist <= 0.0 {
+                    assert!(
+                        bounds.distance(p) < 1e-4,
+                        "{p} outside {bounds:?} of {node}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn param_scales_are_clamped() {
+        let scene = Scene::parse(
+            r#"{ "materials": [{ "base_color": [1, 1, 1] }],
+                "params": [{ "name": "s", "expression": "1 - t" }],
+                "root": { "transform": { "scale": "s", "child": { "sphere": { "radius": 1 } } } } }"#,
+        )
+        .unwrap();
+        for time in [0.5, 1.0, 3.0] {
+            let scene = scene.at(FrameTime {
+                time,
+                ..Default::default()
+            });
+            let Node::Transform { scale, .. } = &scene.root else {
+                unreachable!()
+            };
+            assert_eq!(scale.get(), (1.0 - time).max(MIN_SCALE));
+            let bounds = scene.root.bounds().unwrap();
+            assert!(bounds.min.is_finite() && bounds.max.cmpgt(bounds.min).all());
+        }
+    }
+
+    #[test]
+    fn planes_are_unbounded() {
+        let scene = scene(
+            r#"{ "union": [
+                { "sphere": { "radius": 1 } },
+                { "plane": {} },
+                { "union": [{ "box": { "size": [1, 1, 1] } }] }
+            ] }"#,
+        );
+        assert_eq!(scene.root.bounds(), None);
+        let (bounded, unbounded) = objects(&scene.root);
+        assert_eq!(bounded.len(), 2);
+        assert!(matches!(unbounded[..], [Node::Plane { .. }]));
+    }
+}
@@ -0,0 +1,322 @@
+use glam::{EulerRot, Mat3, Vec3};
+use std::fmt::Write;
+
+use super::{bounds, Node, Pattern, Scene, MIN_SCALE};
+use crate::params::{FromParam, Value};
+
+/// Scenes with up to this many bounded objects check each bounds in turn, larger ones traverse
+/// the BVH.
+const FLAT_OBJECTS: usize = 8;
+
+/// Stands in for a missing object, farther than anything is marched.
+const MISS: &str = "Hit(1e10, 0u, vec3(0), false)";
+
+/// Generates a replacement for `scene.glsl` that evaluates the scene description.
+pub fn generate_glsl(scene: &Scene) -> String {
+    let mut gen = Generator {
+        body: String::new(),
+        next_id: 0,
+        indent: 1,
+        params: scene.params.iter().map(|p| p.name.clone()).collect(),
+    };
+
+    let (bounded, unbounded) = bounds::objects(&scene.root);
+    let mut functions = String::new();
+    let mut hits: Vec<String> = unbounded
+        .iter()
+        .map(|o| gen.node(o, "p", "vec3(0)"))
+        .collect();
+    if bounded.len() <= FLAT_OBJECTS {
+        for (i, object) in bounded.iter().enumerate() {
+            hits.push(gen.bounded(i, object));
+        }
+    } else {
+        functions = gen.object_sdf(&bounded);
+        let nearest = gen.combine(hits, "hit_union", None);
+        let h = gen.var("h");
+        gen.line(format!(
+            "Hit {h} = bvh_sdf(p, {});",
+            nearest.as_deref().unwrap_or(MISS)
+        ));
+        hits = vec![h];
+    }
+    let root = gen.combine(hits, "hit_union", None).unwrap();
+
+    format!(
+        "#include <common.glsl>\n\n\
+         // Generated from a scene description file.\n\
+         {functions}\
+         Hit sdf(Ray ray, float t) {{\n    \
+         vec3 p = ray.origin + ray.direction * t;\n\
+         {}    return {root};\n}}\n",
+        gen.body
+    )
+}
+
+struct Generator {
+    body: String,
+    next_id: usize,
+    /// Nesting depth of the statements emitted by `line`.
+    indent: usize,
+    /// Parameter names, in the order of `globals.params`.
+    params: Vec<String>,
+}
+
+impl Generator {
+    fn var(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        format!("{prefix}{}", self.next_id)
+    }
+
+    /// The `globals.params` element a value refers to, `None` for constants.
+    fn param(&self, value: &Value<impl Copy + FromParam>) -> Option<String> {
+        let name = value.param()?;
+        let index = self.params.iter().position(|p| p == name)?;
+        Some(format!("globals.params[{index}]"))
+    }
+
+    fn line(&mut self, line: String) {
+        writeln!(self.body, "{}{line}", "    ".repeat(self.indent)).unwrap();
+    }
+
+    fn leaf(&mut self, dist: String, material: usize, pattern: Pattern, cell: &str) -> String {
+        let color = match pattern {
+            Pattern::None => format!("materials[{material}].base_color"),
+            Pattern::Cells => format!("cell_color({cell})"),
+            Pattern::Waves => format!("materials[{material}].base_color + 0.4 * waves(p)"),
+        };
+        let h = self.var("h");
+        self.line(format!(
+            "Hit {h} = Hit({dist}, {material}u, {color}, true);"
+        ));
+        h
+    }
+
+    fn fold(&mut self, children: &[Node], p: &str, cell: &str, op: &str, k: Option<f32>) -> String {
+        let hits: Vec<String> = children.iter().map(|c| self.node(c, p, cell)).collect();
+        self.combine(hits, op, k).unwrap()
+    }
+
+    /// Combines the `Hit` variables pairwise with `op`, `None` if there are none.
+    fn combine(&mut self, hits: Vec<String>, op: &str, k: Option<f32>) -> Option<String> {
+        hits.into_iter().reduce(|acc, h| {
+            let next = self.var("h");
+            let args = match k {
+                Some(k) => format!("{acc}, {h}, {}", float(k)),
+                None => format!("{acc}, {h}"),
+            };
+            self.line(format!("Hit {next} = {op}({args});"));
+            next
+        })
+    }
+
+    /// Emits `object`, the `index`th bounded one, evaluated only near its bounds, and returns
+    /// the `Hit` variable. Farther away the distance to the bounds stands in for it.
+    fn bounded(&mut self, index: usize, object: &Node) -> String {
+        let o = self.var("o");
+        let b = self.var("b");
+        self.line(format!("Hit {o};"));
+        self.line(format!(
+            "float {b} = box_distance(p, bvh[{index}].min, bvh[{index}].max);"
+        ));
+        self.line(format!("if ({b} > BOUND_MARGIN) {{"));
+        self.line(format!("    {o} = Hit({b}, 0u, vec3(0), true);"));
+        self.line("} else {".into());
+        self.indent += 1;
+        let h = self.node(object, "p", "vec3(0)");
+        self.line(format!("{o} = {h};"));
+        self.indent -= 1;
+        self.line("}".into());
+        o
+    }
+
+    /// Returns `object_sdf()`, evaluating the bounded objects by index for `bvh.glsl`, and the
+    /// include of the traversal.
+    fn object_sdf(&mut self, objects: &[&Node]) -> String {
+        let body = std::mem::take(&mut self.body);
+        for (i, object) in objects.iter().enumerate() {
+            self.indent = 1;
+            self.line(format!("case {i}u: {{"));
+            self.indent = 2;
+            let h = self.node(object, "p", "vec3(0)");
+            self.line(format!("return {h};"));
+            self.indent = 1;
+            self.line("}".into());
+        }
+        let cases = std::mem::replace(&mut self.body, body);
+        format!(
+            "Hit object_sdf(uint object, vec3 p) {{\n    \
+             switch (object) {{\n\
+             {cases}    }}\n    \
+             return {MISS};\n}}\n\n\
+             #include <bvh.glsl>\n\n"
+        )
+    }
+
+    /// Emits the statements for `node` evaluated at point `p` and returns the `Hit` variable.
+    fn node(&mut self, node: &Node, p: &str, cell: &str) -> String {
+        match node {
+            Node::Sphere {
+                radius,
+                material,
+                pattern,
+            } => self.leaf(
+                format!("sphere_sdf({p}, {})", float(*radius)),
+                *material,
+                *pattern,
+                cell,
+            ),
+            Node::Box {
+                size,
+                corner_radius,
+                material,
+                pattern,
+            } => self.leaf(
+                format!("box_sdf({p}, {}, {})", vec3(*size), float(*corner_radius)),
+                *material,
+                *pattern,
+                cell,
+            ),
+            Node::Cylinder {
+                radius,
+                height,
+                corner_radius,
+                material,
+                pattern,
+            } => self.leaf(
+                format!(
+                    "cylinder_sdf({p}, {}, {}, {})",
+                    float(*radius),
+                    float(*height),
+                    float(*corner_radius)
+                ),
+                *material,
+                *pattern,
+                cell,
+            ),
+            Node::Line {
+                a,
+                b,
+                radius,
+                material,
+                pattern,
+            } => self.leaf(
+                format!(
+                    "line_sdf({p}, {}, {}, {})",
+                    vec3(*a),
+                    vec3(*b),
+                    float(*radius)
+                ),
+                *material,
+                *pattern,
+                cell,
+            ),
+            Node::Plane {
+                normal,
+                offset,
+                material,
+                pattern,
+            } => self.leaf(
+                format!(
+                    "plane_sdf({p}, {}, {})",
+                    vec3(normal.normalize()),
+                    float(*offset)
+                ),
+                *material,
+                *pattern,
+                cell,
+            ),
+            Node::Union(children) => self.fold(children, p, cell, "hit_union", None),
+            Node::Intersection(children) => self.fold(children, p, cell, "hit_intersection", None),
+            Node::SmoothUnion { k, children } => {
+                self.fold(children, p, cell, "hit_smooth_union", Some(*k))
+            }
+            Node::SmoothIntersection { k, children } => {
+                self.fold(children, p, cell, "hit_smooth_intersection", Some(*k))
+            }
+            Node::Subtraction { base, cut } => {
+                let a = self.node(base, p, cell);
+                let b = self.node(cut, p, cell);
+                let h = self.var("h");
+                self.line(format!("Hit {h} = hit_subtraction({a}, {b});"));
+                h
+            }
+            Node::SmoothSubtraction { k, base, cut } => {
+                let a = self.node(base, p, cell);
+                let b = self.node(cut, p, cell);
+                let h = self.var("h");
+                self.line(format!(
+                    "Hit {h} = hit_smooth_subtraction({a}, {b}, {});",
+                    float(*k)
+                ));
+                h
+            }
+            Node::Transform {
+                translate,
+                rotate,
+                scale,
+                child,
+            } => {
+                let q = self.var("p");
+                let translate = match self.param(translate) {
+                    Some(param) => format!("{param}.xyz"),
+                    None => vec3(translate.get()),
+                };
+                let mut expr = format!("({p} - {translate})");
+                if let Some(param) = self.param(rotate) {
+                    expr = format!("transpose(rotation_xyz(radians({param}.xyz))) * {expr}");
+                } else if rotate.get() != Vec3::ZERO {
+                    let rotate = rotate.get();
+                    let r = Mat3::from_euler(
+                        EulerRot::XYZ,
+                        rotate.x.to_radians(),
+                        rotate.y.to_radians(),
+                        rotate.z.to_radians(),
+                    );
+                    expr = format!("{} * {expr}", mat3(r.transpose()));
+                }
+                let scale = match self.param(scale) {
+                    Some(param) => Some(format!("max({param}.x, {})", float(MIN_SCALE))),
+                    None => (scale.get() != 1.0).then(|| float(scale.get())),
+                };
+                if let Some(scale) = &scale {
+                    expr = format!("{expr} / {scale}");
+                }
+                self.line(format!("vec3 {q} = {expr};"));
+                let h = self.node(child, &q, cell);
+                if let Some(scale) = &scale {
+                    self.line(format!("{h}.dist *= {scale};"));
+                }
+                h
+            }
+            Node::Repeat {
+                spacing,
+                min,
+                max,
+                child,
+            } => {
+                let q = self.var("p");
+                let c = self.var("c");
+                let args = format!("{}, {}, {}", float(*spacing), float(*min), float(*max));
+                self.line(format!("vec3 {c} = repeat_xz_cell({p}, {args});"));
+                self.line(format!("vec3 {q} = repeat_xz({p}, {args});"));
+                self.node(child, &q, &c)
+            }
+        }
+    }
+}
+
+fn float(x: f32) -> String {
+    // Debug formatting always keeps a decimal point or exponent, as GLSL float literals need.
+    format!("{x:?}")
+}
+
+fn vec3(v: Vec3) -> String {
+    format!("vec3({}, {}, {})", float(v.x), float(v.y), float(v.z))
+}
+
+fn mat3(m: Mat3) -> String {
+    let c = m.to_cols_array();
+    let values: Vec<String> = c.iter().map(|x| float(*x)).collect();
+    format!("mat3({})", values.join(", "))
+}
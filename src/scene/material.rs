@@ -0,0 +1,226 @@
+use glam::Vec3;
+use serde::{de, Deserialize, Deserializer};
+
+use crate::params::Value;
+
+/// A metallic-roughness material, shaded by `brdf.glsl`. Scene files can also give a material
+/// in the glTF 2.0 layout as `{ "gltf": { ... } }`, see `GltfMaterial`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Material {
+    pub base_color: Value<Vec3>,
+    /// 0 for dielectrics, 1 for metals, which tint their reflections with the base color.
+    #[serde(default)]
+    pub metallic: f32,
+    /// From a mirror at 0 to a fully diffuse reflection at 1.
+    #[serde(default = "half")]
+    pub roughness: f32,
+    /// Light given off by the surface, independent of the lights.
+    #[serde(default)]
+    pub emissive: Vec3,
+    /// Strength of a clear glossy layer on top, like varnish or car paint.
+    #[serde(default)]
+    pub clearcoat: f32,
+    #[serde(default)]
+    pub clearcoat_roughness: f32,
+    /// Share of the light passing into the object instead of being reflected diffusely, see
+    /// `pass_through` in `ray_marching.glsl`.
+    #[serde(default)]
+    pub transmission: f32,
+    /// Index of refraction, also setting the reflectance of dielectrics.
+    #[serde(default = "glass")]
+    pub ior: f32,
+    /// Color white light turns into after `attenuation_distance` inside the object.
+    #[serde(default = "white")]
+    pub attenuation_color: Vec3,
+    #[serde(default = "infinity")]
+    pub attenuation_distance: f32,
+}
+
+fn half() -> f32 {
+    0.5
+}
+
+fn glass() -> f32 {
+    1.5
+}
+
+fn white() -> Vec3 {
+    Vec3::ONE
+}
+
+fn infinity() -> f32 {
+    f32::INFINITY
+}
+
+impl Material {
+    pub fn validate(&self) -> Result<(), String> {
+        let factors = [
+            ("metallic", self.metallic),
+            ("roughness", self.roughness),
+            ("clearcoat", self.clearcoat),
+            ("clearcoat_roughness", self.clearcoat_roughness),
+            ("transmission", self.transmission),
+        ];
+        for (name, value) in factors {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(format!("material {name} must be between 0 and 1"));
+            }
+        }
+        if self.emissive.min_element() < 0.0 {
+            return Err("material emissive cannot be negative".into());
+        }
+        if self.ior < 1.0 {
+            return Err("material ior must be at least 1".into());
+        }
+        if self.attenuation_color.min_element() <= 0.0 || self.attenuation_color.max_element() > 1.0
+        {
+            return Err("material attenuation_color must be above 0 and at most 1".into());
+        }
+        if self.attenuation_distance <= 0.0 {
+            return Err("material attenuation_distance must be positive".into());
+        }
+        Ok(())
+    }
+
+    /// Beer-Lambert absorption coefficient of the inside, per unit of distance.
+    pub fn absorption(&self) -> Vec3 {
+        let c = self.attenuation_color;
+        -Vec3::new(c.x.ln(), c.y.ln(), c.z.ln()) / self.attenuation_distance
+    }
+}
+
+/// A material as in the `materials` of a glTF 2.0 file, with the `KHR_materials_clearcoat`,
+/// `KHR_materials_emissive_strength`, `KHR_materials_transmission`, `KHR_materials_ior` and
+/// `KHR_materials_volume` extensions. Textures are not supported, other fields and
+/// extensions are ignored.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GltfMaterial {
+    #[serde(default)]
+    pub pbr_metallic_roughness: PbrMetallicRoughness,
+    #[serde(default)]
+    pub emissive_factor: [f32; 3],
+    #[serde(default)]
+    pub extensions: GltfExtensions,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PbrMetallicRoughness {
+    #[serde(default = "opaque_white")]
+    pub base_color_factor: [f32; 4],
+    #[serde(default = "one")]
+    pub metallic_factor: f32,
+    #[serde(default = "one")]
+    pub roughness_factor: f32,
+}
+
+impl Default for PbrMetallicRoughness {
+    fn default() -> Self {
+        PbrMetallicRoughness {
+            base_color_factor: opaque_white(),
+            metallic_factor: one(),
+            roughness_factor: one(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct GltfExtensions {
+    #[serde(rename = "KHR_materials_clearcoat")]
+    pub clearcoat: Option<GltfClearcoat>,
+    #[serde(rename = "KHR_materials_emissive_strength")]
+    pub emissive_strength: Option<GltfEmissiveStrength>,
+    #[serde(rename = "KHR_materials_transmission")]
+    pub transmission: Option<GltfTransmission>,
+    #[serde(rename = "KHR_materials_ior")]
+    pub ior: Option<GltfIor>,
+    #[serde(rename = "KHR_materials_volume")]
+    pub volume: Option<GltfVolume>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GltfClearcoat {
+    pub clearcoat_factor: f32,
+    pub clearcoat_roughness_factor: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GltfEmissiveStrength {
+    pub emissive_strength: f32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GltfTransmission {
+    pub transmission_factor: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GltfIor {
+    #[serde(default = "glass")]
+    pub ior: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GltfVolume {
+    #[serde(default = "white")]
+    pub attenuation_color: Vec3,
+    #[serde(default = "infinity")]
+    pub attenuation_distance: f32,
+}
+
+fn opaque_white() -> [f32; 4] {
+    [1.0; 4]
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+impl From<GltfMaterial> for Material {
+    fn from(gltf: GltfMaterial) -> Self {
+        let pbr = gltf.pbr_metallic_roughness;
+        let [r, g, b, _] = pbr.base_color_factor;
+        let strength = gltf
+            .extensions
+            .emissive_strength
+            .map_or(1.0, |e| e.emissive_strength);
+        let clearcoat = gltf.extensions.clearcoat.unwrap_or_default();
+        let volume = gltf.extensions.volume.unwrap_or(GltfVolume {
+            attenuation_color: white(),
+            attenuation_distance: infinity(),
+        });
+        Material {
+            base_color: Value::Const(Vec3::new(r, g, b)),
+            metallic: pbr.metallic_factor,
+            roughness: pbr.roughness_factor,
+            emissive: Vec3::from(gltf.emissive_factor) * strength,
+            clearcoat: clearcoat.clearcoat_factor,
+            clearcoat_roughness: clearcoat.clearcoat_roughness_factor,
+            transmission: gltf
+                .extensions
+                .transmission
+                .map_or(0.0, |t| t.transmission_factor),
+            ior: gltf.extensions.ior.map_or(glass(), |i| i.ior),
+            attenuation_color: volume.attenuation_color,
+            attenuation_distance: volume.attenuation_distance,
+        }
+    }
+}
+
+/// Reads the scene materials, each either a `Material` or a `GltfMaterial` under `gltf`.
+pub fn materials<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Material>, D::Error> {
+    Vec::<serde_json::Value>::deserialize(deserializer)?
+        .into_iter()
+        .map(|value| match value.get("gltf") {
+            Some(gltf) => GltfMaterial::deserialize(gltf).map(Material::from),
+            None => Material::deserialize(value),
+        })
+        .collect::<Result<_, _>>()
+        .map_err(de::Error::custom)
+}
@@ -0,0 +1,371 @@
+use glam::Vec3;
+use serde::Deserialize;
+use std::{fmt, fs, path::Path};
+
+use crate::light::Light;
+use crate::params::{FrameTime, Param, ParamValues, Value, MAX_PARAMS};
+
+/// The smallest scale a param can give a transform, zero or less would fold space over.
+pub const MIN_SCALE: f32 = 1e-3;
+
+pub mod bounds;
+mod glsl;
+mod material;
+
+pub use bounds::Aabb;
+pub use glsl::generate_glsl;
+pub use material::Material;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scene {
+    /// Animated values that nodes, material colors and light directions can refer to by name.
+    #[serde(default)]
+    pub params: Vec<Param>,
+    #[serde(deserialize_with = "material::materials")]
+    pub materials: Vec<Material>,
+    #[serde(default = "default_lights")]
+    pub lights: Vec<Light>,
+    pub root: Node,
+    /// The objects of `root` with their bounds, unbounded ones first, in the order the generated
+    /// `sdf()` evaluates them. Kept up to date by `parse` and `at`.
+    #[serde(skip)]
+    pub objects: Vec<(Node, Option<Aabb>)>,
+}
+
+fn default_lights() -> Vec<Light> {
+    vec![Light::sun()]
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pattern {
+    /// Plain material color.
+    #[default]
+    None,
+    /// Color derived from the index of the enclosing `repeat` cell.
+    Cells,
+    /// Material color modulated by a sine pattern in world space.
+    Waves,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Node {
+    Sphere {
+        radius: f32,
+        #[serde(default)]
+        material: usize,
+        #[serde(default)]
+        pattern: Pattern,
+    },
+    Box {
+        size: Vec3,
+        #[serde(default)]
+        corner_radius: f32,
+        #[serde(default)]
+        material: usize,
+        #[serde(default)]
+        pattern: Pattern,
+    },
+    Cylinder {
+        radius: f32,
+        height: f32,
+        #[serde(default)]
+        corner_radius: f32,
+        #[serde(default)]
+        material: usize,
+        #[serde(default)]
+        pattern: Pattern,
+    },
+    Line {
+        a: Vec3,
+        b: Vec3,
+        radius: f32,
+        #[serde(default)]
+        material: usize,
+        #[serde(default)]
+        pattern: Pattern,
+    },
+    Plane {
+        #[serde(default = "up")]
+        normal: Vec3,
+        #[serde(default)]
+        offset: f32,
+        #[serde(default)]
+        material: usize,
+        #[serde(default)]
+        pattern: Pattern,
+    },
+    Union(Vec<Node>),
+    SmoothUnion {
+        k: f32,
+        children: Vec<Node>,
+    },
+    Intersection(Vec<Node>),
+    SmoothIntersection {
+        k: f32,
+        children: Vec<Node>,
+    },
+    Subtraction {
+        base: Box<Node>,
+        cut: Box<Node>,
+    },
+    SmoothSubtraction {
+        k: f32,
+        base: Box<Node>,
+        cut: Box<Node>,
+    },
+    /// Translation, rotation (XYZ euler angles in degrees) and uniform scale. A scale driven by a
+    /// param is clamped to `MIN_SCALE`, constant scales must be positive.
+    Transform {
+        #[serde(default)]
+        translate: Value<Vec3>,
+        #[serde(default)]
+        rotate: Value<Vec3>,
+        #[serde(default = "one")]
+        scale: Value<f32>,
+        child: Box<Node>,
+    },
+    /// Finite domain repetition on the XZ plane, see `repeat_xz`.
+    Repeat {
+        spacing: f32,
+        min: f32,
+        max: f32,
+        child: Box<Node>,
+    },
+}
+
+fn up() -> Vec3 {
+    Vec3::Y
+}
+
+fn one() -> Value<f32> {
+    Value::Const(1.0)
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(f, "cannot read scene file: {e}"),
+            SceneError::Parse(e) => write!(f, "cannot parse scene file: {e}"),
+            SceneError::Invalid(msg) => write!(f, "invalid scene: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl Scene {
+    /// The scene hard-coded in `scene.glsl`, expressed as a scene description.
+    pub fn builtin() -> Scene {
+        Scene::parse(include_str!("../../scenes/default.json")).unwrap()
+    }
+
+    pub fn load(path: &Path) -> Result<Scene, SceneError> {
+        let text = fs::read_to_string(path).map_err(SceneError::Io)?;
+        Scene::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Scene, SceneError> {
+        let mut scene: Scene = serde_json::from_str(text).map_err(SceneError::Parse)?;
+        scene.validate()?;
+        scene.update_objects();
+        Ok(scene)
+    }
+
+    /// The scene as it is at `frame`, with parameters evaluated and lights animated.
+    pub fn at(&self, frame: FrameTime) -> Scene {
+        let values = ParamValues::evaluate(&self.params, frame);
+        let mut scene = self.clone();
+        for material in &mut scene.materials {
+            material.base_color.update(&values);
+        }
+        for light in &mut scene.lights {
+            *light = light.at(&values);
+        }
+        scene.root.update(&values);
+        scene.update_objects();
+        scene
+    }
+
+    fn update_objects(&mut self) {
+        let (bounded, unbounded) = bounds::objects(&self.root);
+        self.objects = unbounded
+            .into_iter()
+            .chain(bounded)
+            .map(|o| (o.clone(), o.bounds()))
+            .collect();
+    }
+
+    fn validate(&self) -> Result<(), SceneError> {
+        if self.materials.is_empty() {
+            return Err(SceneError::Invalid(
+                "at least one material is required".into(),
+            ));
+        }
+        if self.params.len() > MAX_PARAMS {
+            return Err(SceneError::Invalid(format!(
+                "at most {MAX_PARAMS} params are supported"
+            )));
+        }
+        for (i, param) in self.params.iter().enumerate() {
+            param.validate().map_err(SceneError::Invalid)?;
+            if self.params[..i].iter().any(|p| p.name == param.name) {
+                return Err(SceneError::Invalid(format!(
+                    "param '{}' is declared twice",
+                    param.name
+                )));
+            }
+        }
+        let check_param = |name: Option<&str>| match name {
+            Some(name) if !self.params.iter().any(|p| p.name == name) => {
+                Err(SceneError::Invalid(format!("unknown param '{name}'")))
+            }
+            _ => Ok(()),
+        };
+        for material in &self.materials {
+            material.validate().map_err(SceneError::Invalid)?;
+            check_param(material.base_color.param())?;
+        }
+        for light in &self.lights {
+            light.validate().map_err(SceneError::Invalid)?;
+            check_param(light.param())?;
+        }
+        self.root.validate(self.materials.len(), &check_param)
+    }
+}
+
+impl Node {
+    fn validate(
+        &self,
+        material_count: usize,
+        check_param: &dyn Fn(Option<&str>) -> Result<(), SceneError>,
+    ) -> Result<(), SceneError> {
+        match self {
+            Node::Sphere { material, .. }
+            | Node::Box { material, .. }
+            | Node::Cylinder { material, .. }
+            | Node::Line { material, .. }
+            | Node::Plane { material, .. } => {
+                if matches!(self, Node::Plane { normal, .. } if *normal == Vec3::ZERO) {
+                    return Err(SceneError::Invalid("plane normal must not be zero".into()));
+                }
+                if *material >= material_count {
+                    return Err(SceneError::Invalid(format!(
+                        "material {material} is out of range, the scene has {material_count}"
+                    )));
+                }
+                Ok(())
+            }
+            Node::Union(children)
+            | Node::Intersection(children)
+            | Node::SmoothUnion { children, .. }
+            | Node::SmoothIntersection { children, .. } => {
+                check_smoothness(self)?;
+                if children.is_empty() {
+                    return Err(SceneError::Invalid("empty operator node".into()));
+                }
+                children
+                    .iter()
+                    .try_for_each(|c| c.validate(material_count, check_param))
+            }
+            Node::Subtraction { base, cut } | Node::SmoothSubtraction { base, cut, .. } => {
+                check_smoothness(self)?;
+                base.validate(material_count, check_param)?;
+                cut.validate(material_count, check_param)
+            }
+            Node::Transform {
+                translate,
+                rotate,
+                scale,
+                child,
+            } => {
+                if scale.param().is_none() && scale.get() <= 0.0 {
+                    return Err(SceneError::Invalid(
+                        "transform scale must be positive".into(),
+                    ));
+                }
+                check_param(translate.param())?;
+                check_param(rotate.param())?;
+                check_param(scale.param())?;
+                child.validate(material_count, check_param)
+            }
+            Node::Repeat {
+                spacing,
+                min,
+                max,
+                child,
+            } => {
+                if *spacing <= 0.0 {
+                    return Err(SceneError::Invalid(
+                        "repeat spacing must be positive".into(),
+                    ));
+                }
+                if min > max {
+                    return Err(SceneError::Invalid(format!(
+                        "repeat min {min} is greater than max {max}"
+                    )));
+                }
+                child.validate(material_count, check_param)
+            }
+        }
+    }
+
+    pub fn update(&mut self, values: &ParamValues) {
+        match self {
+            Node::Sphere { .. }
+            | Node::Box { .. }
+            | Node::Cylinder { .. }
+            | Node::Line { .. }
+            | Node::Plane { .. } => {}
+            Node::Union(children)
+            | Node::Intersection(children)
+            | Node::SmoothUnion { children, .. }
+            | Node::SmoothIntersection { children, .. } => {
+                children.iter_mut().for_each(|c| c.update(values))
+            }
+            Node::Subtraction { base, cut } | Node::SmoothSubtraction { base, cut, .. } => {
+                base.update(values);
+                cut.update(values);
+            }
+            Node::Transform {
+                translate,
+                rotate,
+                scale,
+                child,
+            } => {
+                translate.update(values);
+                rotate.update(values);
+                scale.update(values);
+                if let Value::Param { value, .. } = scale {
+                    *value = value.max(MIN_SCALE);
+                }
+                child.update(values);
+            }
+            Node::Repeat { child, .. } => child.update(values),
+        }
+    }
+}
+
+/// Smooth operators blend over `k`, which must be positive.
+fn check_smoothness(node: &Node) -> Result<(), SceneError> {
+    match node {
+        Node::SmoothUnion { k, .. }
+        | Node::SmoothIntersection { k, .. }
+        | Node::SmoothSubtraction { k, .. }
+            if *k <= 0.0 =>
+        {
+            Err(SceneError::Invalid(format!(
+                "smooth operator k must be positive, got {k}"
+            )))
+        }
+        _ => Ok(()),
+    }
+}
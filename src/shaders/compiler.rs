@@ -0,0 +1,104 @@
+use shaderc::{CompileOptions, Compiler, EnvVersion, ResolvedInclude, ShaderKind, TargetEnv};
+use std::{fs, path::Path, sync::Arc};
+use vulkano::{
+    device::Device,
+    shader::{ShaderModule, ShaderModuleCreateInfo},
+};
+
+use crate::render::Backend;
+
+/// Location of the GLSL sources in the checkout the binary was built from.
+pub const GLSL_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders/glsl");
+
+const SOURCES: [(&str, &str); 9] = [
+    ("fs.glsl", include_str!("glsl/fs.glsl")),
+    ("cs.glsl", include_str!("glsl/cs.glsl")),
+    ("pixel.glsl", include_str!("glsl/pixel.glsl")),
+    ("path_tracing.glsl", include_str!("glsl/path_tracing.glsl")),
+    ("ray_marching.glsl", include_str!("glsl/ray_marching.glsl")),
+    ("scene.glsl", include_str!("glsl/scene.glsl")),
+    ("bvh.glsl", include_str!("glsl/bvh.glsl")),
+    ("brdf.glsl", include_str!("glsl/brdf.glsl")),
+    ("common.glsl", include_str!("glsl/common.glsl")),
+];
+
+/// Compiles the shader of `backend` at runtime, replacing `scene.glsl` with `scene_source`.
+pub fn compile_scene_shader(
+    device: Arc<Device>,
+    backend: Backend,
+    scene_source: &str,
+) -> Result<Arc<ShaderModule>, String> {
+    let resolve = |name: &str| {
+        if name == "scene.glsl" {
+            return Ok((name.to_owned(), scene_source.to_owned()));
+        }
+        SOURCES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(n, source)| (n.to_string(), source.to_string()))
+            .ok_or_else(|| format!("unknown include {name}"))
+    };
+
+    create_scene_module(device, backend, resolve)
+}
+
+/// Compiles the shader of `backend` from the files in [`GLSL_DIR`], so edits are picked up
+/// without rebuilding. A generated `scene_source` still takes the place of `scene.glsl`.
+pub fn compile_scene_shader_from_disk(
+    device: Arc<Device>,
+    backend: Backend,
+    scene_source: Option<&str>,
+) -> Result<Arc<ShaderModule>, String> {
+    let resolve = |name: &str| {
+        let path = Path::new(GLSL_DIR).join(name);
+        let resolved_name = path.display().to_string();
+        if let (Some(source), "scene.glsl") = (scene_source, name) {
+            return Ok((resolved_name, source.to_owned()));
+        }
+        fs::read_to_string(&path)
+            .map(|source| (resolved_name, source))
+            .map_err(|e| format!("cannot read {}: {e}", path.display()))
+    };
+
+    create_scene_module(device, backend, resolve)
+}
+
+fn create_scene_module(
+    device: Arc<Device>,
+    backend: Backend,
+    resolve: impl Fn(&str) -> Result<(String, String), String>,
+) -> Result<Arc<ShaderModule>, String> {
+    let (main, kind) = match backend {
+        Backend::Fragment => ("fs.glsl", ShaderKind::Fragment),
+        Backend::Compute => ("cs.glsl", ShaderKind::Compute),
+    };
+    let (name, source) = resolve(main)?;
+    let words = compile(&source, &name, kind, resolve)?;
+
+    unsafe { ShaderModule::new(device, ShaderModuleCreateInfo::new(&words)) }
+        .map_err(|e| format!("cannot create shader module: {e}"))
+}
+
+/// `resolve` maps an include to its source and the name used for it in diagnostics.
+fn compile(
+    source: &str,
+    name: &str,
+    kind: ShaderKind,
+    resolve: impl Fn(&str) -> Result<(String, String), String>,
+) -> Result<Vec<u32>, String> {
+    let compiler = Compiler::new().ok_or("failed to create GLSL compiler")?;
+    let mut options = CompileOptions::new().ok_or("failed to initialize compile options")?;
+    options.set_target_env(TargetEnv::Vulkan, EnvVersion::Vulkan1_0 as u32);
+    options.set_include_callback(|requested, _, _, _| {
+        resolve(requested).map(|(resolved_name, content)| ResolvedInclude {
+            resolved_name,
+            content,
+        })
+    });
+
+    let artifact = compiler
+        .compile_into_spirv(source, kind, name, "main", Some(&options))
+        .map_err(|e| e.to_string())?;
+
+    Ok(artifact.as_binary().to_vec())
+}
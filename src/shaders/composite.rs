@@ -0,0 +1,5 @@
+vulkano_shaders::shader! {
+    ty: "fragment",
+    include: ["src/shaders/glsl"],
+    path: "./src/shaders/glsl/composite.glsl"
+}
@@ -0,0 +1,5 @@
+vulkano_shaders::shader! {
+    ty: "compute",
+    include: ["src/shaders/glsl"],
+    path: "./src/shaders/glsl/cs.glsl"
+}
@@ -0,0 +1,53 @@
+#version 450
+
+// The two kinds of bloom passes over quarter resolution images. The prefilter averages the HDR
+// image down and keeps what is brighter than `threshold`, the others blur `source` along
+// `direction`, once horizontally and once vertically.
+
+layout(set = 0, binding = 0) uniform sampler2D source;
+
+layout(push_constant) uniform Params {
+    vec2 target_size;
+    // The part of `source` with an image in it, the HDR image may only be partly rendered to.
+    vec2 source_scale;
+    // In texels of `source`, the spacing of the blur taps.
+    vec2 direction;
+    float threshold;
+    uint prefilter;
+} params;
+
+layout(location = 0) out vec4 f_color;
+
+const float WEIGHTS[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+// Samples `source`, clamped to half a texel inside the part with an image.
+vec3 fetch(vec2 uv) {
+    vec2 half_texel = 0.5 / vec2(textureSize(source, 0));
+    return texture(source, min(uv, params.source_scale - half_texel)).rgb;
+}
+
+void main() {
+    vec2 uv = gl_FragCoord.xy / params.target_size * params.source_scale;
+    vec2 texel = 1.0 / vec2(textureSize(source, 0));
+
+    if(params.prefilter != 0u) {
+        // Four bilinear taps average the 4x4 source pixels under the target pixel.
+        vec3 c = fetch(uv + vec2(-1.0, -1.0) * texel);
+        c += fetch(uv + vec2(1.0, -1.0) * texel);
+        c += fetch(uv + vec2(-1.0, 1.0) * texel);
+        c += fetch(uv + vec2(1.0, 1.0) * texel);
+        // Clamped, so single bright path tracing samples do not flash.
+        c = min(c * 0.25, vec3(64.0));
+        float brightness = max(c.r, max(c.g, c.b));
+        f_color = vec4(c * max(brightness - params.threshold, 0.0) / max(brightness, 1e-4), 1.0);
+        return;
+    }
+
+    vec2 step = params.direction * texel;
+    vec3 sum = fetch(uv) * WEIGHTS[0];
+    for(int i = 1; i < 5; i++) {
+        sum += fetch(uv + step * float(i)) * WEIGHTS[i];
+        sum += fetch(uv - step * float(i)) * WEIGHTS[i];
+    }
+    f_color = vec4(sum, 1.0);
+}
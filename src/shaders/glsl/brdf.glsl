@@ -0,0 +1,110 @@
+// Metallic-roughness shading: a GGX specular lobe and a Lambert diffuse lobe that gets the light
+// the Fresnel term leaves over, under an optional clearcoat layer. Transmission takes a share of
+// the diffuse lobe and refracts it smoothly, see `pass_through`. `reference/brdf.rs` mirrors it.
+
+#define PI 3.14159265
+// Below this the GGX lobe gets too narrow for a float.
+#define MIN_ROUGHNESS 0.045
+// Reflectance of the clearcoat at normal incidence.
+#define DIELECTRIC_F0 0.04
+
+// A material at one point, with the pattern color as the base color.
+struct Surface {
+    vec3 diffuse;
+    // Tint of the light refracted into the object, black for opaque surfaces.
+    vec3 transmission;
+    vec3 f0;
+    float roughness;
+    float clearcoat;
+    float clearcoat_roughness;
+    vec3 emissive;
+    float ior;
+    vec3 absorption;
+};
+
+// Reflectance at normal incidence of a dielectric with index of refraction `ior`.
+float ior_f0(float ior) {
+    float r = (ior - 1.0) / (ior + 1.0);
+    return r * r;
+}
+
+Surface surface(Material material, vec3 color) {
+    vec3 base_color = clamp(color, 0.0, 1.0);
+    vec3 dielectric = base_color * (1.0 - material.metallic);
+    return Surface(
+        dielectric * (1.0 - material.transmission),
+        dielectric * material.transmission,
+        mix(vec3(ior_f0(material.ior)), base_color, material.metallic),
+        max(material.roughness, MIN_ROUGHNESS),
+        material.clearcoat,
+        max(material.clearcoat_roughness, MIN_ROUGHNESS),
+        material.emissive,
+        material.ior,
+        material.absorption);
+}
+
+// Exact Fresnel reflectance of unpolarized light crossing into another medium, `eta` being the
+// ratio of the indices of refraction on the incident and the transmitted side. 1 on total internal
+// reflection.
+float fresnel_dielectric(float cos_i, float eta) {
+    float sin_t2 = eta * eta * (1.0 - cos_i * cos_i);
+    if(sin_t2 >= 1.0) {
+        return 1.0;
+    }
+    float cos_t = sqrt(1.0 - sin_t2);
+    float rs = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    float rp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    return 0.5 * (rs * rs + rp * rp);
+}
+
+float d_ggx(float n_h, float alpha) {
+    float a2 = alpha * alpha;
+    float d = n_h * n_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Height correlated Smith masking and shadowing, divided by 4 n.v n.l.
+float v_smith(float n_v, float n_l, float alpha) {
+    float a2 = alpha * alpha;
+    float v = n_l * sqrt(n_v * n_v * (1.0 - a2) + a2);
+    float l = n_v * sqrt(n_l * n_l * (1.0 - a2) + a2);
+    return 0.5 / max(v + l, 1e-5);
+}
+
+vec3 f_schlick(vec3 f0, float cos_theta) {
+    return f0 + (1.0 - f0) * pow(1.0 - clamp(cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Share of the light arriving around the mirror direction that a specular lobe reflects, the
+// analytic fit by Karis of its integral over the hemisphere.
+vec3 env_brdf(vec3 f0, float roughness, float n_v) {
+    vec4 r = roughness * vec4(-1.0, -0.0275, -0.572, 0.022) + vec4(1.0, 0.0425, 1.04, -0.04);
+    float a004 = min(r.x * r.x, exp2(-9.28 * n_v)) * r.x + r.y;
+    vec2 ab = vec2(-1.04, 1.04) * a004 + r.zw;
+    return f0 * ab.x + ab.y;
+}
+
+// Share of the light reaching the base layers through the clearcoat.
+float clearcoat_fresnel(Surface s, float cos_theta) {
+    return s.clearcoat * f_schlick(vec3(DIELECTRIC_F0), cos_theta).x;
+}
+
+// Light reflected towards `v` per unit of irradiance from `l`.
+vec3 brdf(Surface s, vec3 n, vec3 v, vec3 l) {
+    vec3 h = normalize(v + l);
+    float n_v = max(dot(n, v), 1e-4);
+    float n_l = max(dot(n, l), 0.0);
+    float n_h = max(dot(n, h), 0.0);
+    float v_h = max(dot(v, h), 0.0);
+
+    float alpha = s.roughness * s.roughness;
+    vec3 f = f_schlick(s.f0, v_h);
+    vec3 specular = d_ggx(n_h, alpha) * v_smith(n_v, n_l, alpha) * f;
+    vec3 diffuse = (1.0 - f) * s.diffuse / PI;
+
+    float coat_alpha = s.clearcoat_roughness * s.clearcoat_roughness;
+    float fc = clearcoat_fresnel(s, v_h);
+    float coat = d_ggx(n_h, coat_alpha) * v_smith(n_v, n_l, coat_alpha) * fc;
+
+    return (diffuse + specular) * (1.0 - fc) + coat;
+}
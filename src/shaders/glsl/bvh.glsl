@@ -0,0 +1,36 @@
+// Traversal of the bounding volume hierarchy in `bvh`, included by generated scenes with many
+// objects after they define `Hit object_sdf(uint object, vec3 p)`.
+
+// Deeper than the balanced hierarchies `bvh::Bvh` builds get for any object count that fits in
+// memory.
+#define BVH_STACK_SIZE 32
+
+// The nearest of `nearest` and the objects in `bvh` at `p`. Nodes whose box is farther than the
+// nearest distance found so far are skipped, and objects farther than BOUND_MARGIN from their box
+// are replaced by the distance to it, as in scenes that check each bounds. Inside an object the
+// nearest distance is negative, boxes around `p` still have to be visited for deeper ones.
+Hit bvh_sdf(vec3 p, Hit nearest) {
+    uint stack[BVH_STACK_SIZE];
+    int top = 0;
+    stack[top++] = uint(bvh.length()) - 1u;
+
+    while(top > 0) {
+        BvhNode node = bvh[stack[--top]];
+        float d = box_distance(p, node.min, node.max);
+        if(d > max(nearest.dist, 0.0)) {
+            continue;
+        }
+        if(node.right == BVH_LEAF) {
+            Hit h = d > BOUND_MARGIN ? Hit(d, 0u, vec3(0), true) : object_sdf(node.left, p);
+            nearest = hit_union(nearest, h);
+            continue;
+        }
+        // The nearer child goes on top, so it is visited first and tightens `nearest` early.
+        float d_left = box_distance(p, bvh[node.left].min, bvh[node.left].max);
+        float d_right = box_distance(p, bvh[node.right].min, bvh[node.right].max);
+        bool left_first = d_left <= d_right;
+        stack[top++] = left_first ? node.right : node.left;
+        stack[top++] = left_first ? node.left : node.right;
+    }
+    return nearest;
+}
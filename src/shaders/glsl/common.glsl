@@ -3,6 +3,10 @@ struct Camera {
     vec3 uu;
     vec3 vv;
     vec3 ww;
+    float fov_y;
+    float aspect;
+    float near;
+    float far;
 };
 
 struct Ray {
@@ -17,18 +21,46 @@ struct Hit {
     bool hit;
 };
 
-struct DirectionalLight {
+#define LIGHT_DIRECTIONAL 0
+#define LIGHT_POINT 1
+#define LIGHT_SPOT 2
+#define LIGHT_AREA 3
+
+struct Light {
+    vec3 position;
+    uint kind;
     vec3 direction;
+    float range;
     vec3 color;
     float intensity;
+    float cos_inner;
+    float cos_outer;
+    float radius;
+    float shadow_softness;
 };
 
+// See `scene::Material`. `absorption` is the Beer-Lambert coefficient of the inside.
 struct Material {
-    float specular;
-    float shininess;
+    vec3 base_color;
+    float metallic;
+    vec3 emissive;
     float roughness;
-    float diffuse;
-    vec3 color;
+    vec3 absorption;
+    float transmission;
+    float clearcoat;
+    float clearcoat_roughness;
+    float ior;
+};
+
+#define BVH_LEAF 0xffffffffu
+
+// A node of the bounding volume hierarchy over the scene objects, see `bvh::Bvh`. Leaves have
+// `right` set to BVH_LEAF and the index of their object in `left`.
+struct BvhNode {
+    vec3 min;
+    uint left;
+    vec3 max;
+    uint right;
 };
 
 float sphere_sdf(vec3 p, float r) {
@@ -41,15 +73,15 @@ float box_sdf(vec3 p, vec3 dimension, float corner_radius) {
 }
 
 float cylinder_sdf(vec3 p, float radius, float height, float corner_radius) {
-    vec2 d = vec2(p.xz.length(), abs(p.y)) - vec2(radius, height * 0.5) + corner_radius;
-    return (max(d, vec2(0))).length() + min(max(d.x, d.y), 0.0) - corner_radius;
+    vec2 d = vec2(length(p.xz), abs(p.y)) - vec2(radius, height * 0.5) + corner_radius;
+    return length(max(d, vec2(0))) + min(max(d.x, d.y), 0.0) - corner_radius;
 }
 
 float line_sdf(vec3 p, vec3 a, vec3 b, float r) {
     vec3 pa = p - a;
     vec3 ba = b - a;
     float h = min(1.0, max(0.0, dot(pa, ba) / dot(ba, ba)));
-    return (pa - h * ba).length() - r;
+    return length(pa - h * ba) - r;
 }
 
 float smooth_min(float d1, float d2, float k) {
@@ -66,4 +98,158 @@ vec3 repeat_xz(vec3 p, float s, float lima, float limb) {
     return vec3(p.x - s * clamp(round(p.x / s), lima, limb), p.y, p.z - s * clamp(round(p.z / s), lima, limb));
 }
 
-Material[2] materials;
\ No newline at end of file
+vec3 repeat_xz_cell(vec3 p, float s, float lima, float limb) {
+    return vec3(clamp(round(p.x / s), lima, limb), 0., clamp(round(p.z / s), lima, limb));
+}
+
+// Rotation by XYZ euler angles in radians, the same matrix as glam's `EulerRot::XYZ`.
+mat3 rotation_xyz(vec3 a) {
+    vec3 c = cos(a);
+    vec3 s = sin(a);
+    mat3 rx = mat3(1., 0., 0., 0., c.x, s.x, 0., -s.x, c.x);
+    mat3 ry = mat3(c.y, 0., -s.y, 0., 1., 0., s.y, 0., c.y);
+    mat3 rz = mat3(c.z, s.z, 0., -s.z, c.z, 0., 0., 0., 1.);
+    return rx * ry * rz;
+}
+
+float plane_sdf(vec3 p, vec3 n, float offset) {
+    return dot(p, n) - offset;
+}
+
+// Distance from `p` to the box from `lo` to `hi`, 0 inside.
+float box_distance(vec3 p, vec3 lo, vec3 hi) {
+    return length(max(max(lo - p, p - hi), vec3(0)));
+}
+
+// Objects farther than this from their bounding box are not evaluated, the distance to the box
+// stands in for theirs. Larger than the reach of `occlusion()`, so that sees exact distances.
+#define BOUND_MARGIN 1.0
+
+vec3 cell_color(vec3 cell) {
+    return abs(cell) + 0.3;
+}
+
+float waves(vec3 p) {
+    return 0.2 * (-1. + 2. * smoothstep(-0.2, 0.2, 28.0 * sin(p.x * 4.) + 28.0 * sin(p.y * 4.) + 28.0 * sin(p.z * 4.)));
+}
+
+Hit hit_union(Hit a, Hit b) {
+    return a.dist < b.dist ? a : b;
+}
+
+Hit hit_smooth_union(Hit a, Hit b, float k) {
+    float h = clamp(0.5 + 0.5 * (b.dist - a.dist) / k, 0., 1.);
+    float d = mix(b.dist, a.dist, h) - k * h * (1. - h);
+    return Hit(d, h > 0.5 ? a.material_index : b.material_index, mix(b.color, a.color, h), true);
+}
+
+Hit hit_intersection(Hit a, Hit b) {
+    return a.dist > b.dist ? a : b;
+}
+
+Hit hit_smooth_intersection(Hit a, Hit b, float k) {
+    float h = clamp(0.5 - 0.5 * (b.dist - a.dist) / k, 0., 1.);
+    float d = mix(b.dist, a.dist, h) + k * h * (1. - h);
+    return Hit(d, h > 0.5 ? a.material_index : b.material_index, mix(b.color, a.color, h), true);
+}
+
+Hit hit_subtraction(Hit a, Hit b) {
+    return Hit(max(a.dist, -b.dist), a.material_index, a.color, true);
+}
+
+Hit hit_smooth_subtraction(Hit a, Hit b, float k) {
+    float h = clamp(0.5 - 0.5 * (a.dist + b.dist) / k, 0., 1.);
+    return Hit(mix(a.dist, -b.dist, h) + k * h * (1. - h), a.material_index, a.color, true);
+}
+
+#define MAX_PARAMS 16
+
+#define MODE_FAST 0
+#define MODE_PATH_TRACING 1
+
+layout(set = 0, binding = 0) uniform Globals {
+    vec2 screen;
+    vec3 cam_position;
+    vec3 cam_uu;
+    vec3 cam_vv;
+    vec3 cam_ww;
+    float cam_fov_y;
+    float cam_aspect;
+    float cam_near;
+    float cam_far;
+    uint light_count;
+    // Seconds since the start, seconds since the previous frame and frames rendered before.
+    float time;
+    float delta_time;
+    uint frame;
+    // Set while frames are averaged in `accumulation`, which then already holds the sum of
+    // `sample_index` samples. `jitter` offsets this sample within the pixel.
+    uint accumulate;
+    uint sample_index;
+    vec2 jitter;
+    // One of the MODE_ values, the other two only apply to MODE_PATH_TRACING.
+    uint mode;
+    uint max_bounces;
+    uint samples_per_pixel;
+    // Values of the scene `params`, in the order they are declared.
+    vec4 params[MAX_PARAMS];
+} globals;
+
+layout(set = 0, binding = 1) readonly buffer Materials {
+    Material materials[];
+};
+
+layout(set = 0, binding = 2) readonly buffer Lights {
+    Light lights[];
+};
+
+layout(set = 0, binding = 3, rgba32f) uniform image2D accumulation;
+
+// Bounding boxes of the scene objects, read by generated scenes. The first entries are the
+// leaves, one per object in order, and the last one is the root.
+layout(set = 0, binding = 4) readonly buffer Bvh {
+    BvhNode bvh[];
+};
+
+bool accumulating() {
+    return globals.accumulate != 0u;
+}
+
+// True while frames are accumulated or path traced. Only then are soft shadows, occlusion and area
+// lights randomized, a single fast frame is deterministic and matches the CPU reference.
+bool randomized() {
+    return accumulating() || globals.mode == MODE_PATH_TRACING;
+}
+
+uint rng_state;
+
+uint pcg_hash(uint v) {
+    uint state = v * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+void seed_random(uvec2 pixel, uint frame) {
+    rng_state = pcg_hash(pixel.x + pcg_hash(pixel.y + pcg_hash(frame)));
+}
+
+// Uniform in [0, 1).
+float random() {
+    rng_state = pcg_hash(rng_state);
+    return float(rng_state) / 4294967296.0;
+}
+
+// Two unit vectors perpendicular to `normal` and each other.
+void orthonormal_basis(vec3 normal, out vec3 tangent, out vec3 bitangent) {
+    tangent = normalize(cross(normal, abs(normal.y) < 0.99 ? vec3(0, 1, 0) : vec3(1, 0, 0)));
+    bitangent = cross(normal, tangent);
+}
+
+// Uniform on the disk of `radius` around `center` facing `normal`.
+vec3 random_on_disk(vec3 center, vec3 normal, float radius) {
+    vec3 tangent, bitangent;
+    orthonormal_basis(normal, tangent, bitangent);
+    float r = radius * sqrt(random());
+    float a = 6.2831853 * random();
+    return center + r * (cos(a) * tangent + sin(a) * bitangent);
+}
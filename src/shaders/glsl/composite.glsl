@@ -0,0 +1,134 @@
+#version 450
+
+#include <tonemap.glsl>
+
+// Maps the linear HDR image of `fs.glsl` to the output, with the screen-space effects enabled in
+// `params.effects`. FXAA, if enabled, runs after it in `fxaa.glsl`.
+
+#define EFFECT_BLOOM 1u
+#define EFFECT_CHROMATIC_ABERRATION 2u
+#define EFFECT_COLOR_GRADE 4u
+#define EFFECT_VIGNETTE 8u
+#define EFFECT_GRAIN 16u
+
+#define UPSCALE_BILINEAR 0u
+#define UPSCALE_EDGE_AWARE 1u
+
+// How strongly the edge-aware upscale leaves out texels that differ from the nearest one.
+const float EDGE_SHARPNESS = 12.0;
+
+// The scene fills `params.scene_size` pixels of it, less than the output with a render scale.
+layout(set = 0, binding = 0) uniform sampler2D hdr;
+// Quarter resolution output of `bloom.glsl`.
+layout(set = 0, binding = 1) uniform sampler2D bloom;
+// Maps sRGB encoded colors to graded ones, loaded from a .cube file.
+layout(set = 0, binding = 2) uniform sampler3D lut;
+
+layout(push_constant) uniform Params {
+    vec2 output_size;
+    vec2 scene_size;
+    uint upscale;
+    // Linear multiplier, 2 to the power of the exposure in stops.
+    float exposure;
+    uint tonemap;
+    // Set for targets that store the values as they are, instead of an sRGB format.
+    uint encode_srgb;
+    uint effects;
+    float bloom_intensity;
+    float chromatic_aberration;
+    float vignette;
+    float grain;
+    // Seeds the grain.
+    uint frame;
+} params;
+
+layout(location = 0) out vec4 f_color;
+
+bool enabled(uint effect) {
+    return (params.effects & effect) != 0u;
+}
+
+// Uniform in [0, 1), different for every pixel and frame.
+float grain_noise(uvec2 pixel, uint frame) {
+    uint h = pixel.x * 1973u + pixel.y * 9277u + frame * 26699u;
+    h = (h ^ 61u) ^ (h >> 16);
+    h *= 9u;
+    h ^= h >> 4;
+    h *= 0x27d4eb2du;
+    h ^= h >> 15;
+    return float(h) / 4294967296.0;
+}
+
+float compressed_luma(vec3 c) {
+    float l = dot(c, vec3(0.2126, 0.7152, 0.0722));
+    return l / (1.0 + l);
+}
+
+// The scene at `uv` of the output, scaled up from the rendered pixels.
+vec3 scene(vec2 uv) {
+    // Half a texel inside the rendered pixels, so nothing beyond them is blended in.
+    vec2 p = clamp(uv * params.scene_size, vec2(0.5), params.scene_size - 0.5);
+    if(params.upscale != UPSCALE_EDGE_AWARE || params.scene_size == params.output_size) {
+        return texture(hdr, p / vec2(textureSize(hdr, 0))).rgb;
+    }
+
+    // Bilinear weights, reduced for texels across an edge from the nearest one, so edges stay
+    // sharp instead of being blurred over the scaled up pixels.
+    vec2 base = floor(p - 0.5);
+    vec2 f = p - 0.5 - base;
+    ivec2 last = ivec2(params.scene_size) - 1;
+    ivec2 i = ivec2(base);
+    vec3 c[4] = vec3[](
+        texelFetch(hdr, min(i, last), 0).rgb,
+        texelFetch(hdr, min(i + ivec2(1, 0), last), 0).rgb,
+        texelFetch(hdr, min(i + ivec2(0, 1), last), 0).rgb,
+        texelFetch(hdr, min(i + ivec2(1, 1), last), 0).rgb);
+    float w[4] = float[]((1.0 - f.x) * (1.0 - f.y), f.x * (1.0 - f.y), (1.0 - f.x) * f.y, f.x * f.y);
+    float nearest = compressed_luma(c[int(f.x >= 0.5) + 2 * int(f.y >= 0.5)]);
+
+    vec3 sum = vec3(0.0);
+    float total = 0.0;
+    for(int k = 0; k < 4; k++) {
+        float weight = w[k] * exp(-abs(compressed_luma(c[k]) - nearest) * EDGE_SHARPNESS);
+        sum += c[k] * weight;
+        total += weight;
+    }
+    return sum / total;
+}
+
+void main() {
+    vec2 uv = gl_FragCoord.xy / params.output_size;
+
+    vec3 col;
+    if(enabled(EFFECT_CHROMATIC_ABERRATION)) {
+        // Red and blue are pulled apart radially, the most in the corners.
+        vec2 offset = (uv - 0.5) * params.chromatic_aberration;
+        col = vec3(scene(uv + offset).r, scene(uv).g, scene(uv - offset).b);
+    } else {
+        col = scene(uv);
+    }
+    if(enabled(EFFECT_BLOOM)) {
+        col += texture(bloom, uv).rgb * params.bloom_intensity;
+    }
+
+    col = tonemap(max(col * params.exposure, 0.0), params.tonemap);
+
+    if(enabled(EFFECT_COLOR_GRADE)) {
+        // Texel centers, so the corners of the cube map to its first and last entries.
+        vec3 size = vec3(textureSize(lut, 0));
+        vec3 coord = (linear_to_srgb(col) * (size - 1.0) + 0.5) / size;
+        col = srgb_to_linear(texture(lut, coord).rgb);
+    }
+    if(enabled(EFFECT_VIGNETTE)) {
+        col *= pow(16.0 * uv.x * uv.y * (1.0 - uv.x) * (1.0 - uv.y), params.vignette);
+    }
+    if(enabled(EFFECT_GRAIN)) {
+        col *= 1.0 + (grain_noise(uvec2(gl_FragCoord.xy), params.frame) - 0.5) * params.grain;
+    }
+
+    col = clamp(col, 0.0, 1.0);
+    if(params.encode_srgb != 0u) {
+        col = linear_to_srgb(col);
+    }
+    f_color = vec4(col, 1.0);
+}
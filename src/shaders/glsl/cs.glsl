@@ -0,0 +1,19 @@
+#version 450
+
+#include <pixel.glsl>
+
+// The compute backend. One invocation renders one pixel, independently of the others, in
+// workgroups of 8x8 pixels.
+
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(set = 1, binding = 0, rgba16f) uniform writeonly image2D target;
+
+void main() {
+    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+    // The tiles along the right and bottom edges reach past the image.
+    if(any(greaterThanEqual(pixel, ivec2(globals.screen)))) {
+        return;
+    }
+    imageStore(target, pixel, vec4(render_pixel(vec2(pixel) + 0.5), 1.0));
+}
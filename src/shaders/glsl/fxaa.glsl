@@ -0,0 +1,57 @@
+#version 450
+
+// A simplified single-pass FXAA, in the style of the early FXAA 2 shaders rather than the full
+// 3.11 edge search: blends each pixel along the edge direction estimated from the luma of its
+// four diagonal neighbours, and falls back to the narrower blend where the wider one would
+// leave the local luma range. Reads the composited image in linear color.
+
+#define FXAA_REDUCE_MIN (1.0 / 128.0)
+#define FXAA_REDUCE_MUL (1.0 / 8.0)
+#define FXAA_SPAN_MAX 8.0
+
+layout(set = 0, binding = 0) uniform sampler2D image;
+
+layout(push_constant) uniform Params {
+    // Set for targets that store the values as they are, instead of an sRGB format.
+    uint encode_srgb;
+} params;
+
+layout(location = 0) out vec4 f_color;
+
+// Roughly perceptual, edges are found as they are seen.
+float luma(vec3 c) {
+    return sqrt(dot(c, vec3(0.299, 0.587, 0.114)));
+}
+
+vec3 linear_to_srgb(vec3 c) {
+    return mix(c * 12.92, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, greaterThan(c, vec3(0.0031308)));
+}
+
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(image, 0));
+    vec2 uv = gl_FragCoord.xy * texel;
+
+    vec3 rgb_m = texture(image, uv).rgb;
+    float luma_nw = luma(texture(image, uv + vec2(-1.0, -1.0) * texel).rgb);
+    float luma_ne = luma(texture(image, uv + vec2(1.0, -1.0) * texel).rgb);
+    float luma_sw = luma(texture(image, uv + vec2(-1.0, 1.0) * texel).rgb);
+    float luma_se = luma(texture(image, uv + vec2(1.0, 1.0) * texel).rgb);
+    float luma_m = luma(rgb_m);
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    vec2 dir = vec2(-((luma_nw + luma_ne) - (luma_sw + luma_se)), (luma_nw + luma_sw) - (luma_ne + luma_se));
+    float reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * FXAA_REDUCE_MUL, FXAA_REDUCE_MIN);
+    float scale = 1.0 / (min(abs(dir.x), abs(dir.y)) + reduce);
+    dir = clamp(dir * scale, -FXAA_SPAN_MAX, FXAA_SPAN_MAX) * texel;
+
+    vec3 rgb_a = 0.5 * (texture(image, uv + dir * (1.0 / 3.0 - 0.5)).rgb + texture(image, uv + dir * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 rgb_b = rgb_a * 0.5 + 0.25 * (texture(image, uv - dir * 0.5).rgb + texture(image, uv + dir * 0.5).rgb);
+    float luma_b = luma(rgb_b);
+    vec3 col = luma_b < luma_min || luma_b > luma_max ? rgb_a : rgb_b;
+
+    if(params.encode_srgb != 0u) {
+        col = linear_to_srgb(col);
+    }
+    f_color = vec4(col, 1.0);
+}
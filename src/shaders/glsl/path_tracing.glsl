@@ -0,0 +1,168 @@
+#include <ray_marching.glsl>
+
+// Monte Carlo path tracing, used for MODE_PATH_TRACING. Every bounce samples all lights directly
+// and picks the next direction at random from the lobes of `brdf` and the transmission, paths end
+// after `globals.max_bounces` or earlier by Russian roulette. Inside a transmissive object paths
+// march on the negated SDF and only reflect or refract at its surface.
+
+// The sky as a light, as bright as the ambient term of `path_trace` so both modes agree. Only
+// camera rays see the full `background`.
+vec3 sky_light(vec3 direction) {
+    return sky_color(direction) * vec3(0.16, 0.20, 0.28);
+}
+
+// Distributed as the cosine to `n`.
+vec3 random_cosine_direction(vec3 n) {
+    vec3 tangent, bitangent;
+    orthonormal_basis(n, tangent, bitangent);
+    float r = sqrt(random());
+    float a = 2.0 * PI * random();
+    return normalize(r * cos(a) * tangent + r * sin(a) * bitangent + sqrt(max(1.0 - r * r, 0.0)) * n);
+}
+
+// Half vector distributed as the GGX normals around `n`.
+vec3 random_ggx_half_vector(vec3 n, float alpha) {
+    vec3 tangent, bitangent;
+    orthonormal_basis(n, tangent, bitangent);
+    float u = random();
+    float cos_h = sqrt((1.0 - u) / (1.0 + (alpha * alpha - 1.0) * u));
+    float sin_h = sqrt(max(1.0 - cos_h * cos_h, 0.0));
+    float a = 2.0 * PI * random();
+    return normalize(sin_h * cos(a) * tangent + sin_h * sin(a) * bitangent + cos_h * n);
+}
+
+// Density of directions `l` reflected off half vectors sampled by `random_ggx_half_vector`.
+float ggx_pdf(vec3 n, vec3 v, vec3 l, float alpha) {
+    vec3 h = normalize(v + l);
+    return d_ggx(max(dot(n, h), 0.0), alpha) * max(dot(n, h), 0.0) / (4.0 * max(dot(v, h), 1e-4));
+}
+
+float luminance(vec3 c) {
+    return dot(c, vec3(0.2126, 0.7152, 0.0722));
+}
+
+// Linear radiance arriving along `ray`.
+vec3 trace_path(Ray ray, float near, float far) {
+    vec3 radiance = vec3(0);
+    vec3 throughput = vec3(1);
+    // The object the path is inside of, if any.
+    bool inside = false;
+    float ior = 1.0;
+    vec3 absorption = vec3(0);
+
+    for(uint bounce = 0u; bounce < globals.max_bounces; bounce++) {
+        if(inside) {
+            Hit hit = ray_march_inside(ray, MAX_DISTANCE);
+            if(!hit.hit) {
+                break;
+            }
+            throughput *= exp(-absorption * hit.dist);
+
+            vec3 p = ray.origin + ray.direction * hit.dist;
+            vec3 n = normal(p);
+            // Reflected back in with the Fresnel probability, which is 1 on total internal
+            // reflection, so the weights cancel.
+            if(random() < fresnel_dielectric(dot(ray.direction, n), ior)) {
+                ray = Ray(p - n * 0.01, reflect(ray.direction, n));
+            } else {
+                ray = Ray(p + n * 0.01, refract(ray.direction, -n, ior));
+                inside = false;
+            }
+            continue;
+        }
+
+        Hit hit = bounce == 0u ? ray_march(ray, near, far) : ray_march(ray, 0.0, MAX_DISTANCE);
+        if(!hit.hit) {
+            radiance += throughput * (bounce == 0u ? background(ray.direction) : sky_light(ray.direction));
+            break;
+        }
+
+        vec3 p = ray.origin + ray.direction * hit.dist;
+        vec3 n = normal(p);
+        vec3 v = -ray.direction;
+        vec3 origin = p + n * 0.01;
+        Surface s = surface(materials[hit.material_index], hit.color);
+
+        radiance += throughput * s.emissive;
+
+        for(uint i = 0u; i < globals.light_count; i++) {
+            LightSample light = sample_light(lights[i], p);
+            float cos_l = dot(n, light.direction);
+            if(cos_l <= 0.0 || ray_march(Ray(origin, light.direction), 0.0, light.dist).hit) {
+                continue;
+            }
+            // Lights are scaled by PI, so they light a white diffuse surface as much as in the
+            // fast mode.
+            radiance += throughput * brdf(s, n, v, light.direction) * cos_l * light.radiance * PI;
+        }
+
+        // One lobe is sampled, picked in proportion to the light it reflects. Dividing by the
+        // density of all lobes together keeps the estimate unbiased whichever was picked. The
+        // smooth transmission has no density, it is weighted by its probability instead.
+        float n_v = max(dot(n, v), 1e-4);
+        float fc = clearcoat_fresnel(s, n_v);
+        vec3 env = env_brdf(s.f0, s.roughness, n_v);
+        vec3 transmitted = s.transmission * (1.0 - env) * (1.0 - fc);
+        float p_diffuse = luminance(s.diffuse * (1.0 - env)) * (1.0 - fc);
+        float p_specular = luminance(env) * (1.0 - fc);
+        float p_coat = fc;
+        float p_transmit = luminance(transmitted);
+        float total = p_diffuse + p_specular + p_coat + p_transmit;
+        if(total <= 0.0) {
+            break;
+        }
+        p_diffuse /= total;
+        p_specular /= total;
+        p_coat /= total;
+        p_transmit /= total;
+
+        if(random() < p_transmit) {
+            throughput *= transmitted / p_transmit;
+            ray = Ray(p - n * 0.01, refract(ray.direction, n, 1.0 / s.ior));
+            inside = true;
+            ior = s.ior;
+            absorption = s.absorption;
+            continue;
+        }
+
+        float alpha = s.roughness * s.roughness;
+        float coat_alpha = s.clearcoat_roughness * s.clearcoat_roughness;
+        float u = random() * (1.0 - p_transmit);
+        vec3 direction;
+        if(u < p_diffuse) {
+            direction = random_cosine_direction(n);
+        } else if(u < p_diffuse + p_specular) {
+            direction = reflect(ray.direction, random_ggx_half_vector(n, alpha));
+        } else {
+            direction = reflect(ray.direction, random_ggx_half_vector(n, coat_alpha));
+        }
+
+        float n_l = dot(n, direction);
+        if(n_l <= 0.0) {
+            break;
+        }
+        float pdf = p_diffuse * n_l / PI + p_specular * ggx_pdf(n, v, direction, alpha) + p_coat * ggx_pdf(n, v, direction, coat_alpha);
+        throughput *= brdf(s, n, v, direction) * n_l / max(pdf, 1e-6);
+
+        if(bounce >= 2u) {
+            float survival = clamp(max(throughput.r, max(throughput.g, throughput.b)), 0.05, 1.0);
+            if(random() >= survival) {
+                break;
+            }
+            throughput /= survival;
+        }
+        ray = Ray(origin, direction);
+    }
+    return radiance;
+}
+
+// The average of `globals.samples_per_pixel` paths through the pixel at `coord`, in linear
+// color. The first goes through `coord` itself, the others through random points in the pixel.
+vec3 run_path_tracing(vec2 coord, vec2 screen, Camera camera) {
+    vec3 sum = vec3(0);
+    for(uint i = 0u; i < globals.samples_per_pixel; i++) {
+        vec2 offset = i == 0u ? vec2(0) : vec2(random(), random()) - 0.5;
+        sum += trace_path(camera_ray(camera, coord + offset, screen), camera.near, camera.far);
+    }
+    return sum / float(max(globals.samples_per_pixel, 1u));
+}
@@ -0,0 +1,31 @@
+// The color of a single pixel, shared by the fragment (`fs.glsl`) and the compute (`cs.glsl`)
+// backends so both render the same image.
+
+#include <path_tracing.glsl>
+
+// `coord` is the center of the pixel in framebuffer coordinates.
+vec3 render_pixel(vec2 coord) {
+    Camera camera = Camera(globals.cam_position, globals.cam_uu, globals.cam_vv, globals.cam_ww,
+                           globals.cam_fov_y, globals.cam_aspect, globals.cam_near, globals.cam_far);
+    if(!randomized()) {
+        return run(coord, globals.screen, camera);
+    }
+
+    ivec2 pixel = ivec2(coord);
+    seed_random(uvec2(pixel), globals.frame);
+    coord += globals.jitter;
+
+    vec3 col = globals.mode == MODE_PATH_TRACING
+        ? run_path_tracing(coord, globals.screen, camera)
+        : run(coord, globals.screen, camera);
+
+    if(accumulating()) {
+        vec3 sum = col;
+        if(globals.sample_index > 0u) {
+            sum += imageLoad(accumulation, pixel).rgb;
+        }
+        imageStore(accumulation, pixel, vec4(sum, 1.0));
+        col = sum / float(globals.sample_index + 1u);
+    }
+    return col;
+}
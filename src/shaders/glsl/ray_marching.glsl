@@ -1,8 +1,12 @@
 #include <scene.glsl>
+#include <brdf.glsl>
 
-#define MAX_STEPS 300
-#define HIT_PRECISION 0.001
-#define MAX_DISTANCE 100.0
+// Set by the `Quality` preset, these defaults are the high one.
+layout(constant_id = 0) const int MAX_STEPS = 300;
+layout(constant_id = 1) const float HIT_PRECISION = 0.001;
+layout(constant_id = 2) const float MAX_DISTANCE = 100.0;
+layout(constant_id = 3) const int SHADOW_STEPS = 64;
+layout(constant_id = 4) const int OCCLUSION_SAMPLES = 5;
 
 vec3 normal(vec3 p) {
     float k = 0.5773 * 0.0005;
@@ -21,26 +25,34 @@ vec3 normal(vec3 p) {
     return normalize(xyy * sdf(r_xyy, k).dist + yyx * sdf(r_yyx, k).dist + yxy * sdf(r_yxy, k).dist + xxx * sdf(r_xxx, k).dist);
 }
 
+// The samples span the same distance along the normal at every quality, and their weighted
+// average is scaled to the total weight of the 5 samples of the high preset, so fewer samples
+// only make it coarser and not brighter.
 float occlusion(vec3 pos, vec3 nor) {
+    float spacing = 4.0 / float(max(OCCLUSION_SAMPLES - 1, 1));
     float occ = 0.0;
-    float sca = 1.0;
-    for(int i = 0; i < 5; i++) {
-        float hr = 0.02 + 0.025 * (i * i);
+    float total = 0.0;
+    for(int i = 0; i < OCCLUSION_SAMPLES; i++) {
+        float fi = (float(i) + (randomized() ? random() - 0.5 : 0.0)) * spacing;
+        float hr = 0.02 + 0.025 * (fi * fi);
 
         Hit hit = sdf(Ray(pos, nor), hr);
 
+        float sca = pow(0.85, float(i) * spacing);
         occ += -(hit.dist - hr) * sca;
-        sca *= 0.85;
+        total += sca;
     }
-    return 1.0 - clamp(occ, 0.0, 1.0);
+    float high_total = (1.0 - pow(0.85, 5.0)) / 0.15;
+    return 1.0 - clamp(occ / total * high_total, 0.0, 1.0);
 }
 
-float shadow(Ray ray, float k) {
+float shadow(Ray ray, float k, float max_t) {
     float res = 1.0;
 
-    float t = 0.01;
+    // Dithering the start hides the banding of the fixed step pattern once frames average.
+    float t = randomized() ? 0.01 + 0.02 * random() : 0.01;
 
-    for(int i = 0; i < 64; i++) {
+    for(int i = 0; i < SHADOW_STEPS; i++) {
         vec3 pos = ray.origin + ray.direction * t;
         float h = sdf(ray, t).dist;
 
@@ -49,15 +61,18 @@ float shadow(Ray ray, float k) {
             break;
         }
         t += clamp(h, 0.01, 5.0);
+        if(t > max_t) {
+            break;
+        }
     }
 
     return res;
 }
 
-Hit ray_march(Ray ray) {
-    float t = 0.0;
+Hit ray_march(Ray ray, float t_min, float t_max) {
+    float t = t_min;
     for(int i = 0; i < MAX_STEPS; i++) {
-        if(t > MAX_DISTANCE) {
+        if(t > t_max) {
             break;
         }
 
@@ -70,89 +85,196 @@ Hit ray_march(Ray ray) {
     return Hit(t, 0, vec3(0), false);
 }
 
-vec3 path_trace(Ray ray, DirectionalLight d_light, vec3 res, vec3 sky, int bounce) {
-
-    vec3 refl_col = vec3(0);
-    float refl_roughness = -1.0;
-    bool need_mix = false;
-
-    for(int bounce = 0; bounce < 3; bounce++) {
+// Marches from inside an object, where the SDF is negative, to where the ray leaves it.
+Hit ray_march_inside(Ray ray, float t_max) {
+    float t = 0.0;
+    for(int i = 0; i < MAX_STEPS; i++) {
+        if(t > t_max) {
+            break;
+        }
 
-        Hit hit = ray_march(ray);
+        Hit h = sdf(ray, t);
+        t -= h.dist;
+        if(-h.dist < HIT_PRECISION) {
+            return Hit(t, h.material_index, h.color, true);
+        }
+    }
+    return Hit(t, 0, vec3(0), false);
+}
 
-        if (hit.hit) {
-            vec3 p = ray.origin + ray.direction * hit.dist;
-            vec3 n = normal(p);
-            vec3 light_dir = -d_light.direction;
-            float occlusion = occlusion(p, n);
-            float shadow = shadow(Ray(p + n * 0.0001, light_dir), 32);
+// Refracts `ray` into the transmissive surface at `p` and follows it through the object, with up
+// to 4 total internal reflections, to where it leaves. `weight` takes the absorption inside and
+// the light reflected back in at the exit. Returns false if the ray does not get out.
+bool pass_through(inout Ray ray, inout vec3 weight, vec3 p, vec3 n, Surface s) {
+    ray = Ray(p - n * 0.01, refract(ray.direction, n, 1.0 / s.ior));
 
-            vec3 half_angle = normalize(-ray.direction + light_dir);
+    for(int i = 0; i < 5; i++) {
+        Hit hit = ray_march_inside(ray, MAX_DISTANCE);
+        if(!hit.hit) {
+            return false;
+        }
+        weight *= exp(-s.absorption * hit.dist);
+
+        vec3 q = ray.origin + ray.direction * hit.dist;
+        vec3 exit_normal = normal(q);
+        float fresnel = fresnel_dielectric(dot(ray.direction, exit_normal), s.ior);
+        if(fresnel < 1.0) {
+            weight *= 1.0 - fresnel;
+            ray = Ray(q + exit_normal * 0.01, refract(ray.direction, -exit_normal, s.ior));
+            return true;
+        }
+        ray = Ray(q - exit_normal * 0.01, reflect(ray.direction, exit_normal));
+    }
+    return false;
+}
 
-            Material material = materials[hit.material_index];
-            float mat_specular = material.specular;
-            float mat_shininess = material.shininess;
+struct LightSample {
+    vec3 direction;
+    float dist;
+    vec3 radiance;
+};
 
-            vec3 col = hit.color;
+// Windowed inverse square falloff, reaching zero at `range`.
+float falloff(float d, float range) {
+    float w = clamp(1.0 - pow(d / range, 4.0), 0.0, 1.0);
+    return w * w / max(d * d, 0.0001);
+}
 
-            float shininess = pow(max(dot(n, half_angle), 0.), mat_shininess);
+// Direction and distance from `p` to the light, and the unshadowed light reaching `p`.
+LightSample sample_light(Light light, vec3 p) {
+    vec3 radiance = light.color * light.intensity;
+    if(light.kind == LIGHT_DIRECTIONAL) {
+        return LightSample(-light.direction, MAX_DISTANCE, radiance);
+    }
 
-            float sun = clamp(dot(n, light_dir), 0.0, 1.0);
-            float indirect = 0.1 * clamp(dot(n, normalize(light_dir * vec3(-1.0, 0.0, -1.0))), 0.0, 1.0);
+    vec3 position = light.position;
+    if(light.kind == LIGHT_AREA && randomized()) {
+        // A point on the disk the sphere covers as seen from `p`, for true soft shadows.
+        position = random_on_disk(position, normalize(p - position), light.radius);
+    }
+    vec3 to_light = position - p;
+    float d = length(to_light);
+    vec3 l = to_light / d;
 
-            vec3 light = material.diffuse * sun * d_light.color * pow(vec3(shadow), vec3(1.3, 1.2, 1.5));
+    if(light.kind == LIGHT_AREA) {
+        // Outside of it, a uniformly emitting sphere lights like a point at its center.
+        return LightSample(l, max(d - light.radius, 0.0), radiance * falloff(max(d, light.radius), light.range));
+    }
 
-            light += sky * vec3(0.16, 0.20, 0.28) * occlusion;
-            light += indirect * vec3(0.40, 0.28, 0.20) * occlusion;
-            light += mat_specular * shininess * shadow;
+    radiance *= falloff(d, light.range);
+    if(light.kind == LIGHT_SPOT) {
+        radiance *= smoothstep(light.cos_outer, light.cos_inner, dot(-l, light.direction));
+    }
+    return LightSample(l, d, radiance);
+}
 
-            col *= light * d_light.intensity;
+vec3 sky_color(vec3 direction) {
+    vec3 sky = clamp(vec3(0.5, 0.8, 1.) - (0.7 * direction.y), 0.0, 1.0);
 
-            res = clamp(col, 0.0, 1.0);
+    return mix(sky, vec3(0.5, 0.7, 0.9), exp(-10.0 * max(direction.y, 0.0)));
+}
 
-            if(refl_roughness >= 0) {
-                res = mix(res, refl_col, refl_roughness);
-            }
+// The sky with the glow of the directional lights, as seen by the camera.
+vec3 background(vec3 direction) {
+    vec3 res = sky_color(direction);
 
-            if (material.roughness < 1.0) {
-                vec3 refl = normalize(reflect(ray.direction, n));
-                ray = Ray(p + n * 0.01, refl);
-                refl_col = res;
-                refl_roughness = material.roughness;
-            } else {
-                refl_roughness = -1;
-            }
-        } else {
-            if (refl_roughness >= 0) {
-                res = mix(res, refl_col, refl_roughness);
-            }
-            break;
+    for(uint i = 0; i < globals.light_count; i++) {
+        if(lights[i].kind != LIGHT_DIRECTIONAL) {
+            continue;
         }
+        float sundot = clamp(dot(direction, -lights[i].direction), 0.0, 1.0);
+        float intensity = lights[i].intensity;
+
+        res += 0.25 * vec3(1.0, 0.7, 0.4) * pow(sundot, 5.0) * intensity;
+        res += 0.25 * vec3(1.0, 0.6, 0.6) * pow(sundot, 64.0) * intensity;
+        res += 0.25 * vec3(1.0, 0.9, 0.6) * pow(sundot, 512.0) * intensity;
     }
     return res;
 }
 
-vec3 run(vec2 coord, vec2 screen, Camera camera) {
-    vec2 p = (coord - 0.5 * screen) / screen.y;
-    p.y = -p.y;
+// `res` is the background and `sky` the sky color of the primary ray, which is bounded by `near`
+// and `far`. Reflections march up to MAX_DISTANCE.
+vec3 path_trace(Ray ray, vec3 res, vec3 sky, float near, float far) {
+    vec3 col = vec3(0);
+    // Share of the pixel the current ray brings in.
+    vec3 weight = vec3(1);
 
-    Ray ray = Ray(camera.position, normalize(p.x * camera.uu + p.y * camera.vv + 1.5 * camera.ww));
-    DirectionalLight d_light = DirectionalLight(normalize(vec3(-3., -1.5, -2.)), vec3(1., 0.85, 0.70), 1.0);
+    for(int bounce = 0; bounce < 3; bounce++) {
 
-    vec3 sky = clamp(vec3(0.5, 0.8, 1.) - (0.7 * ray.direction.y), 0.0, 1.0);
+        Hit hit = bounce == 0 ? ray_march(ray, near, far) : ray_march(ray, 0.0, MAX_DISTANCE);
 
-    sky = mix(sky, vec3(0.5, 0.7, 0.9), exp(-10.0 * max(ray.direction.y, 0.0)));
+        if (!hit.hit) {
+            col += weight * (bounce == 0 ? res : background(ray.direction));
+            break;
+        }
 
-    vec3 res = sky;
+        vec3 p = ray.origin + ray.direction * hit.dist;
+        vec3 n = normal(p);
+        vec3 v = -ray.direction;
+        float occlusion = occlusion(p, n);
+
+        Surface s = surface(materials[hit.material_index], hit.color);
+
+        float n_v = max(dot(n, v), 1e-4);
+        float fc = clearcoat_fresnel(s, n_v);
+        vec3 env = env_brdf(s.f0, s.roughness, n_v);
+        vec3 specular = env * (1.0 - fc);
+        // The mirror ray only shows the sharp part of the reflections, the blurred rest is
+        // approximated by the sky.
+        float sharpness = 1.0 - s.roughness;
+        float coat_sharpness = 1.0 - s.clearcoat_roughness;
+
+        vec3 ambient = sky * vec3(0.16, 0.20, 0.28) * occlusion;
+        vec3 light = s.emissive + ambient * (s.diffuse * (1.0 - env) * (1.0 - fc) + specular * (1.0 - sharpness) + fc * (1.0 - coat_sharpness));
+
+        for(uint i = 0; i < globals.light_count; i++) {
+            LightSample ls = sample_light(lights[i], p);
+            float visibility = shadow(Ray(p + n * 0.0001, ls.direction), lights[i].shadow_softness, ls.dist);
+            float n_l = clamp(dot(n, ls.direction), 0.0, 1.0);
+
+            // Lights are scaled by PI, so a white diffuse surface facing a light of intensity 1
+            // is lit to 1.
+            light += brdf(s, n, v, ls.direction) * n_l * PI * ls.radiance * pow(vec3(visibility), vec3(1.3, 1.2, 1.5));
+
+            if(lights[i].kind == LIGHT_DIRECTIONAL) {
+                // Light bounced back towards the sun by the surroundings.
+                float indirect = 0.1 * clamp(dot(n, normalize(ls.direction * vec3(-1.0, 0.0, -1.0))), 0.0, 1.0);
+                light += indirect * vec3(0.40, 0.28, 0.20) * occlusion * lights[i].intensity * s.diffuse;
+            }
+        }
 
-    float sundot = clamp(dot(ray.direction, -d_light.direction), 0.0, 1.0);
+        col += weight * light;
 
-    res += 0.25 * vec3(1.0, 0.7, 0.4) * pow(sundot, 5.0);
-    res += 0.25 * vec3(1.0, 0.6, 0.6) * pow(sundot, 64.0);
-    res += 0.25 * vec3(1.0, 0.9, 0.6) * pow(sundot, 512.0);
+        vec3 reflection = specular * sharpness + fc * coat_sharpness;
+        vec3 refl = normalize(reflect(ray.direction, n));
+        if(s.transmission != vec3(0)) {
+            // Only one ray is followed, the refracted one, reflections only show the sky.
+            col += weight * reflection * background(refl);
+            weight *= s.transmission * (1.0 - env) * (1.0 - fc);
+            if(!pass_through(ray, weight, p, n, s)) {
+                break;
+            }
+        } else {
+            weight *= reflection;
+            ray = Ray(p + n * 0.01, refl);
+        }
+        if(max(weight.r, max(weight.g, weight.b)) < 0.01) {
+            break;
+        }
+    }
+    return col;
+}
 
-    res = path_trace(ray, d_light, res, sky, 0);
+Ray camera_ray(Camera camera, vec2 coord, vec2 screen) {
+    vec2 ndc = coord / screen * 2.0 - 1.0;
+    ndc.y = -ndc.y;
+    float tan_half = tan(camera.fov_y * 0.5);
+    return Ray(camera.position, normalize(ndc.x * camera.aspect * tan_half * camera.uu + ndc.y * tan_half * camera.vv + camera.ww));
+}
 
-    res = pow(res, vec3(0.4545));
-    return res;
+// Linear radiance arriving at the pixel at `coord`, unbounded, the tone mapping pass maps it to
+// the display.
+vec3 run(vec2 coord, vec2 screen, Camera camera) {
+    Ray ray = camera_ray(camera, coord, screen);
+    return path_trace(ray, background(ray.direction), sky_color(ray.direction), camera.near, camera.far);
 }
\ No newline at end of file
@@ -23,14 +23,13 @@ Hit sdf(Ray ray, float t) {
 
     //vec3 col = vec3(0, 0, 0);
     int material = 1;
-    vec3 col = materials[material].color;
+    vec3 col = materials[material].base_color;
 
     if (d == d4) {
         material = 0;
-        col = abs(id) + 0.3;
+        col = cell_color(id);
     } else if (d == d1 || d == d5) {
-        float f = 0.2 * (-1. + 2. * smoothstep(-0.2, 0.2, 28.0 * sin(p.x * 4.) + 28.0 * sin(p.y * 4.) + 28.0 * sin(p.z * 4.)));
-        col += 0.4 * f;
+        col += 0.4 * waves(p);
     }
 
     return Hit(d, material, col, true);
@@ -0,0 +1,62 @@
+// Maps linear HDR colors to the displayable range with one of the TONEMAP_* operators, used by
+// `composite.glsl`. `reference/tonemap.rs` mirrors it.
+
+#define TONEMAP_NONE 0
+#define TONEMAP_REINHARD 1
+#define TONEMAP_ACES 2
+#define TONEMAP_AGX 3
+
+vec3 reinhard(vec3 x) {
+    return x / (1.0 + x);
+}
+
+// Narkowicz's fit of the ACES filmic curve.
+vec3 aces(vec3 x) {
+    return clamp(x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+// Fit of the AgX base contrast curve, by Wrensch.
+vec3 agx_contrast(vec3 x) {
+    vec3 x2 = x * x;
+    vec3 x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+vec3 agx(vec3 x) {
+    const mat3 inset = mat3(
+        0.84247906, 0.04232824, 0.04237565,
+        0.0784336, 0.87846864, 0.0784336,
+        0.07922375, 0.07916613, 0.879143);
+    const mat3 outset = mat3(
+        1.196879, -0.05289685, -0.05297164,
+        -0.09802088, 1.1519031, -0.09804345,
+        -0.09902974, -0.09896118, 1.1510737);
+    const float min_ev = -12.47393;
+    const float max_ev = 4.026069;
+
+    x = clamp(log2(max(inset * x, 1e-10)), min_ev, max_ev);
+    x = agx_contrast((x - min_ev) / (max_ev - min_ev));
+    // The curve already encodes with a 2.2 gamma, decoded here as the encoding comes last.
+    return pow(clamp(outset * x, 0.0, 1.0), vec3(2.2));
+}
+
+vec3 tonemap(vec3 x, uint kind) {
+    switch(kind) {
+    case TONEMAP_REINHARD:
+        return reinhard(x);
+    case TONEMAP_ACES:
+        return aces(x);
+    case TONEMAP_AGX:
+        return agx(x);
+    default:
+        return clamp(x, 0.0, 1.0);
+    }
+}
+
+vec3 linear_to_srgb(vec3 c) {
+    return mix(c * 12.92, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, greaterThan(c, vec3(0.0031308)));
+}
+
+vec3 srgb_to_linear(vec3 c) {
+    return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), greaterThan(c, vec3(0.04045)));
+}
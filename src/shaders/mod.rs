@@ -1,2 +1,8 @@
+pub mod bloom;
+pub mod compiler;
+pub mod composite;
+pub mod compute;
+pub mod fragment;
+pub mod fxaa;
 pub mod vertex;
-pub mod fragment;
\ No newline at end of file
+pub mod watcher;
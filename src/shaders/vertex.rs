@@ -1,4 +1,3 @@
-
 vulkano_shaders::shader! {
     ty: "vertex",
     path: "./src/shaders/glsl/vs.glsl"
@@ -0,0 +1,79 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use super::compiler::GLSL_DIR;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Polls the modification times of `fs.glsl` and `cs.glsl`, everything they include and a list
+/// of extra files, such as the scene description the shader is generated from.
+pub struct ShaderWatcher {
+    extra_files: Vec<PathBuf>,
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub fn new(extra_files: Vec<PathBuf>) -> Self {
+        ShaderWatcher {
+            files: snapshot(&extra_files),
+            extra_files,
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Returns true once after any of the watched files changed.
+    pub fn poll(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        // The include graph is walked again since an edit may add or remove includes.
+        let files = snapshot(&self.extra_files);
+        if files == self.files {
+            return false;
+        }
+        self.files = files;
+        true
+    }
+}
+
+fn snapshot(extra_files: &[PathBuf]) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let mut files = vec![];
+    for main in ["fs.glsl", "cs.glsl"] {
+        collect(&Path::new(GLSL_DIR).join(main), &mut files);
+    }
+    for path in extra_files {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        files.push((path.clone(), modified));
+    }
+    files
+}
+
+fn collect(path: &Path, files: &mut Vec<(PathBuf, Option<SystemTime>)>) {
+    if files.iter().any(|(p, _)| p == path) {
+        return;
+    }
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+    files.push((path.to_owned(), modified));
+
+    let Ok(source) = fs::read_to_string(path) else {
+        return;
+    };
+    for line in source.lines() {
+        if let Some(name) = include_name(line) {
+            collect(&Path::new(GLSL_DIR).join(name), files);
+        }
+    }
+}
+
+fn include_name(line: &str) -> Option<&str> {
+    let rest = line.trim().strip_prefix("#include")?.trim();
+    rest.strip_prefix('<')
+        .and_then(|r| r.strip_suffix('>'))
+        .or_else(|| rest.strip_prefix('"').and_then(|r| r.strip_suffix('"')))
+}
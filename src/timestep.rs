@@ -0,0 +1,91 @@
+use std::time::{Duration, Instant};
+
+/// Steps that can be run to catch up at once, beyond this the simulation slows down instead of
+/// spending every frame on updates.
+const MAX_STEPS: u32 = 10;
+
+/// Splits real time into fixed simulation steps, leaving a remainder for rendering to
+/// interpolate over.
+pub struct FixedTimestep {
+    dt: Duration,
+    accumulator: Duration,
+    last: Instant,
+}
+
+impl FixedTimestep {
+    /// `rate` steps per second, a positive and finite number as `--tick-rate` accepts.
+    pub fn new(rate: f32) -> Self {
+        FixedTimestep {
+            dt: Duration::from_secs_f32(1.0 / rate),
+            accumulator: Duration::ZERO,
+            last: Instant::now(),
+        }
+    }
+
+    /// Seconds per step.
+    pub fn dt(&self) -> f32 {
+        self.dt.as_secs_f32()
+    }
+
+    /// Adds the time since the last call and returns how many steps to run.
+    pub fn advance(&mut self) -> u32 {
+        let now = Instant::now();
+        let elapsed = now - self.last;
+        self.last = now;
+        self.step(elapsed)
+    }
+
+    fn step(&mut self, elapsed: Duration) -> u32 {
+        // Dropping the time past `MAX_STEPS` first keeps a long stall from counting steps it
+        // will not run.
+        self.accumulator = (self.accumulator + elapsed).min(self.dt * MAX_STEPS);
+
+        let mut steps = 0;
+        while self.accumulator >= self.dt {
+            self.accumulator -= self.dt;
+            steps += 1;
+        }
+        steps
+    }
+
+    /// How far real time is into the next step, from 0 to 1. Rendering blends the previous
+    /// and the current state by this amount.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.dt.as_secs_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn counts_whole_steps_and_keeps_the_rest() {
+        let mut timestep = FixedTimestep::new(100.0);
+        assert_eq!(timestep.step(MS * 5), 0);
+        assert_eq!(timestep.step(MS * 6), 1);
+        assert!((timestep.alpha() - 0.1).abs() < 1e-3);
+        assert_eq!(timestep.step(MS * 29), 3);
+        assert_eq!(timestep.step(Duration::ZERO), 0);
+    }
+
+    #[test]
+    fn caps_the_steps_after_a_stall() {
+        let mut timestep = FixedTimestep::new(60.0);
+        assert_eq!(timestep.step(Duration::from_secs(3600)), MAX_STEPS);
+        assert_eq!(timestep.alpha(), 0.0);
+        assert_eq!(timestep.step(MS), 0);
+    }
+
+    #[test]
+    fn alpha_stays_within_a_step() {
+        let mut timestep = FixedTimestep::new(60.0);
+        for i in 0..200 {
+            timestep.step(MS * (i % 37));
+            let alpha = timestep.alpha();
+            assert!((0.0..1.0).contains(&alpha), "{alpha}");
+        }
+    }
+}
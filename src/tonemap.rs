@@ -0,0 +1,56 @@
+use std::fmt;
+use vulkano::format::{Format, NumericFormat};
+
+/// How the linear HDR colors of the scene are mapped to the displayable range, passed as the
+/// `TONEMAP_*` values in `tonemap.glsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tonemap {
+    /// Clips everything above 1.
+    None = 0,
+    Reinhard = 1,
+    Aces = 2,
+    AgX = 3,
+}
+
+impl Tonemap {
+    pub fn next(self) -> Self {
+        match self {
+            Tonemap::None => Tonemap::Reinhard,
+            Tonemap::Reinhard => Tonemap::Aces,
+            Tonemap::Aces => Tonemap::AgX,
+            Tonemap::AgX => Tonemap::None,
+        }
+    }
+}
+
+impl fmt::Display for Tonemap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tonemap::None => write!(f, "none"),
+            Tonemap::Reinhard => write!(f, "reinhard"),
+            Tonemap::Aces => write!(f, "aces"),
+            Tonemap::AgX => write!(f, "agx"),
+        }
+    }
+}
+
+/// Whether `format` encodes to sRGB on write, so the shader must not.
+pub fn is_srgb(format: Format) -> bool {
+    format.numeric_format_color() == Some(NumericFormat::SRGB)
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}